@@ -1,6 +1,8 @@
 use thiserror::*;
 use tokio::io;
 
+use crate::{protocol::MessageError, zone::ZoneParseError};
+
 #[derive(Debug, Error)]
 pub enum NauticDnsError {
     #[error("Connection to the server has failed")]
@@ -14,4 +16,34 @@ pub enum NauticDnsError {
 
     #[error("Target is not a valid hostname: {0}")]
     InvalidTarget(String),
+
+    #[error("Invalid upstream specification {0}")]
+    InvalidUpstream(String),
+
+    #[error("Failed to parse zone: {0}")]
+    ZoneParse(#[from] ZoneParseError),
+
+    #[error("Invalid zone {0}")]
+    InvalidZone(String),
+
+    #[error("Received a malformed DNS message: {0}")]
+    MalformedMessage(#[from] MessageError),
+
+    #[error("No name servers are configured")]
+    NoNameServers,
+
+    #[error("Timed out waiting for a response from the name servers")]
+    Timeout,
+
+    #[error("Exceeded the recursion limits while resolving {0}")]
+    RecursionLimitExceeded(String),
+
+    #[error("The alias chain for {0} loops back on itself")]
+    AliasLoop(String),
+
+    #[error("The shared query this lookup was waiting on failed: {0}")]
+    InFlightFailure(String),
+
+    #[error("None of the name servers for {0} gave a usable answer")]
+    NoUsableNameServers(String),
 }
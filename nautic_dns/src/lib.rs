@@ -1,5 +1,9 @@
 pub mod errors;
+pub mod policy;
 pub mod resolver;
+pub mod server;
+pub mod transport;
 pub mod util;
+pub mod zone;
 
 pub mod protocol;
@@ -0,0 +1,105 @@
+use std::net::IpAddr;
+
+use super::{Policy, PolicyAction};
+use crate::protocol::LabelSequence;
+
+/// Names hosts files map to themselves, which are never worth blocking.
+const HOSTS_BUILTINS: [&str; 6] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+];
+
+impl Policy {
+    /// Builds a policy applying `action` to every name in `text` and the names below
+    /// them. Each line holds either one domain, one `||domain^` adblock rule, or an
+    /// address followed by domains as in a hosts file, where the address is ignored.
+    /// Comments start with `#` or `!`, and lines that cannot be read are skipped.
+    pub fn from_blocklist(name: impl Into<String>, text: &str, action: PolicyAction) -> Self {
+        let mut policy = Policy::new(name);
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() || line.starts_with('!') {
+                continue;
+            }
+
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let domains = match fields.as_slice() {
+                [address, domains @ ..] if address.parse::<IpAddr>().is_ok() => domains,
+                [_] => &fields[..],
+                _ => {
+                    log::debug!("Skipping blocklist line {line} of {}", policy.name());
+                    continue;
+                }
+            };
+
+            for domain in domains {
+                let domain = domain.trim_start_matches("||").trim_end_matches('^');
+                if !is_domain(domain) || HOSTS_BUILTINS.contains(&domain) {
+                    log::debug!("Skipping blocklist entry {domain} of {}", policy.name());
+                    continue;
+                }
+
+                let domain = LabelSequence::new(domain);
+                policy.add_name(&domain, action.clone());
+                policy.add_subdomains(&domain, action.clone());
+            }
+        }
+
+        policy
+    }
+}
+
+/// Whether `text` looks like a domain rather than an address or a rule with
+/// options, which blocklists mix in freely.
+fn is_domain(text: &str) -> bool {
+    let text = text.trim_end_matches('.');
+
+    !text.is_empty()
+        && text.parse::<IpAddr>().is_err()
+        && text
+            .split('.')
+            .all(|label| !label.is_empty() && label.len() <= 63)
+        && text
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || "-_.".contains(character))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocklist_formats_are_mixed() {
+        let list = "
+# Pi-hole style list
+ads.example.com
+0.0.0.0 tracker.example.net telemetry.example.net # inline comment
+127.0.0.1 localhost
+::1 ip6-localhost
+||banner.example.org^
+! adblock comment
+not a domain!
+";
+        let policy = Policy::from_blocklist("ads", list, PolicyAction::NxDomain);
+        let blocked = |name: &str| policy.match_name(&LabelSequence::new(name)).is_some();
+
+        for name in [
+            "ads.example.com",
+            "img.ads.example.com",
+            "tracker.example.net",
+            "telemetry.example.net",
+            "banner.example.org",
+        ] {
+            assert!(blocked(name), "{name}");
+        }
+
+        for name in ["example.com", "localhost", "ip6-localhost", "not", "a"] {
+            assert!(!blocked(name), "{name}");
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+
+use super::{Policy, PolicyAction};
+use crate::{
+    protocol::{Message, Query, Record, RecordData, RecordType, ResponseCode},
+    server::{Request, RequestHandler},
+};
+
+/// Applies response policies in front of another [`RequestHandler`], such as a
+/// forwarder. Policies are checked in order and the first one with a matching
+/// trigger decides; within a policy, a trigger on the queried name wins over one on
+/// the addresses in the response.
+pub struct PolicyFilter<H> {
+    inner: H,
+    policies: Vec<Policy>,
+}
+
+impl<H: RequestHandler> PolicyFilter<H> {
+    pub fn new(inner: H, policies: Vec<Policy>) -> Self {
+        Self { inner, policies }
+    }
+
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+    pub fn policies(&self) -> &[Policy] {
+        &self.policies
+    }
+
+    /// Builds the response `action` calls for. `response` is the answer of the inner
+    /// handler, when it was needed already.
+    async fn apply(
+        &self,
+        action: &PolicyAction,
+        request: &Request,
+        response: Option<Message>,
+    ) -> Option<Message> {
+        let question = request.message().question()?;
+
+        let records = match action {
+            PolicyAction::NxDomain => return request.reply(ResponseCode::NoDomain).build().ok(),
+            PolicyAction::NoData => return request.reply(ResponseCode::NoError).build().ok(),
+            PolicyAction::Drop => return None,
+            PolicyAction::Passthru => {
+                return match response {
+                    Some(response) => Some(response),
+                    None => self.inner.handle(request).await,
+                }
+            }
+            PolicyAction::LocalData(records) => records,
+        };
+
+        let local = |record: &Record| {
+            Record::new(
+                question.name().clone(),
+                *question.class(),
+                record.ttl(),
+                record.data().clone(),
+            )
+        };
+
+        let answers = records
+            .iter()
+            .filter(|record| record.r#type() == question.r#type())
+            .map(local)
+            .collect::<Vec<_>>();
+
+        let alias = records
+            .iter()
+            .find(|record| record.r#type() == &RecordType::CNAME);
+
+        match (answers.is_empty(), alias) {
+            (true, Some(alias)) => {
+                let RecordData::CNAME(target) = alias.data() else {
+                    unreachable!("Aliases are CNAME records");
+                };
+
+                // The alias target is answered as usual, as if the CNAME had come
+                // from upstream
+                let header = request.message().header();
+                let query = Query::new(target.clone(), *question.r#type(), *question.class());
+                let message =
+                    Message::query(query, header.flags().recursion_desired()).with_id(header.id());
+                let target = Request::new(message, request.source(), request.protocol())
+                    .with_recursion_allowed(request.recursion_allowed());
+
+                let mut answers = vec![local(alias)];
+                let response_code = match self.inner.handle(&target).await {
+                    Some(response) => {
+                        answers.extend_from_slice(response.answers());
+                        response.response_code().clone()
+                    }
+                    None => ResponseCode::ServerFailure,
+                };
+
+                request.reply(response_code).answers(answers).build().ok()
+            }
+            _ => request
+                .reply(ResponseCode::NoError)
+                .answers(answers)
+                .build()
+                .ok(),
+        }
+    }
+}
+
+#[async_trait]
+impl<H: RequestHandler> RequestHandler for PolicyFilter<H> {
+    async fn handle(&self, request: &Request) -> Option<Message> {
+        let question = request.message().question()?;
+        let mut response: Option<Message> = None;
+
+        for policy in &self.policies {
+            if let Some(action) = policy.match_name(question.name()) {
+                log_hit(policy, action, request, &question.name().to_string());
+                return self.apply(action, request, response).await;
+            }
+
+            // Only policies with address triggers need the answer before deciding
+            if policy.addresses.is_empty() {
+                continue;
+            }
+
+            let answer = match &response {
+                Some(response) => response,
+                None => response.insert(self.inner.handle(request).await?),
+            };
+
+            let hit = addresses(answer)
+                .find_map(|address| Some((address, policy.match_address(address)?)));
+
+            if let Some((address, action)) = hit {
+                log_hit(policy, action, request, &address.to_string());
+                return self.apply(action, request, response).await;
+            }
+        }
+
+        match response {
+            Some(response) => Some(response),
+            None => self.inner.handle(request).await,
+        }
+    }
+}
+
+fn addresses(response: &Message) -> impl Iterator<Item = IpAddr> + '_ {
+    response
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data() {
+            RecordData::A(address) => Some(IpAddr::V4(*address)),
+            RecordData::AAAA(address) => Some(IpAddr::V6(*address)),
+            _ => None,
+        })
+}
+
+fn log_hit(policy: &Policy, action: &PolicyAction, request: &Request, trigger: &str) {
+    let Some(question) = request.message().question() else {
+        return;
+    };
+
+    log::info!(
+        "Policy {} matched {trigger} for {} {} from {}: {action}",
+        policy.name(),
+        question.name(),
+        question.r#type().mnemonic(),
+        request.source()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        sync::Mutex,
+    };
+
+    use super::*;
+    use crate::{
+        protocol::{Class, LabelSequence},
+        transport::Protocol,
+        zone::parse_zone,
+    };
+
+    /// Answers every A query with 198.51.100.1, except `bad.example` which gets
+    /// 192.0.2.66, and remembers the names it was asked for.
+    #[derive(Default)]
+    struct Upstream(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl RequestHandler for Upstream {
+        async fn handle(&self, request: &Request) -> Option<Message> {
+            let name = request.message().question()?.name().clone();
+            self.0.lock().unwrap().push(name.label().to_owned());
+
+            let address = match name.label() {
+                "bad.example" => Ipv4Addr::new(192, 0, 2, 66),
+                _ => Ipv4Addr::new(198, 51, 100, 1),
+            };
+            let answer = Record::new(name, Class::IN, 60, RecordData::A(address));
+
+            request
+                .reply(ResponseCode::NoError)
+                .answers(vec![answer])
+                .build()
+                .ok()
+        }
+    }
+
+    const RPZ: &str = "
+$TTL 300
+blocked.example         CNAME   .
+*.blocked.example       CNAME   .
+empty.example           CNAME   *.
+dropped.example         CNAME   rpz-drop.
+portal.example          A       192.0.2.10
+search.example          CNAME   safe.example.
+24.0.2.0.192.rpz-ip     CNAME   .
+";
+
+    fn filter(policies: Vec<Policy>) -> PolicyFilter<Upstream> {
+        PolicyFilter::new(Upstream::default(), policies)
+    }
+
+    fn rpz() -> Policy {
+        let origin = LabelSequence::new("rpz.local");
+        Policy::from_rpz(&origin, parse_zone(RPZ, &origin).unwrap()).unwrap()
+    }
+
+    fn request(name: &str, r#type: RecordType) -> Request {
+        let query = Query::new(LabelSequence::new(name), r#type, Class::IN);
+        let source = SocketAddr::from(([192, 0, 2, 1], 5353));
+
+        Request::new(Message::query(query, true), source, Protocol::Udp)
+    }
+
+    #[tokio::test]
+    async fn name_triggers_answer_without_upstream() {
+        let filter = filter(vec![rpz()]);
+
+        let response = filter
+            .handle(&request("www.blocked.example", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::NoDomain);
+
+        let response = filter
+            .handle(&request("empty.example", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+
+        assert!(filter
+            .handle(&request("dropped.example", RecordType::A))
+            .await
+            .is_none());
+
+        assert!(filter.inner().0.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn local_data_replaces_answer() {
+        let filter = filter(vec![rpz()]);
+
+        let response = filter
+            .handle(&request("portal.example", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(response.answers()[0].name(), "portal.example");
+        assert_eq!(
+            response.answers()[0].data(),
+            &RecordData::A(Ipv4Addr::new(192, 0, 2, 10))
+        );
+
+        let response = filter
+            .handle(&request("portal.example", RecordType::AAAA))
+            .await
+            .unwrap();
+        assert!(response.answers().is_empty());
+
+        let response = filter
+            .handle(&request("search.example", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(response.answers().len(), 2);
+        assert_eq!(response.answers()[1].name(), "safe.example");
+    }
+
+    #[tokio::test]
+    async fn response_address_triggers_policy() {
+        let filter = filter(vec![rpz()]);
+
+        let response = filter
+            .handle(&request("bad.example", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::NoDomain);
+
+        let response = filter
+            .handle(&request("good.example", RecordType::A))
+            .await
+            .unwrap();
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn earlier_policy_wins() {
+        let allow = Policy::from_blocklist("allow", "www.blocked.example", PolicyAction::Passthru);
+        let filter = filter(vec![allow, rpz()]);
+
+        let response = filter
+            .handle(&request("www.blocked.example", RecordType::A))
+            .await
+            .unwrap();
+
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            filter.inner().0.lock().unwrap().as_slice(),
+            ["www.blocked.example"]
+        );
+    }
+}
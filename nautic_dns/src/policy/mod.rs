@@ -0,0 +1,202 @@
+mod blocklist;
+mod filter;
+mod rpz;
+
+pub use filter::*;
+
+use std::{collections::HashMap, fmt, net::IpAddr};
+
+use crate::protocol::{LabelSequence, Record};
+
+/// What to do with a query or response that triggered a policy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Answer that the name does not exist.
+    NxDomain,
+    /// Answer that the name has no records of the queried type.
+    NoData,
+    /// Answer as usual, skipping any later policies.
+    Passthru,
+    /// Send nothing back, so the client times out.
+    Drop,
+    /// Answer with these records in place of the real ones, under the queried name.
+    LocalData(Vec<Record>),
+}
+
+impl fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyAction::NxDomain => write!(f, "NXDOMAIN"),
+            PolicyAction::NoData => write!(f, "NODATA"),
+            PolicyAction::Passthru => write!(f, "PASSTHRU"),
+            PolicyAction::Drop => write!(f, "DROP"),
+            PolicyAction::LocalData(_) => write!(f, "local data"),
+        }
+    }
+}
+
+/// A range of addresses, such as 192.0.2.0/24.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Returns `None` when `prefix` is longer than the address.
+    pub fn new(address: IpAddr, prefix: u8) -> Option<Self> {
+        let bits = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        (prefix <= bits).then_some(Self { address, prefix })
+    }
+
+    pub fn address(&self) -> IpAddr {
+        self.address
+    }
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let (network, address, bits) = match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                (u32::from(network) as u128, u32::from(address) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                (u128::from(network), u128::from(address), 128)
+            }
+            _ => return false,
+        };
+
+        let shift = bits - self.prefix as u32;
+        shift == bits || network >> shift == address >> shift
+    }
+}
+
+/// A set of triggers and the actions they lead to, loaded from a response policy
+/// zone or a blocklist.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    name: String,
+    /// Actions for exact names, keyed by lowercased name.
+    names: HashMap<LabelSequence, PolicyAction>,
+    /// Actions for every name strictly below these, keyed by lowercased name.
+    subdomains: HashMap<LabelSequence, PolicyAction>,
+    addresses: Vec<(IpNetwork, PolicyAction)>,
+}
+
+impl Policy {
+    /// An empty policy. `name` identifies it in the log of policy hits.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            names: HashMap::new(),
+            subdomains: HashMap::new(),
+            addresses: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.subdomains.is_empty() && self.addresses.is_empty()
+    }
+
+    /// Applies `action` to queries for `name`.
+    pub fn add_name(&mut self, name: &LabelSequence, action: PolicyAction) {
+        self.names.insert(name.to_lowercase(), action);
+    }
+
+    /// Applies `action` to queries for every name below `name`, but not `name`
+    /// itself.
+    pub fn add_subdomains(&mut self, name: &LabelSequence, action: PolicyAction) {
+        self.subdomains.insert(name.to_lowercase(), action);
+    }
+
+    /// Applies `action` to responses with an address in `network`.
+    pub fn add_network(&mut self, network: IpNetwork, action: PolicyAction) {
+        self.addresses.push((network, action));
+    }
+
+    /// The action for queries for `name`. An exact name wins over the subdomains
+    /// of a name, and the subdomains of a closer name over those further up.
+    pub fn match_name(&self, name: &LabelSequence) -> Option<&PolicyAction> {
+        let name = name.to_lowercase();
+        if let Some(action) = self.names.get(&name) {
+            return Some(action);
+        }
+
+        let mut ancestor = name.parent();
+        while let Some(name) = ancestor {
+            if let Some(action) = self.subdomains.get(&name) {
+                return Some(action);
+            }
+
+            ancestor = name.parent();
+        }
+
+        None
+    }
+
+    /// The action for a response holding `address`, from the longest network that
+    /// contains it.
+    pub fn match_address(&self, address: IpAddr) -> Option<&PolicyAction> {
+        self.addresses
+            .iter()
+            .filter(|(network, _)| network.contains(address))
+            .max_by_key(|(network, _)| network.prefix())
+            .map(|(_, action)| action)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn closest_name_wins() {
+        let mut policy = Policy::new("test");
+        policy.add_subdomains(&LabelSequence::new("example.com"), PolicyAction::NxDomain);
+        policy.add_subdomains(
+            &LabelSequence::new("ok.example.com"),
+            PolicyAction::Passthru,
+        );
+        policy.add_name(
+            &LabelSequence::new("exact.ok.example.com"),
+            PolicyAction::Drop,
+        );
+
+        let matched = |name: &str| policy.match_name(&LabelSequence::new(name)).cloned();
+
+        assert_eq!(matched("ads.Example.com"), Some(PolicyAction::NxDomain));
+        assert_eq!(matched("a.b.ok.example.com"), Some(PolicyAction::Passthru));
+        assert_eq!(matched("exact.ok.example.com"), Some(PolicyAction::Drop));
+        assert_eq!(matched("example.com"), None);
+    }
+
+    #[test]
+    fn longest_network_wins() {
+        let mut policy = Policy::new("test");
+        let network =
+            |address: [u8; 4], prefix| IpNetwork::new(IpAddr::from(address), prefix).unwrap();
+        policy.add_network(network([192, 0, 2, 0], 24), PolicyAction::NxDomain);
+        policy.add_network(network([192, 0, 2, 128], 25), PolicyAction::NoData);
+        policy.add_network(network([0, 0, 0, 0], 0), PolicyAction::Passthru);
+
+        let matched = |address: [u8; 4]| policy.match_address(IpAddr::from(address)).cloned();
+
+        assert_eq!(matched([192, 0, 2, 1]), Some(PolicyAction::NxDomain));
+        assert_eq!(matched([192, 0, 2, 200]), Some(PolicyAction::NoData));
+        assert_eq!(matched([198, 51, 100, 1]), Some(PolicyAction::Passthru));
+        assert!(policy
+            .match_address(IpAddr::V6(Ipv4Addr::LOCALHOST.to_ipv6_mapped()))
+            .is_none());
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use super::{IpNetwork, Policy, PolicyAction};
+use crate::{
+    errors::NauticDnsError,
+    protocol::{LabelSequence, Record, RecordData},
+    zone::parse_zone_file,
+};
+
+/// Owner suffix of response IP triggers.
+const IP_TRIGGER: &str = "rpz-ip";
+
+/// Owner suffixes of triggers this server does not act on.
+const UNSUPPORTED_TRIGGERS: [&str; 3] = ["rpz-nsdname", "rpz-nsip", "rpz-client-ip"];
+
+impl Policy {
+    /// Reads the triggers and actions of the response policy zone at `origin` from
+    /// its records (draft-ietf-dnsop-dns-rpz section 3). The records at the apex,
+    /// such as the SOA, carry no policy and are skipped, as are triggers on name
+    /// servers and clients.
+    pub fn from_rpz(origin: &LabelSequence, records: Vec<Record>) -> Result<Self, NauticDnsError> {
+        let invalid = |reason: String| NauticDnsError::InvalidZone(format!("{origin}: {reason}"));
+
+        let mut owners: HashMap<LabelSequence, Vec<Record>> = HashMap::new();
+        for record in records {
+            if !record.name.is_subdomain_of(origin) {
+                return Err(invalid(format!("{} is outside the zone", record.name)));
+            }
+
+            if !record.name.eq_ignore_case(origin) {
+                owners
+                    .entry(record.name.to_lowercase())
+                    .or_default()
+                    .push(record);
+            }
+        }
+
+        let mut policy = Policy::new(origin.label());
+        for (owner, records) in owners {
+            let labels = owner
+                .labels()
+                .take(owner.label_count() - origin.label_count())
+                .collect::<Vec<_>>();
+
+            let Some(action) = action(&records) else {
+                log::warn!("Skipping unsupported policy action at {owner}");
+                continue;
+            };
+
+            match labels.as_slice() {
+                [trigger @ .., IP_TRIGGER] => {
+                    let network = parse_network(trigger)
+                        .ok_or_else(|| invalid(format!("{owner} is not a valid IP trigger")))?;
+                    policy.add_network(network, action);
+                }
+                [.., kind] if UNSUPPORTED_TRIGGERS.contains(kind) => {
+                    log::warn!("Skipping unsupported policy trigger {owner}");
+                }
+                ["*", name @ ..] => {
+                    policy.add_subdomains(&LabelSequence::new(&name.join(".")), action);
+                }
+                name => policy.add_name(&LabelSequence::new(&name.join(".")), action),
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// Reads a response policy zone from a master file, see [`Policy::from_rpz`].
+    pub fn from_rpz_file(
+        path: impl AsRef<Path>,
+        origin: &LabelSequence,
+    ) -> Result<Self, NauticDnsError> {
+        let records = parse_zone_file(path, origin)?;
+
+        Self::from_rpz(origin, records)
+    }
+}
+
+/// The action encoded in the records of one trigger: special CNAME targets stand
+/// for the actions, and anything else is local data. The special targets are whole
+/// names, so `*.example.` or `rpz-passthru.example.` rewrite to that name instead.
+/// Returns `None` for actions that are not supported.
+fn action(records: &[Record]) -> Option<PolicyAction> {
+    let target = records.iter().find_map(|record| match record.data() {
+        RecordData::CNAME(target) => Some(target.to_lowercase()),
+        _ => None,
+    });
+    let labels = target
+        .as_ref()
+        .map(|target| target.labels().collect::<Vec<_>>());
+
+    let action = match labels.as_deref() {
+        Some([]) => PolicyAction::NxDomain,
+        Some(["*"]) => PolicyAction::NoData,
+        Some(["rpz-passthru"]) => PolicyAction::Passthru,
+        Some(["rpz-drop"]) => PolicyAction::Drop,
+        Some([special]) if special.starts_with("rpz-") => return None,
+        _ => PolicyAction::LocalData(records.to_vec()),
+    };
+
+    Some(action)
+}
+
+/// Parses the labels in front of `rpz-ip`: the prefix length followed by the
+/// address in reverse, with `zz` standing for the `::` of IPv6 addresses. Four
+/// labels are an IPv4 address only when they are all octets, as an IPv6 address
+/// such as `zz.1.db8.2001` may have four groups too.
+fn parse_network(labels: &[&str]) -> Option<IpNetwork> {
+    let (prefix, address) = labels.split_first()?;
+    let prefix = prefix.parse::<u8>().ok()?;
+    let address = address.iter().rev().copied().collect::<Vec<_>>();
+    let ipv4 = (address.len() == 4)
+        .then(|| address.join(".").parse::<Ipv4Addr>().ok())
+        .flatten();
+
+    let address = match ipv4 {
+        Some(address) => IpAddr::V4(address),
+        None => {
+            let groups = address
+                .iter()
+                .map(|group| match *group {
+                    "zz" => "",
+                    group => group,
+                })
+                .collect::<Vec<_>>();
+
+            // A `::` at either end leaves a lone colon after joining
+            let mut text = groups.join(":");
+            if text.is_empty() || text.ends_with(':') {
+                text.push(':');
+            }
+            if text.starts_with(':') {
+                text.insert(0, ':');
+            }
+
+            IpAddr::V6(text.parse::<Ipv6Addr>().ok()?)
+        }
+    };
+
+    IpNetwork::new(address, prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::parse_zone;
+
+    const RPZ: &str = "
+$TTL 300
+@                       SOA     localhost. root.localhost. 1 3600 600 86400 60
+                        NS      localhost.
+ads.example.com         CNAME   .
+*.ads.example.com       CNAME   .
+empty.example.com       CNAME   *.
+ok.ads.example.com      CNAME   rpz-passthru.
+silent.example.net      CNAME   rpz-drop.
+portal.example.org      A       192.0.2.10
+                        AAAA    2001:db8::10
+search.example          CNAME   safe.search.example.
+wild.example            CNAME   *.example.
+pass.example            CNAME   rpz-passthru.example.
+tcp.example             CNAME   rpz-tcp-only.
+ns.rpz-nsdname          CNAME   .
+24.0.2.0.198.rpz-ip     CNAME   .
+48.zz.db8.2001.rpz-ip   CNAME   *.
+48.zz.1.db8.2001.rpz-ip CNAME   rpz-passthru.
+";
+
+    fn origin() -> LabelSequence {
+        LabelSequence::new("rpz.local")
+    }
+
+    fn policy() -> Policy {
+        Policy::from_rpz(&origin(), parse_zone(RPZ, &origin()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn rpz_triggers_map_to_actions() {
+        let policy = policy();
+        let matched = |name: &str| policy.match_name(&LabelSequence::new(name)).cloned();
+
+        assert_eq!(matched("ads.example.com"), Some(PolicyAction::NxDomain));
+        assert_eq!(matched("x.ads.example.com"), Some(PolicyAction::NxDomain));
+        assert_eq!(matched("empty.example.com"), Some(PolicyAction::NoData));
+        assert_eq!(matched("ok.ads.example.com"), Some(PolicyAction::Passthru));
+        assert_eq!(matched("silent.example.net"), Some(PolicyAction::Drop));
+        assert_eq!(matched("tcp.example"), None);
+        assert_eq!(matched("ns"), None);
+
+        let Some(PolicyAction::LocalData(records)) = matched("portal.example.org") else {
+            panic!("expected local data");
+        };
+        assert_eq!(records.len(), 2);
+
+        let Some(PolicyAction::LocalData(records)) = matched("search.example") else {
+            panic!("expected local data");
+        };
+        assert_eq!(
+            records[0].data(),
+            &RecordData::CNAME(LabelSequence::new("safe.search.example"))
+        );
+
+        for (name, target) in [
+            ("wild.example", "*.example"),
+            ("pass.example", "rpz-passthru.example"),
+        ] {
+            let Some(PolicyAction::LocalData(records)) = matched(name) else {
+                panic!("expected local data for {name}");
+            };
+            assert_eq!(
+                records[0].data(),
+                &RecordData::CNAME(LabelSequence::new(target))
+            );
+        }
+    }
+
+    #[test]
+    fn rpz_ip_triggers_are_reversed_networks() {
+        let policy = policy();
+        let matched = |address: &str| policy.match_address(address.parse().unwrap()).cloned();
+
+        assert_eq!(matched("198.0.2.77"), Some(PolicyAction::NxDomain));
+        assert_eq!(matched("198.0.3.1"), None);
+        assert_eq!(matched("2001:db8::1"), Some(PolicyAction::NoData));
+        assert_eq!(matched("2001:db9::1"), None);
+        assert_eq!(matched("2001:db8:1::1"), Some(PolicyAction::Passthru));
+        assert_eq!(matched("2001:db8:2::1"), None);
+    }
+
+    #[test]
+    fn malformed_ip_trigger_is_rejected() {
+        let records = parse_zone("$TTL 60\n40.0.2.0.192.rpz-ip CNAME .\n", &origin()).unwrap();
+
+        assert!(matches!(
+            Policy::from_rpz(&origin(), records),
+            Err(NauticDnsError::InvalidZone(_))
+        ));
+    }
+}
@@ -1,26 +1,76 @@
-use super::BitParseError;
+use std::borrow::Cow;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-#[repr(u16)]
+use super::strip_prefix_ignore_case;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Class {
-    IN = 1,
-    Any = 255,
-    CH = 3,
-    HS = 4,
-    NONE = 254,
+    IN,
+    Any,
+    CH,
+    HS,
+    NONE,
+    /// Any other value. OPT records put the sender's UDP payload size here (RFC
+    /// 6891 section 6.1.2).
+    Unknown(u16),
 }
 
-impl TryFrom<u16> for Class {
-    type Error = BitParseError;
+impl From<u16> for Class {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Class::IN,
+            255 => Class::Any,
+            3 => Class::CH,
+            4 => Class::HS,
+            254 => Class::NONE,
+            _ => Class::Unknown(value),
+        }
+    }
+}
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+impl From<Class> for u16 {
+    fn from(value: Class) -> Self {
         match value {
-            1 => Ok(Class::IN),
-            255 => Ok(Class::Any),
-            3 => Ok(Class::CH),
-            4 => Ok(Class::HS),
-            254 => Ok(Class::NONE),
-            _ => Err(BitParseError::BadField("Class".into(), value as u64)),
+            Class::IN => 1,
+            Class::Any => 255,
+            Class::CH => 3,
+            Class::HS => 4,
+            Class::NONE => 254,
+            Class::Unknown(value) => value,
+        }
+    }
+}
+
+impl Class {
+    /// The name of this class in presentation format, e.g. `IN`, or `CLASS32` for
+    /// classes without one here (RFC 3597 section 5).
+    pub fn mnemonic(&self) -> Cow<'static, str> {
+        let mnemonic = match self {
+            Class::IN => "IN",
+            Class::Any => "ANY",
+            Class::CH => "CH",
+            Class::HS => "HS",
+            Class::NONE => "NONE",
+            Class::Unknown(value) => return Cow::Owned(format!("CLASS{value}")),
+        };
+
+        Cow::Borrowed(mnemonic)
+    }
+
+    /// Looks a class up by its presentation name, ignoring case. `CHAOS` and
+    /// `HESIOD` are accepted as the long forms of `CH` and `HS`, and `CLASS<number>`
+    /// for every class.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        if let Some(value) = strip_prefix_ignore_case(mnemonic, "CLASS") {
+            return value.parse::<u16>().ok().map(Self::from);
+        }
+
+        match mnemonic.to_ascii_uppercase().as_str() {
+            "IN" => Some(Class::IN),
+            "ANY" => Some(Class::Any),
+            "CH" | "CHAOS" => Some(Class::CH),
+            "HS" | "HESIOD" => Some(Class::HS),
+            "NONE" => Some(Class::NONE),
+            _ => None,
         }
     }
 }
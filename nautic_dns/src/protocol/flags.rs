@@ -183,6 +183,8 @@ pub enum OpCode {
     Query = 0b0000,
     IQuery = 0b0001,
     Status = 0b0010,
+    Notify = 0b0100,
+    Update = 0b0101,
 }
 
 impl TryFrom<u8> for OpCode {
@@ -193,6 +195,8 @@ impl TryFrom<u8> for OpCode {
             0b0000 => Ok(Self::Query),
             0b0001 => Ok(Self::IQuery),
             0b0010 => Ok(Self::Status),
+            0b0100 => Ok(Self::Notify),
+            0b0101 => Ok(Self::Update),
             _ => Err(BitParseError::BadField("OPCODE".to_owned(), value as u64)),
         }
     }
@@ -205,8 +209,11 @@ pub enum ResponseCode {
     FormatError = 0b0001,
     ServerFailure = 0b0010,
     NoDomain = 0b0011,
-    NotImplemented = 0b1000,
+    NotImplemented = 0b0100,
     Refused = 0b0101,
+    /// The request is not authorized, e.g. its TSIG signature did not verify (RFC
+    /// 8945 section 5.2).
+    NotAuth = 0b1001,
 }
 
 impl TryFrom<u8> for ResponseCode {
@@ -218,8 +225,9 @@ impl TryFrom<u8> for ResponseCode {
             0b0001 => Ok(Self::FormatError),
             0b0010 => Ok(Self::ServerFailure),
             0b0011 => Ok(Self::NoDomain),
-            0b1000 => Ok(Self::NotImplemented),
+            0b0100 => Ok(Self::NotImplemented),
             0b0101 => Ok(Self::Refused),
+            0b1001 => Ok(Self::NotAuth),
             _ => Err(BitParseError::BadField("RCODE".to_owned(), value as u64)),
         }
     }
@@ -325,6 +333,23 @@ mod tests {
         assert!(flags.is_err());
     }
 
+    #[test]
+    fn flags_not_implemented_response_code_success() {
+        let flags = FlagsBuilder::default()
+            .message_type(MessageType::Response)
+            .op(OpCode::Notify)
+            .response(ResponseCode::NotImplemented)
+            .build()
+            .unwrap();
+
+        let bytes: Bytes = flags.into();
+        assert_eq!(bytes, vec![0b1010_0000, 0b0000_0100]);
+
+        let flags = Flags::try_scan(&bytes, 0).expect("Failed to scan flags");
+        assert_eq!(flags.value().op(), &OpCode::Notify);
+        assert_eq!(flags.value().response(), &ResponseCode::NotImplemented);
+    }
+
     #[test]
     fn flags_builder_to_binary_success() {
         let flags = FlagsBuilder::default()
@@ -45,6 +45,10 @@ impl Header {
     pub fn additional_size(&self) -> u16 {
         self.additional_size
     }
+
+    pub fn with_id(self, id: u16) -> Self {
+        Self { id, ..self }
+    }
 }
 
 impl ByteScanner for Header {
@@ -137,9 +141,9 @@ mod tests {
         let bytes = vec![
             0xab,
             0xaa,
-            0x00,
             0b1000_0000,
             0x00,
+            0x00,
             0x01,
             0x00,
             0x00,
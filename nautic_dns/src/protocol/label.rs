@@ -2,30 +2,268 @@ use super::{BitParseError, ByteScanner, ScanResult};
 use crate::protocol::ByteScan;
 use bitter::BitReader;
 use bytes::{BufMut, Bytes, BytesMut};
-use std::rc::Rc;
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct LabelSequence(Rc<str>);
+use std::{
+    cmp::Ordering,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+const IPV4_REVERSE_ZONE: &str = "in-addr.arpa";
+const IPV6_REVERSE_ZONE: &str = "ip6.arpa";
+
+/// Longest label and name on the wire, in bytes (RFC 1035 section 2.3.4).
+const MAX_LABEL_LENGTH: usize = 63;
+const MAX_NAME_LENGTH: usize = 255;
+
+/// A domain name, kept in presentation format (RFC 1035 section 5.1): label bytes
+/// other than printable ASCII are written as `\DDD`, and dots and backslashes
+/// within a label are escaped with a backslash, so every name carries its exact
+/// wire bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LabelSequence(Arc<str>);
 
 impl LabelSequence {
+    /// Reads `value` in presentation format, with or without the trailing dot.
+    /// Escapes are rewritten in the one form this type keeps, so `a\066c` and
+    /// `aBc` are the same name.
     pub fn new(value: &str) -> Self {
-        Self(Rc::from(value))
+        let mut name = String::with_capacity(value.len());
+        for label in split_labels(value.trim()) {
+            let label = unescape(label);
+            if label.is_empty() {
+                continue;
+            }
+
+            if !name.is_empty() {
+                name.push('.');
+            }
+            escape(&label, &mut name);
+        }
+
+        Self(Arc::from(name))
+    }
+
+    pub fn root() -> Self {
+        Self::new("")
     }
 
     pub fn total_bits(&self) -> usize {
-        self.0.clone().len()
+        let bytes = self
+            .label_bytes()
+            .map(|label| label.len() + 1)
+            .sum::<usize>()
+            + 1;
+        bytes * 8
+    }
+
+    /// Whether every label fits in 63 bytes and the whole name in 255 on the wire
+    /// (RFC 1035 section 2.3.4).
+    pub fn is_valid(&self) -> bool {
+        self.label_bytes()
+            .all(|label| label.len() <= MAX_LABEL_LENGTH)
+            && self.total_bits() <= MAX_NAME_LENGTH * 8
     }
 
     pub fn label(&self) -> &str {
         self.0.as_ref()
     }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The labels from the left, each in presentation format.
+    pub fn labels(&self) -> impl DoubleEndedIterator<Item = &str> {
+        split_labels(&self.0).filter(|label| !label.is_empty())
+    }
+
+    /// The labels from the left, each as the bytes sent on the wire.
+    pub fn label_bytes(&self) -> impl DoubleEndedIterator<Item = Vec<u8>> + '_ {
+        self.labels().map(unescape)
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels().count()
+    }
+
+    /// Returns the name with its leftmost label removed, or `None` for the root.
+    pub fn parent(&self) -> Option<LabelSequence> {
+        if self.is_root() {
+            return None;
+        }
+
+        match separators(&self.0).next() {
+            Some(separator) => Some(Self(Arc::from(&self.0[separator + 1..]))),
+            None => Some(LabelSequence::root()),
+        }
+    }
+
+    /// Returns the rightmost `count` labels of this name, e.g. 2 for `www.github.com`
+    /// gives `github.com`.
+    pub fn suffix(&self, count: usize) -> LabelSequence {
+        let labels = self.labels().collect::<Vec<_>>();
+        let start = labels.len().saturating_sub(count);
+
+        LabelSequence::new(&labels[start..].join("."))
+    }
+
+    /// Appends `suffix` to this name, e.g. `www` + `github.com` = `www.github.com`.
+    pub fn join(&self, suffix: &LabelSequence) -> LabelSequence {
+        match (self.is_root(), suffix.is_root()) {
+            (true, _) => suffix.clone(),
+            (_, true) => self.clone(),
+            _ => LabelSequence::new(&format!("{}.{}", self.label(), suffix.label())),
+        }
+    }
+
+    /// Domain names compare case-insensitively (RFC 4343).
+    pub fn eq_ignore_case(&self, other: &LabelSequence) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+
+    pub fn to_lowercase(&self) -> LabelSequence {
+        Self(Arc::from(self.0.to_ascii_lowercase()))
+    }
+
+    /// Flips the case of each letter at random, for 0x20 encoding of outgoing
+    /// queries (draft-vixie-dnsext-dns0x20).
+    pub fn randomise_case(&self) -> LabelSequence {
+        let name = self
+            .0
+            .chars()
+            .map(|character| match rand::random::<bool>() {
+                true => character.to_ascii_uppercase(),
+                false => character.to_ascii_lowercase(),
+            })
+            .collect::<String>();
+
+        Self(Arc::from(name))
+    }
+
+    /// Whether this name is equal to or below `zone`.
+    pub fn is_subdomain_of(&self, zone: &LabelSequence) -> bool {
+        if zone.is_root() {
+            return true;
+        }
+
+        self.label_count() >= zone.label_count()
+            && self
+                .labels()
+                .rev()
+                .zip(zone.labels().rev())
+                .all(|(label, zone)| label.eq_ignore_ascii_case(zone))
+    }
+
+    /// Replaces the `owner` suffix of this name with `target`, as a DNAME does
+    /// (RFC 6672). Returns `None` when this name is not strictly below `owner` or
+    /// the result would be longer than 255 bytes.
+    pub fn substitute(&self, owner: &LabelSequence, target: &LabelSequence) -> Option<Self> {
+        if !self.is_subdomain_of(owner) || self.eq_ignore_case(owner) {
+            return None;
+        }
+
+        let prefix_count = self.label_count() - owner.label_count();
+        let prefix = self
+            .labels()
+            .take(prefix_count)
+            .collect::<Vec<_>>()
+            .join(".");
+        let name = LabelSequence::new(&prefix).join(target);
+
+        (name.total_bits() <= MAX_NAME_LENGTH * 8).then_some(name)
+    }
+
+    /// Returns the name PTR records for `address` are found under, e.g.
+    /// `4.121.82.140.in-addr.arpa` for 140.82.121.4 (RFC 1035 section 3.5), or the
+    /// reversed nibbles under `ip6.arpa` for IPv6 (RFC 3596 section 2.5).
+    pub fn reverse_pointer(address: IpAddr) -> Self {
+        let labels = match address {
+            IpAddr::V4(address) => address
+                .octets()
+                .iter()
+                .rev()
+                .map(|octet| octet.to_string())
+                .chain([IPV4_REVERSE_ZONE.to_owned()])
+                .collect::<Vec<_>>(),
+            IpAddr::V6(address) => address
+                .octets()
+                .iter()
+                .rev()
+                .flat_map(|octet| [octet & 0x0f, octet >> 4])
+                .map(|nibble| format!("{nibble:x}"))
+                .chain([IPV6_REVERSE_ZONE.to_owned()])
+                .collect::<Vec<_>>(),
+        };
+
+        Self::new(&labels.join("."))
+    }
+
+    /// Returns the address this name is the reverse pointer of, or `None` when it is
+    /// not a complete `in-addr.arpa` or `ip6.arpa` name.
+    pub fn reverse_address(&self) -> Option<IpAddr> {
+        let name = self.to_lowercase();
+        let labels = name.labels().collect::<Vec<_>>();
+
+        if let Some(octets) = labels.strip_suffix(&["in-addr", "arpa"]) {
+            let octets = octets
+                .iter()
+                .rev()
+                .map(|octet| {
+                    octet
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|value| value.to_string() == *octet)
+                })
+                .collect::<Option<Vec<_>>>()?;
+
+            let octets: [u8; 4] = octets.try_into().ok()?;
+            return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+        }
+
+        let nibbles = labels.strip_suffix(&["ip6", "arpa"])?;
+        if nibbles.len() != 32 {
+            return None;
+        }
+
+        let mut address = 0u128;
+        for nibble in nibbles.iter().rev() {
+            let mut characters = nibble.chars();
+            let (Some(digit), None) = (characters.next(), characters.next()) else {
+                return None;
+            };
+
+            address = address << 4 | digit.to_digit(16)? as u128;
+        }
+
+        Some(IpAddr::V6(Ipv6Addr::from(address)))
+    }
+
+    /// Orders names canonically (RFC 4034 section 6.1): label by label from the
+    /// right, ignoring case, so a zone sorts with parents before their children.
+    pub fn canonical_cmp(&self, other: &LabelSequence) -> Ordering {
+        let labels = |name: &LabelSequence| {
+            name.label_bytes()
+                .rev()
+                .map(|label| label.to_ascii_lowercase())
+                .collect::<Vec<_>>()
+        };
+
+        labels(self).cmp(&labels(other))
+    }
+}
+
+impl fmt::Display for LabelSequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.", self.label())
+    }
 }
 
 impl ByteScanner for LabelSequence {
     type Error = BitParseError;
 
     fn try_scan(message: &[u8], cursor: usize) -> ScanResult<Self, Self::Error> {
-        let mut reader = bitter::BigEndianReader::new(&message[cursor..]);
+        let mut reader = bitter::BigEndianReader::new(message.get(cursor..).unwrap_or_default());
 
         let mut name = String::new();
         let mut label_byte_size = 0;
@@ -45,7 +283,7 @@ impl ByteScanner for LabelSequence {
             }
 
             // Check if it is a pointer. If so, read it and exit loop
-            if length & 0b11000000 != 0 {
+            if length & 0b11000000 == 0b11000000 {
                 // Obtain offset for label
                 let offset = reader
                     .read_u8()
@@ -53,6 +291,15 @@ impl ByteScanner for LabelSequence {
 
                 let offset = u16::from_be_bytes([length & 0b00111111, offset]) as usize;
 
+                // Pointers may only jump to before the start of this name, so every
+                // jump goes further back and loops are impossible
+                if offset >= cursor {
+                    return Err(BitParseError::BadField(
+                        "Label pointer offset".into(),
+                        offset as u64,
+                    ));
+                }
+
                 // Read label from message with cursor set with pointer offset
                 let label_scan = LabelSequence::try_scan(message, offset)?;
                 name.push_str(label_scan.value().label());
@@ -60,18 +307,32 @@ impl ByteScanner for LabelSequence {
                 break;
             }
 
+            if length as usize > MAX_LABEL_LENGTH {
+                return Err(BitParseError::BadField(
+                    "Label length".into(),
+                    length as u64,
+                ));
+            }
+
+            let mut label = Vec::with_capacity(length as usize);
             for _ in 0..length {
-                let character = reader
+                let byte = reader
                     .read_u8()
-                    .ok_or_else(|| BitParseError::MalformedBits("Label Character".into()))?
-                    as char;
+                    .ok_or_else(|| BitParseError::MalformedBits("Label Character".into()))?;
 
                 label_byte_size += 1;
-                name.push(character);
+                label.push(byte);
             }
+            escape(&label, &mut name);
+        }
+
+        let name = Self(Arc::from(name));
+        let length = name.total_bits() / 8;
+        if length > MAX_NAME_LENGTH {
+            return Err(BitParseError::BadField("Name length".into(), length as u64));
         }
 
-        Ok(ByteScan::new(LabelSequence::new(&name), label_byte_size))
+        Ok(ByteScan::new(name, label_byte_size))
     }
 }
 
@@ -84,16 +345,13 @@ impl From<LabelSequence> for Bytes {
 impl From<&LabelSequence> for Bytes {
     fn from(value: &LabelSequence) -> Self {
         let mut buffer = BytesMut::new();
-        let labels = value.label().trim().split('.').collect::<Vec<&str>>();
-
-        for label in labels {
-            let length = label.len() as u8;
-            buffer.put_u8(length);
 
-            let chars = label.chars().collect::<Vec<char>>();
-            for i in 0..length {
-                buffer.put_u8(chars[i as usize] as u8);
-            }
+        // Names from the wire and zone files are checked on the way in; anything
+        // longer is cut rather than let the length run into the pointer bits
+        for label in value.label_bytes() {
+            let label = &label[..label.len().min(MAX_LABEL_LENGTH)];
+            buffer.put_u8(label.len() as u8);
+            buffer.put_slice(label);
         }
 
         buffer.put_u8(0);
@@ -101,12 +359,87 @@ impl From<&LabelSequence> for Bytes {
     }
 }
 
+/// Positions of the dots separating labels in `text`, skipping escaped ones.
+fn separators(text: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut escaped = false;
+    text.bytes()
+        .enumerate()
+        .filter_map(move |(position, byte)| {
+            let separator = byte == b'.' && !escaped;
+            escaped = byte == b'\\' && !escaped;
+            separator.then_some(position)
+        })
+}
+
+/// Splits `text` at the dots separating labels, leaving escaped dots in their
+/// label. A trailing dot leaves an empty label at the end.
+pub(crate) fn split_labels(text: &str) -> std::vec::IntoIter<&str> {
+    let mut labels = vec![];
+    let mut start = 0;
+    for separator in separators(text) {
+        labels.push(&text[start..separator]);
+        start = separator + 1;
+    }
+    labels.push(&text[start..]);
+
+    labels.into_iter()
+}
+
+/// Decodes the escapes of one label in presentation format into its bytes: `\DDD`
+/// for a decimal byte value and `\X` for a literal X. A backslash not followed by
+/// a valid escape stands for itself.
+pub(crate) fn unescape(label: &str) -> Vec<u8> {
+    let bytes = label.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let digits = bytes
+            .get(position + 1..position + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_digit))
+            .and_then(|digits| std::str::from_utf8(digits).ok()?.parse::<u8>().ok());
+
+        match (bytes[position], digits) {
+            (b'\\', Some(value)) => {
+                decoded.push(value);
+                position += 4;
+            }
+            (b'\\', None) if position + 1 < bytes.len() => {
+                decoded.push(bytes[position + 1]);
+                position += 2;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                position += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+/// Writes the bytes of one label in presentation format: printable ASCII as is,
+/// dots and backslashes escaped with a backslash and anything else as `\DDD`.
+fn escape(label: &[u8], output: &mut String) {
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' => {
+                output.push('\\');
+                output.push(byte as char);
+            }
+            0x21..=0x7e => output.push(byte as char),
+            _ => output.push_str(&format!("\\{byte:03}")),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::LabelSequence;
     use crate::protocol::ByteScanner;
     use bytes::Bytes;
+    use std::net::{IpAddr, Ipv4Addr};
 
     #[test]
     fn sample_domain_label_sequence_to_bytes_and_back_success() {
@@ -131,6 +464,51 @@ mod tests {
         assert_eq!(label_sequence.label(), label_sequence2.label());
     }
 
+    #[test]
+    fn label_bytes_outside_ascii_to_bytes_and_back_success() {
+        let bytes = [
+            0x04, 0x63, 0x61, 0x66, 0xe9, 0x03, 0x61, 0x2e, 0x62, 0x02, 0xc3, 0xa9, 0x00,
+        ];
+
+        let scan = LabelSequence::try_scan(&bytes[..], 0).expect("Failed to parse label sequence");
+        let label_sequence = scan.value();
+
+        assert_eq!(label_sequence.label(), "caf\\233.a\\.b.\\195\\169");
+        assert_eq!(label_sequence.label_count(), 3);
+        assert_eq!(label_sequence.total_bits(), bytes.len() * 8);
+        assert_eq!(Bytes::from(label_sequence).as_ref(), &bytes[..]);
+        assert_eq!(
+            label_sequence,
+            &LabelSequence::new("caf\\233.a\\.b.\\195\\169.")
+        );
+        assert_eq!(label_sequence.parent().unwrap().label(), "a\\.b.\\195\\169");
+    }
+
+    #[test]
+    fn escapes_are_kept_in_one_form() {
+        let name = LabelSequence::new("a\\066c.\\e\\.x.example.");
+
+        assert_eq!(name.label(), "aBc.e\\.x.example");
+        assert_eq!(name.label_count(), 3);
+        assert!(name.is_subdomain_of(&LabelSequence::new("example")));
+        assert!(!name.is_subdomain_of(&LabelSequence::new("x.example")));
+    }
+
+    #[test]
+    fn overlong_names_are_invalid() {
+        let label = "a".repeat(63);
+        let name = LabelSequence::new(&[label.as_str(); 3].join("."));
+        assert!(name.is_valid());
+        assert!(!LabelSequence::new(&"a".repeat(64)).is_valid());
+        assert!(!LabelSequence::new(&[label.as_str(); 4].join(".")).is_valid());
+
+        // Longer than 255 bytes once the pointer is followed
+        let mut bytes = Bytes::from(&name).to_vec();
+        let start = bytes.len();
+        bytes.extend([63].iter().chain(label.as_bytes()).chain(&[0xc0, 0x00]));
+        assert!(LabelSequence::try_scan(&bytes, start).is_err());
+    }
+
     #[test]
     fn total_bits_sample_domain_is_correct_success() {
         let label_sequence = LabelSequence::new("www.github.com");
@@ -174,6 +552,18 @@ mod tests {
         assert_eq!(label_sequence, "www.github.com");
     }
 
+    #[test]
+    fn suffix_keeps_rightmost_labels() {
+        let label_sequence = LabelSequence::new("this.is.a.subdomain.github.com");
+
+        assert_eq!(label_sequence.suffix(2).label(), "github.com");
+        assert_eq!(label_sequence.suffix(0).label(), "");
+        assert_eq!(
+            label_sequence.suffix(9).label(),
+            "this.is.a.subdomain.github.com"
+        );
+    }
+
     #[test]
     fn parse_bytes_sample_malformed_bytes_fails() {
         let bytes = [0x03, 0x77, 0x03, 0x63, 0x6f, 0x6d];
@@ -193,4 +583,102 @@ mod tests {
 
         assert!(label_sequence.is_err());
     }
+
+    #[test]
+    fn substitute_replaces_owner_suffix_success() {
+        let name = LabelSequence::new("www.old.example");
+        let substituted = name
+            .substitute(
+                &LabelSequence::new("old.example"),
+                &LabelSequence::new("new.net"),
+            )
+            .unwrap();
+
+        assert_eq!(substituted.label(), "www.new.net");
+    }
+
+    #[test]
+    fn substitute_owner_itself_fails() {
+        let name = LabelSequence::new("old.example");
+        let substituted = name.substitute(
+            &LabelSequence::new("old.example"),
+            &LabelSequence::new("new.net"),
+        );
+
+        assert!(substituted.is_none());
+    }
+
+    #[test]
+    fn reverse_pointer_ipv4_round_trip_success() {
+        let address = IpAddr::V4(Ipv4Addr::new(140, 82, 121, 4));
+        let pointer = LabelSequence::reverse_pointer(address);
+
+        assert_eq!(pointer.label(), "4.121.82.140.in-addr.arpa");
+        assert_eq!(pointer.reverse_address(), Some(address));
+    }
+
+    #[test]
+    fn reverse_pointer_ipv6_round_trip_success() {
+        let address = IpAddr::V6("2001:db8::567:89ab".parse().unwrap());
+        let pointer = LabelSequence::reverse_pointer(address);
+
+        assert_eq!(
+            pointer.label(),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+
+        let uppercase = LabelSequence::new(&pointer.label().to_ascii_uppercase());
+        assert_eq!(uppercase.reverse_address(), Some(address));
+    }
+
+    #[test]
+    fn reverse_address_partial_names_fail() {
+        for name in [
+            "121.82.140.in-addr.arpa",
+            "04.121.82.140.in-addr.arpa",
+            "256.121.82.140.in-addr.arpa",
+            "8.b.d.0.1.0.0.2.ip6.arpa",
+            "www.github.com",
+        ] {
+            assert!(
+                LabelSequence::new(name).reverse_address().is_none(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn randomise_case_keeps_name_success() {
+        let name = LabelSequence::new("www.github.com");
+        let randomised = name.randomise_case();
+
+        assert!(randomised.eq_ignore_case(&name));
+    }
+
+    #[test]
+    fn canonical_order_sorts_by_labels_from_the_right() {
+        let mut names = [
+            "z.example",
+            "a.example",
+            "yljkjljk.a.example",
+            "Z.a.example",
+            "example",
+            "*.z.example",
+        ]
+        .map(LabelSequence::new);
+        names.sort_by(LabelSequence::canonical_cmp);
+
+        let names = names.iter().map(LabelSequence::label).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "example",
+                "a.example",
+                "yljkjljk.a.example",
+                "Z.a.example",
+                "z.example",
+                "*.z.example"
+            ]
+        );
+    }
 }
@@ -1,47 +1,313 @@
-use crate::protocol::ByteScanner;
+use crate::protocol::{ByteScan, ByteScanner, ScanResult};
+use bytes::{BufMut, Bytes, BytesMut};
 use derive_builder::Builder;
 
-use super::{query::Query, Header, Record};
+use super::{
+    query::Query, FlagsBuilder, Header, HeaderBuilder, MessageError, Record, RecordType,
+    ResponseCode,
+};
+
+/// Largest UDP message every client accepts, and the most one without EDNS does
+/// (RFC 1035 section 4.2.1).
+pub const MIN_UDP_PAYLOAD: u16 = 512;
 
 #[derive(Debug, Clone, Builder, PartialEq, Eq)]
 pub struct Message {
     header: Header,
-    question: Query,
-    answer: Vec<Record>,
+
+    #[builder(default)]
+    questions: Vec<Query>,
+
+    #[builder(default)]
+    answers: Vec<Record>,
+
+    #[builder(default)]
+    authorities: Vec<Record>,
+
+    #[builder(default)]
+    additionals: Vec<Record>,
+}
+
+impl Message {
+    /// Creates a standard query message with a random id.
+    pub fn query(query: Query, recursion_desired: bool) -> Self {
+        let flags = FlagsBuilder::default()
+            .recursion_desired(recursion_desired)
+            .build()
+            .expect("Flags have defaults for every field");
+
+        let header = HeaderBuilder::default()
+            .flags(flags)
+            .questions_size(1)
+            .build()
+            .expect("Header has defaults for every field");
+
+        Self {
+            header,
+            questions: vec![query],
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        }
+    }
+
+    /// Returns the same message with an OPT record advertising `payload_size`, unless
+    /// it has one already.
+    pub fn with_edns(mut self, payload_size: u16) -> Self {
+        if self.edns().is_none() {
+            self.additionals.push(Record::edns(payload_size));
+        }
+
+        self
+    }
+
+    /// Returns the same message with `record` added at the end of its additional
+    /// section, where a TSIG record has to go.
+    pub fn with_additional(mut self, record: Record) -> Self {
+        self.additionals.push(record);
+        self
+    }
+
+    /// Returns the same message under a different id.
+    pub fn with_id(self, id: u16) -> Self {
+        Self {
+            header: self.header.with_id(id),
+            ..self
+        }
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    pub fn questions(&self) -> &[Query] {
+        &self.questions
+    }
+    pub fn question(&self) -> Option<&Query> {
+        self.questions.first()
+    }
+    pub fn answers(&self) -> &[Record] {
+        &self.answers
+    }
+    pub fn authorities(&self) -> &[Record] {
+        &self.authorities
+    }
+    pub fn additionals(&self) -> &[Record] {
+        &self.additionals
+    }
+    pub fn response_code(&self) -> &ResponseCode {
+        self.header.flags().response()
+    }
+
+    /// The OPT pseudo-record of the additional section, present when the sender
+    /// supports EDNS (RFC 6891).
+    pub fn edns(&self) -> Option<&Record> {
+        self.additionals
+            .iter()
+            .find(|record| record.r#type() == &RecordType::OPT)
+    }
+
+    /// The largest UDP message the sender accepts: the payload size of its OPT
+    /// record, and never less than 512 bytes.
+    pub fn udp_payload_size(&self) -> u16 {
+        self.edns()
+            .map(|opt| u16::from(*opt.class()))
+            .unwrap_or(MIN_UDP_PAYLOAD)
+            .max(MIN_UDP_PAYLOAD)
+    }
+}
+
+fn scan_records(
+    message: &[u8],
+    cursor: &mut usize,
+    count: u16,
+) -> Result<Vec<Record>, MessageError> {
+    let mut records = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let scan = Record::try_scan(message, *cursor)?;
+        *cursor += scan.total_bytes();
+        records.push(scan.value().clone());
+    }
+
+    Ok(records)
 }
 
 impl ByteScanner for Message {
-    type Error = String;
-
-    fn try_scan(message: &[u8], cursor: usize) -> Result<(Self, usize), Self::Error> {
-        let (header, header_len) = Header::try_scan(message, cursor)?;
-        let (query, query_len) = Query::try_scan(message, cursor + header_len)?;
-
-        let mut records = vec![];
-        let mut records_len = 0;
-        if header.flags.message_type == MessageType::Response {
-            let mut cursor = cursor + header_len + query_len;
-            for _ in 0..header.answers_size {
-                let (record, record_len) = Record::try_scan(message, cursor)?;
-                records.push(record);
-                cursor += record_len;
-                records_len += record_len;
-            }
-        };
-
-        let answers = if records.is_empty() {
-            None
-        } else {
-            Some(records)
-        };
-
-        Ok((
+    type Error = MessageError;
+
+    fn try_scan(message: &[u8], cursor: usize) -> ScanResult<Self, Self::Error> {
+        let header = Header::try_scan(message, cursor)?;
+        let mut position = cursor + header.total_bytes();
+        let header = header.value().clone();
+
+        let mut questions = Vec::with_capacity(header.questions_size() as usize);
+        for _ in 0..header.questions_size() {
+            let scan = Query::try_scan(message, position)?;
+            position += scan.total_bytes();
+            questions.push(scan.value().clone());
+        }
+
+        let answers = scan_records(message, &mut position, header.answers_size())?;
+        let authorities = scan_records(message, &mut position, header.name_servers_size())?;
+        let additionals = scan_records(message, &mut position, header.additional_size())?;
+
+        Ok(ByteScan::new(
             Message {
                 header,
-                query,
+                questions,
                 answers,
+                authorities,
+                additionals,
             },
-            header_len + query_len + records_len,
+            position - cursor,
         ))
     }
 }
+
+impl From<Message> for Bytes {
+    fn from(value: Message) -> Self {
+        (&value).into()
+    }
+}
+
+impl From<&Message> for Bytes {
+    fn from(value: &Message) -> Self {
+        let mut buffer = BytesMut::new();
+        let flags: Bytes = value.header.flags().into();
+
+        // Section sizes always reflect the records actually being written
+        buffer.put_u16(value.header.id());
+        buffer.put_slice(&flags);
+        buffer.put_u16(value.questions.len() as u16);
+        buffer.put_u16(value.answers.len() as u16);
+        buffer.put_u16(value.authorities.len() as u16);
+        buffer.put_u16(value.additionals.len() as u16);
+
+        for query in &value.questions {
+            buffer.put_slice(&Bytes::from(query));
+        }
+
+        let records = value
+            .answers
+            .iter()
+            .chain(&value.authorities)
+            .chain(&value.additionals);
+
+        for record in records {
+            buffer.put_slice(&Bytes::from(record));
+        }
+
+        buffer.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::protocol::{Class, LabelSequence, MessageType, RecordData, RecordType};
+
+    #[test]
+    fn parse_bytes_response_with_compressed_answer_success() {
+        let bytes = [
+            0xa6, 0x29, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x03, 0x77,
+            0x77, 0x77, 0x06, 0x67, 0x69, 0x74, 0x68, 0x75, 0x62, 0x03, 0x63, 0x6f, 0x6d, 0x00,
+            0x00, 0x01, 0x00, 0x01, 0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c,
+            0x00, 0x04, 0x8c, 0x52, 0x79, 0x04,
+        ];
+
+        let message = Message::try_scan(&bytes, 0).expect("Failed to parse message");
+        assert_eq!(message.total_bytes(), bytes.len());
+
+        let message = message.value();
+        assert_eq!(message.header().id(), 0xa629);
+        assert_eq!(
+            message.header().flags().message_type(),
+            &MessageType::Response
+        );
+        assert_eq!(message.response_code(), &ResponseCode::NoError);
+
+        let question = message.question().expect("Missing question");
+        assert_eq!(question.name().label(), "www.github.com");
+        assert_eq!(question.r#type(), &RecordType::A);
+
+        assert_eq!(message.answers().len(), 1);
+        assert_eq!(message.answers()[0].name(), "www.github.com");
+        assert_eq!(
+            message.answers()[0].data(),
+            &RecordData::A(Ipv4Addr::new(140, 82, 121, 4))
+        );
+    }
+
+    #[test]
+    fn message_to_bytes_and_back_success() {
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::NS, Class::IN);
+        let header = HeaderBuilder::default()
+            .flags(
+                FlagsBuilder::default()
+                    .message_type(MessageType::Response)
+                    .build()
+                    .unwrap(),
+            )
+            .questions_size(1)
+            .answers_size(1)
+            .additional_size(1)
+            .build()
+            .unwrap();
+
+        let message = MessageBuilder::default()
+            .header(header)
+            .questions(vec![query])
+            .answers(vec![Record::new(
+                LabelSequence::new("github.com"),
+                Class::IN,
+                900,
+                RecordData::NS(LabelSequence::new("ns1.github.com")),
+            )])
+            .additionals(vec![Record::new(
+                LabelSequence::new("ns1.github.com"),
+                Class::IN,
+                900,
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )])
+            .build()
+            .unwrap();
+
+        let bytes: Bytes = message.clone().into();
+        let scanned = Message::try_scan(&bytes, 0).expect("Failed to parse message");
+
+        assert_eq!(scanned.value(), &message);
+    }
+
+    #[test]
+    fn query_with_edns_and_unknown_type_is_parsed() {
+        // What `dig example.com HTTPS` sends, with a cookie option
+        let bytes = [
+            0x12, 0x34, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x07, 0x65,
+            0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x03, 0x63, 0x6f, 0x6d, 0x00, 0x00, 0x41, 0x00,
+            0x01, 0x00, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x0a,
+            0x00, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+        ];
+
+        let scan = Message::try_scan(&bytes, 0).expect("Failed to parse message");
+        let message = scan.value();
+
+        assert_eq!(
+            message.question().unwrap().r#type(),
+            &RecordType::Unknown(65)
+        );
+        assert_eq!(message.edns().unwrap().r#type(), &RecordType::OPT);
+        assert_eq!(message.udp_payload_size(), 1232);
+        assert_eq!(Bytes::from(message).as_ref(), bytes);
+    }
+
+    #[test]
+    fn parse_bytes_message_missing_answer_fails() {
+        let bytes = [
+            0xa6, 0x29, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x01,
+        ];
+
+        assert!(Message::try_scan(&bytes, 0).is_err());
+    }
+}
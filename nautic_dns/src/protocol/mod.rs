@@ -5,6 +5,7 @@ mod header;
 mod label;
 mod message;
 mod query;
+mod rdata;
 mod record;
 mod types;
 
@@ -15,6 +16,7 @@ pub use header::*;
 pub use label::*;
 pub use message::*;
 pub use query::*;
+pub use rdata::*;
 pub use record::*;
 pub use types::*;
 
@@ -30,11 +32,11 @@ impl<T> ByteScan<T> {
     pub fn new(value: T, total_bytes: usize) -> Self {
         Self { value, total_bytes }
     }
-    
+
     pub fn value(&self) -> &T {
         &self.value
     }
-    
+
     pub fn total_bytes(&self) -> usize {
         self.total_bytes
     }
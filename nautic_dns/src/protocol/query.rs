@@ -4,7 +4,7 @@ use bytes::{BufMut, Bytes, BytesMut};
 
 use super::{label::LabelSequence, types::*, BitParseError, Class};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Query {
     name: LabelSequence,
     r#type: RecordType,
@@ -35,21 +35,22 @@ impl ByteScanner for Query {
     type Error = BitParseError;
 
     fn try_scan(message: &[u8], cursor: usize) -> ScanResult<Self, Self::Error> {
-        let name = LabelSequence::try_scan(message, cursor)?.value().clone();
-        let name_bytes = name.total_bits() / 8;
+        let scan = LabelSequence::try_scan(message, cursor)?;
+        let name = scan.value().clone();
+        let name_bytes = scan.total_bytes();
 
-        let value = &message[name_bytes..];
+        let value = message.get(cursor + name_bytes..).unwrap_or_default();
         let mut reader = bitter::BigEndianReader::new(value);
 
         let r#type: RecordType = reader
             .read_u16()
             .ok_or_else(|| BitParseError::MalformedBits("Record Type".into()))?
-            .try_into()?;
+            .into();
 
         let class: Class = reader
             .read_u16()
             .ok_or_else(|| BitParseError::MalformedBits("Class Type".into()))?
-            .try_into()?;
+            .into();
 
         Ok(ByteScan::new(
             Query::new(name, r#type, class),
@@ -71,8 +72,8 @@ impl From<&Query> for Bytes {
         let name = Bytes::from(value.name);
 
         buffer.put_slice(&name);
-        buffer.put_u16(value.r#type as u16);
-        buffer.put_u16(value.class as u16);
+        buffer.put_u16(value.r#type.into());
+        buffer.put_u16(value.class.into());
 
         buffer.freeze()
     }
@@ -0,0 +1,399 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use bitter::BitReader;
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::{BitParseError, ByteScanner, LabelSequence, RecordType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    AAAA(Ipv6Addr),
+    CNAME(LabelSequence),
+    DNAME(LabelSequence),
+    MX {
+        preference: u16,
+        exchange: LabelSequence,
+    },
+    NS(LabelSequence),
+    /// The EDNS options of an OPT pseudo-record, as sent (RFC 6891 section 6.1.2).
+    OPT(Vec<u8>),
+    PTR(LabelSequence),
+    SOA(StartOfAuthority),
+    TSIG(TransactionSignature),
+    TXT(Vec<Vec<u8>>),
+    /// RDATA of a type this library does not decode, kept as sent so it can be
+    /// passed on unchanged (RFC 3597).
+    Unknown {
+        r#type: u16,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StartOfAuthority {
+    pub mname: LabelSequence,
+    pub rname: LabelSequence,
+    pub serial: u32,
+    pub refresh: u32,
+    pub retry: u32,
+    pub expire: u32,
+    pub minimum: u32,
+}
+
+/// RDATA of a TSIG pseudo-record, which signs the message it ends (RFC 8945
+/// section 4.2).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionSignature {
+    pub algorithm: LabelSequence,
+    /// Seconds since the epoch, of which only the lower 48 bits are sent.
+    pub time_signed: u64,
+    /// Seconds of clock difference allowed either way.
+    pub fudge: u16,
+    pub mac: Vec<u8>,
+    /// Id of the message when it was signed, before any forwarder changed it.
+    pub original_id: u16,
+    /// Extended RCODE, such as BADSIG (16), BADKEY (17) or BADTIME (18).
+    pub error: u16,
+    pub other: Vec<u8>,
+}
+
+impl RecordData {
+    pub fn r#type(&self) -> RecordType {
+        match self {
+            RecordData::A(_) => RecordType::A,
+            RecordData::AAAA(_) => RecordType::AAAA,
+            RecordData::CNAME(_) => RecordType::CNAME,
+            RecordData::DNAME(_) => RecordType::DNAME,
+            RecordData::MX { .. } => RecordType::MX,
+            RecordData::NS(_) => RecordType::NS,
+            RecordData::OPT(_) => RecordType::OPT,
+            RecordData::PTR(_) => RecordType::PTR,
+            RecordData::SOA(_) => RecordType::SOA,
+            RecordData::TSIG(_) => RecordType::TSIG,
+            RecordData::TXT(_) => RecordType::TXT,
+            RecordData::Unknown { r#type, .. } => RecordType::from(*r#type),
+        }
+    }
+
+    /// Decodes `length` bytes of RDATA starting at `cursor`. The whole message is
+    /// required since names inside RDATA may be compressed.
+    pub fn try_scan(
+        r#type: &RecordType,
+        message: &[u8],
+        cursor: usize,
+        length: u16,
+    ) -> Result<Self, BitParseError> {
+        let end = cursor + length as usize;
+        let rdata = message
+            .get(cursor..end)
+            .ok_or_else(|| BitParseError::MalformedBits("Record Data".into()))?;
+
+        let mut reader = bitter::BigEndianReader::new(rdata);
+
+        let data = match r#type {
+            RecordType::A => {
+                let address = reader
+                    .read_u32()
+                    .ok_or_else(|| BitParseError::MalformedBits("A Address".into()))?;
+
+                RecordData::A(Ipv4Addr::from(address))
+            }
+            RecordType::AAAA => {
+                let octets: [u8; 16] = rdata
+                    .try_into()
+                    .map_err(|_| BitParseError::MalformedBits("AAAA Address".into()))?;
+
+                RecordData::AAAA(Ipv6Addr::from(octets))
+            }
+            RecordType::CNAME => RecordData::CNAME(scan_name(message, cursor)?),
+            RecordType::DNAME => RecordData::DNAME(scan_name(message, cursor)?),
+            RecordType::NS => RecordData::NS(scan_name(message, cursor)?),
+            RecordType::PTR => RecordData::PTR(scan_name(message, cursor)?),
+            RecordType::MX => {
+                let preference = reader
+                    .read_u16()
+                    .ok_or_else(|| BitParseError::MalformedBits("MX Preference".into()))?;
+
+                RecordData::MX {
+                    preference,
+                    exchange: scan_name(message, cursor + 2)?,
+                }
+            }
+            RecordType::SOA => {
+                let mname = LabelSequence::try_scan(message, cursor)?;
+                let rname_cursor = cursor + mname.total_bytes();
+                let rname = LabelSequence::try_scan(message, rname_cursor)?;
+
+                let timers_cursor = rname_cursor + rname.total_bytes();
+                let timers = message
+                    .get(timers_cursor..end)
+                    .ok_or_else(|| BitParseError::MalformedBits("SOA Timers".into()))?;
+
+                let mut reader = bitter::BigEndianReader::new(timers);
+                let mut read_timer = |field: &str| {
+                    reader
+                        .read_u32()
+                        .ok_or_else(|| BitParseError::MalformedBits(field.into()))
+                };
+
+                RecordData::SOA(StartOfAuthority {
+                    mname: mname.value().clone(),
+                    rname: rname.value().clone(),
+                    serial: read_timer("SOA Serial")?,
+                    refresh: read_timer("SOA Refresh")?,
+                    retry: read_timer("SOA Retry")?,
+                    expire: read_timer("SOA Expire")?,
+                    minimum: read_timer("SOA Minimum")?,
+                })
+            }
+            RecordType::TXT => {
+                let mut strings = vec![];
+                while reader.has_bits_remaining(8) {
+                    let length = reader
+                        .read_u8()
+                        .ok_or_else(|| BitParseError::MalformedBits("TXT Length".into()))?;
+
+                    let mut value = Vec::with_capacity(length as usize);
+                    for _ in 0..length {
+                        let byte = reader
+                            .read_u8()
+                            .ok_or_else(|| BitParseError::MalformedBits("TXT Character".into()))?;
+
+                        value.push(byte);
+                    }
+
+                    strings.push(value);
+                }
+
+                RecordData::TXT(strings)
+            }
+            RecordType::TSIG => {
+                let algorithm = LabelSequence::try_scan(message, cursor)?;
+                let fields = rdata
+                    .get(algorithm.total_bytes()..)
+                    .ok_or_else(|| BitParseError::MalformedBits("TSIG Fields".into()))?;
+
+                let mut reader = bitter::BigEndianReader::new(fields);
+                let mut read_u16 = |field: &str| {
+                    reader
+                        .read_u16()
+                        .ok_or_else(|| BitParseError::MalformedBits(field.into()))
+                };
+
+                let time_high = read_u16("TSIG Time Signed")?;
+                let time_low = read_u16("TSIG Time Signed")?;
+                let time_lowest = read_u16("TSIG Time Signed")?;
+                let fudge = read_u16("TSIG Fudge")?;
+                let mac_size = read_u16("TSIG MAC Size")? as usize;
+
+                let mac_start = 10;
+                let mac = fields
+                    .get(mac_start..mac_start + mac_size)
+                    .ok_or_else(|| BitParseError::MalformedBits("TSIG MAC".into()))?;
+
+                let tail = &fields[mac_start + mac_size..];
+                let [id_high, id_low, error_high, error_low, other_high, other_low, other @ ..] =
+                    tail
+                else {
+                    return Err(BitParseError::MalformedBits("TSIG Fields".into()));
+                };
+
+                let other_size = u16::from_be_bytes([*other_high, *other_low]) as usize;
+                if other.len() != other_size {
+                    return Err(BitParseError::MalformedBits("TSIG Other Data".into()));
+                }
+
+                RecordData::TSIG(TransactionSignature {
+                    algorithm: algorithm.value().clone(),
+                    time_signed: (time_high as u64) << 32
+                        | (time_low as u64) << 16
+                        | time_lowest as u64,
+                    fudge,
+                    mac: mac.to_vec(),
+                    original_id: u16::from_be_bytes([*id_high, *id_low]),
+                    error: u16::from_be_bytes([*error_high, *error_low]),
+                    other: other.to_vec(),
+                })
+            }
+            RecordType::OPT => RecordData::OPT(rdata.to_vec()),
+            RecordType::Unknown(r#type) => RecordData::Unknown {
+                r#type: *r#type,
+                data: rdata.to_vec(),
+            },
+        };
+
+        Ok(data)
+    }
+}
+
+fn scan_name(message: &[u8], cursor: usize) -> Result<LabelSequence, BitParseError> {
+    Ok(LabelSequence::try_scan(message, cursor)?.value().clone())
+}
+
+impl From<RecordData> for Bytes {
+    fn from(value: RecordData) -> Self {
+        (&value).into()
+    }
+}
+
+impl From<&RecordData> for Bytes {
+    fn from(value: &RecordData) -> Self {
+        let mut buffer = BytesMut::new();
+
+        match value {
+            RecordData::A(address) => buffer.put_slice(&address.octets()),
+            RecordData::AAAA(address) => buffer.put_slice(&address.octets()),
+            RecordData::CNAME(name)
+            | RecordData::DNAME(name)
+            | RecordData::NS(name)
+            | RecordData::PTR(name) => buffer.put_slice(&Bytes::from(name)),
+            RecordData::MX {
+                preference,
+                exchange,
+            } => {
+                buffer.put_u16(*preference);
+                buffer.put_slice(&Bytes::from(exchange));
+            }
+            RecordData::SOA(soa) => {
+                buffer.put_slice(&Bytes::from(&soa.mname));
+                buffer.put_slice(&Bytes::from(&soa.rname));
+                buffer.put_u32(soa.serial);
+                buffer.put_u32(soa.refresh);
+                buffer.put_u32(soa.retry);
+                buffer.put_u32(soa.expire);
+                buffer.put_u32(soa.minimum);
+            }
+            RecordData::TSIG(tsig) => {
+                buffer.put_slice(&Bytes::from(&tsig.algorithm));
+                buffer.put_slice(&tsig.time_signed.to_be_bytes()[2..]);
+                buffer.put_u16(tsig.fudge);
+                buffer.put_u16(tsig.mac.len() as u16);
+                buffer.put_slice(&tsig.mac);
+                buffer.put_u16(tsig.original_id);
+                buffer.put_u16(tsig.error);
+                buffer.put_u16(tsig.other.len() as u16);
+                buffer.put_slice(&tsig.other);
+            }
+            RecordData::OPT(data) | RecordData::Unknown { data, .. } => buffer.put_slice(data),
+            RecordData::TXT(strings) => {
+                for value in strings {
+                    let value = &value[..value.len().min(u8::MAX as usize)];
+                    buffer.put_u8(value.len() as u8);
+                    buffer.put_slice(value);
+                }
+            }
+        }
+
+        buffer.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_rdata_to_bytes_and_back_success() {
+        let data = RecordData::A(Ipv4Addr::new(140, 82, 121, 4));
+        let bytes: Bytes = data.clone().into();
+
+        assert_eq!(bytes, vec![140, 82, 121, 4]);
+
+        let scanned = RecordData::try_scan(&RecordType::A, &bytes, 0, 4).unwrap();
+        assert_eq!(scanned, data);
+    }
+
+    #[test]
+    fn txt_rdata_with_binary_bytes_to_bytes_and_back_success() {
+        let data = RecordData::TXT(vec![b"plain".to_vec(), vec![0xc3, 0xa9, 0x00, 0xff]]);
+        let bytes: Bytes = data.clone().into();
+
+        assert_eq!(bytes, b"\x05plain\x04\xc3\xa9\x00\xff".to_vec());
+
+        let scanned =
+            RecordData::try_scan(&RecordType::TXT, &bytes, 0, bytes.len() as u16).unwrap();
+        assert_eq!(scanned, data);
+    }
+
+    #[test]
+    fn soa_rdata_to_bytes_and_back_success() {
+        let data = RecordData::SOA(StartOfAuthority {
+            mname: LabelSequence::new("ns1.github.com"),
+            rname: LabelSequence::new("hostmaster.github.com"),
+            serial: 2023100101,
+            refresh: 3600,
+            retry: 600,
+            expire: 604800,
+            minimum: 60,
+        });
+
+        let bytes: Bytes = data.clone().into();
+        let scanned =
+            RecordData::try_scan(&RecordType::SOA, &bytes, 0, bytes.len() as u16).unwrap();
+
+        assert_eq!(scanned, data);
+    }
+
+    #[test]
+    fn mx_rdata_with_compressed_exchange_success() {
+        let mut message = BytesMut::new();
+        message.put_slice(&Bytes::from(LabelSequence::new("github.com")));
+        message.put_u16(10);
+        message.put_slice(&[0x04, b'm', b'a', b'i', b'l', 0xc0, 0x00]);
+
+        let data = RecordData::try_scan(&RecordType::MX, &message, 12, 9).unwrap();
+
+        assert_eq!(
+            data,
+            RecordData::MX {
+                preference: 10,
+                exchange: LabelSequence::new("mail.github.com")
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_rdata_is_kept_opaque() {
+        let bytes = [0x00, 0x01, 0x00, 0x00, 0x01, 0x00, 0x03, b'h', b'2', 0x00];
+        let data = RecordData::try_scan(&RecordType::Unknown(65), &bytes, 0, 10).unwrap();
+
+        assert_eq!(
+            data,
+            RecordData::Unknown {
+                r#type: 65,
+                data: bytes.to_vec()
+            }
+        );
+        assert_eq!(data.r#type(), RecordType::Unknown(65));
+        assert_eq!(Bytes::from(&data), bytes.to_vec());
+    }
+
+    #[test]
+    fn tsig_rdata_to_bytes_and_back_success() {
+        let data = RecordData::TSIG(TransactionSignature {
+            algorithm: LabelSequence::new("hmac-sha256"),
+            time_signed: 0x0001_6553_f100,
+            fudge: 300,
+            mac: vec![0xab; 32],
+            original_id: 0x1234,
+            error: 0,
+            other: vec![],
+        });
+
+        let bytes: Bytes = data.clone().into();
+        let scanned =
+            RecordData::try_scan(&RecordType::TSIG, &bytes, 0, bytes.len() as u16).unwrap();
+
+        assert_eq!(scanned, data);
+        assert!(RecordData::try_scan(&RecordType::TSIG, &bytes, 0, 20).is_err());
+    }
+
+    #[test]
+    fn aaaa_rdata_bad_length_fails() {
+        let bytes = [0x20, 0x01, 0x0d, 0xb8];
+        let data = RecordData::try_scan(&RecordType::AAAA, &bytes, 0, 4);
+
+        assert!(data.is_err());
+    }
+}
@@ -1,5 +1,6 @@
-use super::{types::*, Class};
-use crate::protocol::{BitParseError, ByteScanner, LabelSequence, ScanResult};
+use super::{types::*, Class, RecordData};
+use crate::protocol::{BitParseError, ByteScan, ByteScanner, LabelSequence, ScanResult};
+use bytes::{BufMut, Bytes, BytesMut};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Record {
@@ -8,21 +9,16 @@ pub struct Record {
     pub class: Class,
     pub ttl: u32,
     pub length: u16,
-    pub data: String,
+    pub data: RecordData,
 }
 
 impl Record {
-    pub fn new(
-        name: LabelSequence,
-        r#type: RecordType,
-        class: Class,
-        ttl: u32,
-        length: u16,
-        data: String,
-    ) -> Self {
+    pub fn new(name: LabelSequence, class: Class, ttl: u32, data: RecordData) -> Self {
+        let length = Bytes::from(&data).len() as u16;
+
         Self {
             name,
-            r#type,
+            r#type: data.r#type(),
             class,
             ttl,
             length,
@@ -50,9 +46,20 @@ impl Record {
         self.length
     }
 
-    pub fn data(&self) -> &str {
+    pub fn data(&self) -> &RecordData {
         &self.data
     }
+
+    /// An OPT pseudo-record without options, advertising `payload_size` as the
+    /// largest UDP message the sender accepts (RFC 6891 section 6.1.2).
+    pub fn edns(payload_size: u16) -> Self {
+        Self::new(
+            LabelSequence::root(),
+            Class::from(payload_size),
+            0,
+            RecordData::OPT(vec![]),
+        )
+    }
 }
 
 impl ByteScanner for Record {
@@ -60,17 +67,20 @@ impl ByteScanner for Record {
 
     fn try_scan(message: &[u8], cursor: usize) -> ScanResult<Self, Self::Error> {
         let scan = LabelSequence::try_scan(message, cursor)?;
-        let label = scan.value();
-        let scan_length = scan.total_bytes;
+        let name = scan.value().clone();
+        let name_len = scan.total_bytes;
 
-        let value = &message[cursor + scan_length..];
-        let r#type = RecordType::try_from(u16::from_be_bytes([value[0], value[1]]))?;
-        let class = Class::try_from(u16::from_be_bytes([value[2], value[3]]))?;
+        let value = message
+            .get(cursor + name_len..cursor + name_len + 10)
+            .ok_or_else(|| BitParseError::MalformedBits("Record Fields".into()))?;
+
+        let r#type = RecordType::from(u16::from_be_bytes([value[0], value[1]]));
+        let class = Class::from(u16::from_be_bytes([value[2], value[3]]));
         let ttl = u32::from_be_bytes([value[4], value[5], value[6], value[7]]);
         let length = u16::from_be_bytes([value[8], value[9]]);
-        let data = decode_rdata(&class, &r#type, message, cursor + name_len + 10)?;
+        let data = RecordData::try_scan(&r#type, message, cursor + name_len + 10, length)?;
 
-        Ok((
+        Ok(ByteScan::new(
             Self {
                 name,
                 r#type,
@@ -84,65 +94,79 @@ impl ByteScanner for Record {
     }
 }
 
-// impl TryFrom<&Vec<u8>> for Record {
-//     type Error = BitParseError;
-
-//     fn try_from(value: Vec<u8>) -> Result<Self, Self::Error> {
-//         let mut reader = bitter::BigEndianReader::new(&value);
-//         let mut name = String::new();
-
-//         let r#type: RecordType = reader
-//             .read_u16()
-//             .ok_or_else(|| BitParseError::MalformedBits("Record Type".into()))?
-//             .try_into()?;
-
-//         let class = reader
-//             .read_u16()
-//             .ok_or_else(|| BitParseError::MalformedBits("Class Type".into()))?;
-
-//         let class: Class = class.try_into()?;
-
-//         let ttl = reader
-//             .read_u32()
-//             .ok_or_else(|| BitParseError::MalformedBits("TTL".into()))?;
+impl From<Record> for Bytes {
+    fn from(value: Record) -> Self {
+        (&value).into()
+    }
+}
 
-//         let length = reader
-//             .read_u16()
-//             .ok_or_else(|| BitParseError::MalformedBits("Length".into()))?;
+impl From<&Record> for Bytes {
+    fn from(value: &Record) -> Self {
+        let mut buffer = BytesMut::new();
+        let data = Bytes::from(&value.data);
 
-//         let data = todo!();
+        buffer.put_slice(&Bytes::from(&value.name));
+        buffer.put_u16(value.r#type.into());
+        buffer.put_u16(value.class.into());
+        buffer.put_u32(value.ttl);
+        buffer.put_u16(data.len() as u16);
+        buffer.put_slice(&data);
 
-//         Ok(Record::new(
-//             name,
-//             r#type,
-//             class,
-//             ttl,
-//             length,
-//             data,
-//         ))
-//     }
-// }
+        buffer.freeze()
+    }
+}
 
-// impl From<Record> for Vec<u8> {
-//     fn from(value: Record) -> Self {
-//         let mut buffer = vec![];
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn parse_bytes_record_a_sample_domain_success() {
+        let bytes = [
+            0x03, 0x77, 0x77, 0x77, 0x06, 0x67, 0x69, 0x74, 0x68, 0x75, 0x62, 0x03, 0x63, 0x6f,
+            0x6d, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x04, 0x8c, 0x52,
+            0x79, 0x04,
+        ];
+
+        let record = Record::try_scan(&bytes, 0).expect("Failed to parse record");
+
+        assert_eq!(record.total_bytes(), bytes.len());
+
+        let record = record.value();
+        assert_eq!(record.name(), "www.github.com");
+        assert_eq!(record.r#type(), &RecordType::A);
+        assert_eq!(record.class(), &Class::IN);
+        assert_eq!(record.ttl(), 60);
+        assert_eq!(record.length(), 4);
+        assert_eq!(
+            record.data(),
+            &RecordData::A(Ipv4Addr::new(140, 82, 121, 4))
+        );
+    }
 
-//         let labels = value.name.trim().split('.').collect::<Vec<&str>>();
+    #[test]
+    fn record_to_bytes_and_back_success() {
+        let record = Record::new(
+            LabelSequence::new("github.com"),
+            Class::IN,
+            300,
+            RecordData::NS(LabelSequence::new("ns1.github.com")),
+        );
 
-//         for label in labels {
-//             let length = label.len() as u8;
-//             buffer.put_u8(length);
+        let bytes: Bytes = record.clone().into();
+        let scanned = Record::try_scan(&bytes, 0).expect("Failed to parse record");
 
-//             let chars = label.chars().collect::<Vec<char>>();
-//             for i in 0..length {
-//                 buffer.put_u8(chars[i as usize] as u8);
-//             }
-//         }
+        assert_eq!(scanned.total_bytes(), bytes.len());
+        assert_eq!(scanned.value(), &record);
+    }
 
-//         buffer.put_u8(0);
-//         buffer.put_u16(value.r#type as u16);
-//         buffer.put_u16(value.class);
+    #[test]
+    fn parse_bytes_record_truncated_fails() {
+        let bytes = [0x03, 0x77, 0x77, 0x77, 0x00, 0x00, 0x01, 0x00];
+        let record = Record::try_scan(&bytes, 0);
 
-//         buffer
-//     }
-// }
+        assert!(record.is_err());
+    }
+}
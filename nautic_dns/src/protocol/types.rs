@@ -1,26 +1,135 @@
-use super::BitParseError;
+use std::borrow::Cow;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-#[repr(u16)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RecordType {
-    A = 1,
-    AAAA = 28,
-    CNAME = 5,
-    MX = 15,
-    NS = 2,
+    A,
+    AAAA,
+    CNAME,
+    DNAME,
+    MX,
+    NS,
+    OPT,
+    PTR,
+    SOA,
+    TSIG,
+    TXT,
+    /// Any other type, carried as opaque RDATA (RFC 3597). Types listed above are
+    /// never represented this way.
+    Unknown(u16),
 }
 
-impl TryFrom<u16> for RecordType {
-    type Error = BitParseError;
+impl From<u16> for RecordType {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => Self::A,
+            28 => Self::AAAA,
+            5 => Self::CNAME,
+            39 => Self::DNAME,
+            15 => Self::MX,
+            2 => Self::NS,
+            41 => Self::OPT,
+            12 => Self::PTR,
+            6 => Self::SOA,
+            250 => Self::TSIG,
+            16 => Self::TXT,
+            _ => Self::Unknown(value),
+        }
+    }
+}
 
-    fn try_from(value: u16) -> Result<Self, Self::Error> {
+impl From<RecordType> for u16 {
+    fn from(value: RecordType) -> Self {
         match value {
-            1 => Ok(Self::A),
-            28 => Ok(Self::AAAA),
-            5 => Ok(Self::CNAME),
-            15 => Ok(Self::MX),
-            2 => Ok(Self::NS),
-            _ => Err(BitParseError::BadField("Record Type".into(), value as u64)),
+            RecordType::A => 1,
+            RecordType::AAAA => 28,
+            RecordType::CNAME => 5,
+            RecordType::DNAME => 39,
+            RecordType::MX => 15,
+            RecordType::NS => 2,
+            RecordType::OPT => 41,
+            RecordType::PTR => 12,
+            RecordType::SOA => 6,
+            RecordType::TSIG => 250,
+            RecordType::TXT => 16,
+            RecordType::Unknown(value) => value,
+        }
+    }
+}
+
+impl RecordType {
+    pub const ALL: [RecordType; 11] = [
+        Self::A,
+        Self::AAAA,
+        Self::CNAME,
+        Self::DNAME,
+        Self::MX,
+        Self::NS,
+        Self::OPT,
+        Self::PTR,
+        Self::SOA,
+        Self::TSIG,
+        Self::TXT,
+    ];
+
+    /// The name of this type in presentation format, e.g. `AAAA`, or `TYPE65` for
+    /// types without one here (RFC 3597 section 5).
+    pub fn mnemonic(&self) -> Cow<'static, str> {
+        let mnemonic = match self {
+            Self::A => "A",
+            Self::AAAA => "AAAA",
+            Self::CNAME => "CNAME",
+            Self::DNAME => "DNAME",
+            Self::MX => "MX",
+            Self::NS => "NS",
+            Self::OPT => "OPT",
+            Self::PTR => "PTR",
+            Self::SOA => "SOA",
+            Self::TSIG => "TSIG",
+            Self::TXT => "TXT",
+            Self::Unknown(value) => return Cow::Owned(format!("TYPE{value}")),
+        };
+
+        Cow::Borrowed(mnemonic)
+    }
+
+    /// Looks a type up by its presentation name, ignoring case. The generic
+    /// `TYPE<number>` form is accepted for every type.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        if let Some(value) = strip_prefix_ignore_case(mnemonic, "TYPE") {
+            return value.parse::<u16>().ok().map(Self::from);
         }
+
+        Self::ALL
+            .into_iter()
+            .find(|r#type| r#type.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+}
+
+/// `text` without `prefix`, when it starts with it in any case.
+pub(crate) fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+        .filter(|rest| !rest.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_types_round_trip() {
+        assert_eq!(RecordType::from(28), RecordType::AAAA);
+        assert_eq!(RecordType::from(65), RecordType::Unknown(65));
+        assert_eq!(u16::from(RecordType::Unknown(65)), 65);
+
+        assert_eq!(RecordType::Unknown(65).mnemonic(), "TYPE65");
+        assert_eq!(
+            RecordType::from_mnemonic("type65"),
+            Some(RecordType::Unknown(65))
+        );
+        assert_eq!(RecordType::from_mnemonic("TYPE1"), Some(RecordType::A));
+        assert_eq!(RecordType::from_mnemonic("aaaa"), Some(RecordType::AAAA));
+        assert_eq!(RecordType::from_mnemonic("TYPE"), None);
     }
 }
@@ -0,0 +1,627 @@
+use std::{num::NonZeroUsize, time::Duration};
+
+use derive_builder::Builder;
+use lru::LruCache;
+use tokio::time::Instant;
+
+use super::{chain::AliasChain, synthesize_response};
+use crate::protocol::{
+    Class, LabelSequence, Message, Query, Record, RecordData, RecordType, ResponseCode,
+};
+
+#[derive(Debug, Clone, Builder)]
+pub struct CacheConfig {
+    /// Entries beyond this limit evict the least recently used ones.
+    #[builder(default = "10_000")]
+    max_entries: usize,
+
+    #[builder(default = "0")]
+    min_ttl: u32,
+
+    #[builder(default = "86_400")]
+    max_ttl: u32,
+
+    /// Upper bound for NXDOMAIN/NODATA entries, on top of the SOA minimum (RFC 2308).
+    #[builder(default = "3_600")]
+    max_negative_ttl: u32,
+
+    /// How long expired entries are kept around to be served while upstreams are
+    /// unreachable (RFC 8767). Zero disables serve-stale.
+    #[builder(default = "0")]
+    max_stale_ttl: u32,
+
+    /// TTL given to records in a stale answer.
+    #[builder(default = "30")]
+    stale_answer_ttl: u32,
+
+    /// Refresh popular entries in the background shortly before they expire.
+    #[builder(default = "false")]
+    prefetch: bool,
+
+    /// Entries become eligible for prefetch once less than this percentage of
+    /// their original TTL remains.
+    #[builder(default = "10")]
+    prefetch_threshold: u8,
+
+    /// Hits an entry needs before it is considered popular enough to prefetch.
+    #[builder(default = "2")]
+    prefetch_min_hits: u32,
+}
+
+impl CacheConfig {
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+    pub fn min_ttl(&self) -> u32 {
+        self.min_ttl
+    }
+    pub fn max_ttl(&self) -> u32 {
+        self.max_ttl
+    }
+    pub fn max_negative_ttl(&self) -> u32 {
+        self.max_negative_ttl
+    }
+    pub fn max_stale_ttl(&self) -> u32 {
+        self.max_stale_ttl
+    }
+    pub fn stale_answer_ttl(&self) -> u32 {
+        self.stale_answer_ttl
+    }
+    pub fn prefetch(&self) -> bool {
+        self.prefetch
+    }
+    pub fn prefetch_threshold(&self) -> u8 {
+        self.prefetch_threshold
+    }
+    pub fn prefetch_min_hits(&self) -> u32 {
+        self.prefetch_min_hits
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfigBuilder::default()
+            .build()
+            .expect("Cache config has defaults for every field")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    name: LabelSequence,
+    r#type: RecordType,
+    class: Class,
+}
+
+impl From<&Query> for CacheKey {
+    fn from(query: &Query) -> Self {
+        Self {
+            name: query.name().to_lowercase(),
+            r#type: *query.r#type(),
+            class: *query.class(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum CachedAnswer {
+    Records(Vec<Record>),
+    NoData(Record),
+    /// The aliases leading to the name that does not exist, if any, and the SOA.
+    NoDomain {
+        chain: Vec<Record>,
+        soa: Record,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    answer: CachedAnswer,
+    inserted: Instant,
+    expires: Instant,
+    ttl: u32,
+    hits: u32,
+    prefetching: bool,
+}
+
+impl CacheEntry {
+    fn response(&self, query: &Query, ttl: impl Fn(u32) -> u32) -> Message {
+        let age = |record: &Record| {
+            let mut record = record.clone();
+            record.ttl = ttl(record.ttl);
+            record
+        };
+
+        let (response_code, answers, authorities) = match &self.answer {
+            CachedAnswer::Records(records) => (
+                ResponseCode::NoError,
+                records.iter().map(age).collect(),
+                vec![],
+            ),
+            CachedAnswer::NoData(soa) => (ResponseCode::NoError, vec![], vec![age(soa)]),
+            CachedAnswer::NoDomain { chain, soa } => (
+                ResponseCode::NoDomain,
+                chain.iter().map(age).collect(),
+                vec![age(soa)],
+            ),
+        };
+
+        synthesize_response(query, response_code, answers, authorities)
+    }
+}
+
+/// In-memory cache of upstream answers keyed by (name, type, class).
+pub struct DnsCache {
+    config: CacheConfig,
+    entries: LruCache<CacheKey, CacheEntry>,
+}
+
+impl DnsCache {
+    pub fn new(config: CacheConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.max_entries()).unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            config,
+            entries: LruCache::new(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns a response for `query` with TTLs reduced by the time spent in the cache.
+    pub fn get(&mut self, query: &Query) -> Option<Message> {
+        let now = Instant::now();
+        let entry = self.live_entry(query, now)?;
+        if entry.expires <= now {
+            return None;
+        }
+
+        entry.hits += 1;
+        let elapsed = now.duration_since(entry.inserted).as_secs() as u32;
+
+        Some(entry.response(query, |ttl| ttl.saturating_sub(elapsed)))
+    }
+
+    /// Like [`DnsCache::get`], but also answers from entries that expired less than
+    /// `max_stale_ttl` seconds ago. Stale records carry `stale_answer_ttl`.
+    pub fn get_stale(&mut self, query: &Query) -> Option<Message> {
+        let now = Instant::now();
+        let stale_answer_ttl = self.config.stale_answer_ttl();
+
+        let entry = self.live_entry(query, now)?;
+        if entry.expires > now {
+            return self.get(query);
+        }
+
+        Some(entry.response(query, |_| stale_answer_ttl))
+    }
+
+    /// Whether `query` should be refreshed ahead of its expiry. This only returns
+    /// `true` once per cached answer, so callers may start a single refresh.
+    pub fn take_prefetch(&mut self, query: &Query) -> bool {
+        if !self.config.prefetch() {
+            return false;
+        }
+
+        let now = Instant::now();
+        let threshold = self.config.prefetch_threshold() as u64;
+        let min_hits = self.config.prefetch_min_hits();
+
+        let Some(entry) = self.live_entry(query, now) else {
+            return false;
+        };
+
+        if entry.prefetching || entry.expires <= now || entry.hits < min_hits {
+            return false;
+        }
+
+        let remaining = entry.expires.duration_since(now).as_millis() as u64;
+        if remaining * 100 > entry.ttl as u64 * 1000 * threshold {
+            return false;
+        }
+
+        entry.prefetching = true;
+        true
+    }
+
+    /// Lets the entry for `query` be prefetched again, once the refresh handed out
+    /// by [`DnsCache::take_prefetch`] is over without replacing it.
+    pub fn end_prefetch(&mut self, query: &Query) {
+        if let Some(entry) = self.entries.get_mut(&CacheKey::from(query)) {
+            entry.prefetching = false;
+        }
+    }
+
+    /// Finds the entry for `query`, dropping it once it is past its serve-stale window.
+    fn live_entry(&mut self, query: &Query, now: Instant) -> Option<&mut CacheEntry> {
+        let key = CacheKey::from(query);
+        let stale_window = Duration::from_secs(self.config.max_stale_ttl() as u64);
+
+        let expires = self.entries.get(&key)?.expires;
+        if expires + stale_window <= now {
+            self.entries.pop(&key);
+            return None;
+        }
+
+        self.entries.get_mut(&key)
+    }
+
+    /// Stores `response` as the answer to `query`. Responses that cannot be cached,
+    /// such as server failures or negative answers without an SOA, are ignored.
+    ///
+    /// A name error reached through aliases keeps the chain next to the SOA, and the
+    /// name the chain ends at is cached as not existing too (RFC 2308 section 2.1).
+    pub fn insert(&mut self, query: &Query, response: &Message) {
+        if response.header().flags().truncation() {
+            return;
+        }
+
+        let answer = match response.response_code() {
+            ResponseCode::NoError if !response.answers().is_empty() => {
+                CachedAnswer::Records(self.clamp_records(response.answers()))
+            }
+            ResponseCode::NoError => match self.negative_soa(response) {
+                Some(soa) => CachedAnswer::NoData(soa),
+                None => return,
+            },
+            ResponseCode::NoDomain => {
+                let Some(soa) = self.negative_soa(response) else {
+                    return;
+                };
+
+                let mut aliases = AliasChain::new(query, u8::MAX);
+                if aliases.follow(response.answers()).is_err() {
+                    return;
+                }
+
+                if !aliases.name().eq_ignore_case(query.name()) {
+                    let target =
+                        Query::new(aliases.name().clone(), *query.r#type(), *query.class());
+                    let answer = CachedAnswer::NoDomain {
+                        chain: vec![],
+                        soa: soa.clone(),
+                    };
+                    self.put(&target, answer);
+                }
+
+                CachedAnswer::NoDomain {
+                    chain: self.clamp_records(response.answers()),
+                    soa,
+                }
+            }
+            _ => return,
+        };
+
+        self.put(query, answer);
+    }
+
+    /// Caches `answer` for as long as its shortest lived record.
+    fn put(&mut self, query: &Query, answer: CachedAnswer) {
+        let ttl = match &answer {
+            CachedAnswer::Records(records) => records.iter().map(Record::ttl).min(),
+            CachedAnswer::NoData(soa) => Some(soa.ttl()),
+            CachedAnswer::NoDomain { chain, soa } => {
+                chain.iter().map(Record::ttl).chain([soa.ttl()]).min()
+            }
+        };
+
+        let ttl = match ttl {
+            Some(ttl) if ttl > 0 => ttl,
+            _ => return,
+        };
+
+        let now = Instant::now();
+        self.entries.put(
+            CacheKey::from(query),
+            CacheEntry {
+                answer,
+                inserted: now,
+                expires: now + Duration::from_secs(ttl as u64),
+                ttl,
+                hits: 0,
+                prefetching: false,
+            },
+        );
+    }
+
+    fn clamp_records(&self, records: &[Record]) -> Vec<Record> {
+        records
+            .iter()
+            .map(|record| {
+                let mut record = record.clone();
+                record.ttl = self.clamp_ttl(record.ttl);
+                record
+            })
+            .collect()
+    }
+
+    fn clamp_ttl(&self, ttl: u32) -> u32 {
+        ttl.clamp(
+            self.config.min_ttl(),
+            self.config.max_ttl().max(self.config.min_ttl()),
+        )
+    }
+
+    /// Finds the authority SOA and rewrites its TTL to the negative caching TTL,
+    /// which is the lesser of the SOA TTL and its minimum field.
+    fn negative_soa(&self, response: &Message) -> Option<Record> {
+        let mut soa = response
+            .authorities()
+            .iter()
+            .find(|record| record.r#type() == &RecordType::SOA)?
+            .clone();
+
+        let RecordData::SOA(data) = soa.data() else {
+            return None;
+        };
+
+        let ttl = soa.ttl.min(data.minimum);
+        soa.ttl = self
+            .clamp_ttl(ttl)
+            .min(self.config.max_negative_ttl().max(self.config.min_ttl()));
+
+        Some(soa)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::protocol::StartOfAuthority;
+
+    fn query(name: &str) -> Query {
+        Query::new(LabelSequence::new(name), RecordType::A, Class::IN)
+    }
+
+    fn a_response(name: &str, ttl: u32) -> Message {
+        synthesize_response(
+            &query(name),
+            ResponseCode::NoError,
+            vec![Record::new(
+                LabelSequence::new(name),
+                Class::IN,
+                ttl,
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            )],
+            vec![],
+        )
+    }
+
+    fn negative_response(name: &str, response_code: ResponseCode, minimum: u32) -> Message {
+        let soa = Record::new(
+            LabelSequence::new("github.com"),
+            Class::IN,
+            900,
+            RecordData::SOA(StartOfAuthority {
+                mname: LabelSequence::new("ns1.github.com"),
+                rname: LabelSequence::new("hostmaster.github.com"),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 604800,
+                minimum,
+            }),
+        );
+
+        synthesize_response(&query(name), response_code, vec![], vec![soa])
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cached_answer_ttl_is_decremented() {
+        let mut cache = DnsCache::new(CacheConfig::default());
+        cache.insert(&query("www.github.com"), &a_response("www.github.com", 60));
+
+        tokio::time::advance(Duration::from_secs(25)).await;
+
+        let response = cache.get(&query("WWW.GitHub.com")).expect("Missing entry");
+        assert_eq!(response.answers()[0].ttl(), 35);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cached_answer_expires() {
+        let mut cache = DnsCache::new(CacheConfig::default());
+        cache.insert(&query("www.github.com"), &a_response("www.github.com", 60));
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+
+        assert!(cache.get(&query("www.github.com")).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn negative_answer_uses_soa_minimum() {
+        let mut cache = DnsCache::new(CacheConfig::default());
+        let response = negative_response("nope.github.com", ResponseCode::NoDomain, 30);
+        cache.insert(&query("nope.github.com"), &response);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+
+        let cached = cache.get(&query("nope.github.com")).expect("Missing entry");
+        assert_eq!(cached.response_code(), &ResponseCode::NoDomain);
+        assert_eq!(cached.authorities()[0].ttl(), 20);
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        assert!(cache.get(&query("nope.github.com")).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn name_error_keeps_alias_chain() {
+        let mut cache = DnsCache::new(CacheConfig::default());
+        let alias = Record::new(
+            LabelSequence::new("www.github.com"),
+            Class::IN,
+            60,
+            RecordData::CNAME(LabelSequence::new("gone.github.com")),
+        );
+        let negative = negative_response("gone.github.com", ResponseCode::NoDomain, 300);
+        let response = synthesize_response(
+            &query("www.github.com"),
+            ResponseCode::NoDomain,
+            vec![alias.clone()],
+            negative.authorities().to_vec(),
+        );
+        cache.insert(&query("www.github.com"), &response);
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+
+        let cached = cache.get(&query("www.github.com")).expect("Missing entry");
+        assert_eq!(cached.response_code(), &ResponseCode::NoDomain);
+        assert_eq!(cached.answers().len(), 1);
+        assert_eq!(cached.answers()[0].data(), alias.data());
+        assert_eq!(cached.answers()[0].ttl(), 50);
+        assert_eq!(cached.authorities()[0].r#type(), &RecordType::SOA);
+
+        let target = cache.get(&query("gone.github.com")).expect("Missing entry");
+        assert_eq!(target.response_code(), &ResponseCode::NoDomain);
+        assert!(target.answers().is_empty());
+        assert_eq!(target.authorities()[0].ttl(), 290);
+
+        tokio::time::advance(Duration::from_secs(50)).await;
+        assert!(cache.get(&query("www.github.com")).is_none());
+        assert!(cache.get(&query("gone.github.com")).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn nodata_answer_is_cached() {
+        let mut cache = DnsCache::new(CacheConfig::default());
+        let response = negative_response("github.com", ResponseCode::NoError, 300);
+        cache.insert(&query("github.com"), &response);
+
+        let cached = cache.get(&query("github.com")).expect("Missing entry");
+        assert_eq!(cached.response_code(), &ResponseCode::NoError);
+        assert!(cached.answers().is_empty());
+        assert_eq!(cached.authorities()[0].ttl(), 300);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ttl_is_clamped_to_configured_bounds() {
+        let config = CacheConfigBuilder::default()
+            .min_ttl(30)
+            .max_ttl(120)
+            .build()
+            .unwrap();
+
+        let mut cache = DnsCache::new(config);
+        cache.insert(
+            &query("short.github.com"),
+            &a_response("short.github.com", 5),
+        );
+        cache.insert(
+            &query("long.github.com"),
+            &a_response("long.github.com", 9000),
+        );
+
+        let short = cache.get(&query("short.github.com")).unwrap();
+        let long = cache.get(&query("long.github.com")).unwrap();
+
+        assert_eq!(short.answers()[0].ttl(), 30);
+        assert_eq!(long.answers()[0].ttl(), 120);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn least_recently_used_entry_is_evicted() {
+        let config = CacheConfigBuilder::default()
+            .max_entries(2)
+            .build()
+            .unwrap();
+        let mut cache = DnsCache::new(config);
+
+        cache.insert(&query("a.github.com"), &a_response("a.github.com", 60));
+        cache.insert(&query("b.github.com"), &a_response("b.github.com", 60));
+        cache.get(&query("a.github.com"));
+        cache.insert(&query("c.github.com"), &a_response("c.github.com", 60));
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&query("a.github.com")).is_some());
+        assert!(cache.get(&query("b.github.com")).is_none());
+        assert!(cache.get(&query("c.github.com")).is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn server_failure_is_not_cached() {
+        let mut cache = DnsCache::new(CacheConfig::default());
+        let response = synthesize_response(
+            &query("www.github.com"),
+            ResponseCode::ServerFailure,
+            vec![],
+            vec![],
+        );
+
+        cache.insert(&query("www.github.com"), &response);
+
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn expired_answer_is_served_stale_within_window() {
+        let config = CacheConfigBuilder::default()
+            .max_stale_ttl(300)
+            .build()
+            .unwrap();
+
+        let mut cache = DnsCache::new(config);
+        cache.insert(&query("www.github.com"), &a_response("www.github.com", 60));
+
+        tokio::time::advance(Duration::from_secs(120)).await;
+
+        assert!(cache.get(&query("www.github.com")).is_none());
+        let stale = cache
+            .get_stale(&query("www.github.com"))
+            .expect("Missing entry");
+        assert_eq!(stale.answers()[0].ttl(), 30);
+
+        tokio::time::advance(Duration::from_secs(300)).await;
+
+        assert!(cache.get_stale(&query("www.github.com")).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn popular_entry_is_prefetched_once_near_expiry() {
+        let config = CacheConfigBuilder::default()
+            .prefetch(true)
+            .prefetch_threshold(10)
+            .prefetch_min_hits(2)
+            .build()
+            .unwrap();
+
+        let mut cache = DnsCache::new(config);
+        cache.insert(&query("www.github.com"), &a_response("www.github.com", 100));
+        cache.get(&query("www.github.com"));
+
+        tokio::time::advance(Duration::from_secs(95)).await;
+        assert!(!cache.take_prefetch(&query("www.github.com")));
+
+        cache.get(&query("www.github.com"));
+        assert!(cache.take_prefetch(&query("www.github.com")));
+        assert!(!cache.take_prefetch(&query("www.github.com")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn entry_is_not_prefetched_early() {
+        let config = CacheConfigBuilder::default()
+            .prefetch(true)
+            .prefetch_min_hits(0)
+            .build()
+            .unwrap();
+
+        let mut cache = DnsCache::new(config);
+        cache.insert(&query("www.github.com"), &a_response("www.github.com", 100));
+
+        tokio::time::advance(Duration::from_secs(50)).await;
+
+        assert!(!cache.take_prefetch(&query("www.github.com")));
+    }
+}
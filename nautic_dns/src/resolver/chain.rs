@@ -0,0 +1,226 @@
+use std::collections::HashSet;
+
+use super::synthesize_response;
+use crate::{
+    errors::NauticDnsError,
+    protocol::{LabelSequence, Message, Query, Record, RecordData, RecordType, ResponseCode},
+};
+
+/// The outcome of a lookup once its CNAME and DNAME aliases have been followed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup {
+    query: Query,
+    canonical_name: LabelSequence,
+    response_code: ResponseCode,
+    chain: Vec<Record>,
+    records: Vec<Record>,
+    authorities: Vec<Record>,
+}
+
+impl Lookup {
+    /// The query as it was first asked, after search list expansion.
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+    /// The name the chain ends at, which owns the final RRset.
+    pub fn canonical_name(&self) -> &LabelSequence {
+        &self.canonical_name
+    }
+    pub fn response_code(&self) -> &ResponseCode {
+        &self.response_code
+    }
+    /// The CNAME and DNAME records that were followed, in order.
+    pub fn chain(&self) -> &[Record] {
+        &self.chain
+    }
+    /// The records of the queried type owned by the canonical name.
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+    pub fn authorities(&self) -> &[Record] {
+        &self.authorities
+    }
+
+    /// Flattens the lookup back into a response to the original query, with the
+    /// chain leading the answer section.
+    pub fn into_response(self) -> Message {
+        let mut answers = self.chain;
+        answers.extend(self.records);
+
+        synthesize_response(&self.query, self.response_code, answers, self.authorities)
+    }
+}
+
+/// Tracks the aliases followed for one query, which may span several responses.
+pub(crate) struct AliasChain {
+    query: Query,
+    name: LabelSequence,
+    max_length: usize,
+    seen: HashSet<LabelSequence>,
+    records: Vec<Record>,
+}
+
+impl AliasChain {
+    pub(crate) fn new(query: &Query, max_length: u8) -> Self {
+        Self {
+            query: query.clone(),
+            name: query.name().clone(),
+            max_length: max_length as usize,
+            seen: HashSet::from([query.name().to_lowercase()]),
+            records: vec![],
+        }
+    }
+
+    /// The name the chain currently ends at.
+    pub(crate) fn name(&self) -> &LabelSequence {
+        &self.name
+    }
+
+    /// Follows the aliases for the current name through `answers`, returning whether
+    /// the chain moved on to a new name. A DNAME above the current name takes
+    /// precedence over the CNAME a server may have synthesized from it.
+    pub(crate) fn follow(&mut self, answers: &[Record]) -> Result<bool, NauticDnsError> {
+        if self.query.r#type() == &RecordType::CNAME {
+            return Ok(false);
+        }
+
+        let start = self.name.clone();
+        while let Some((record, next)) = self.next_alias(answers) {
+            if !self.seen.insert(next.to_lowercase()) {
+                return Err(NauticDnsError::AliasLoop(self.query.name().to_string()));
+            }
+
+            self.records.push(record);
+            if self.records.len() > self.max_length {
+                return Err(NauticDnsError::RecursionLimitExceeded(
+                    self.query.name().to_string(),
+                ));
+            }
+
+            self.name = next;
+        }
+
+        Ok(!self.name.eq_ignore_case(&start))
+    }
+
+    fn next_alias(&self, answers: &[Record]) -> Option<(Record, LabelSequence)> {
+        let dname = answers.iter().find_map(|record| match record.data() {
+            RecordData::DNAME(target) if self.query.r#type() != &RecordType::DNAME => self
+                .name
+                .substitute(&record.name, target)
+                .map(|next| (record.clone(), next)),
+            _ => None,
+        });
+
+        dname.or_else(|| {
+            answers.iter().find_map(|record| match record.data() {
+                RecordData::CNAME(target) if record.name.eq_ignore_case(&self.name) => {
+                    Some((record.clone(), target.clone()))
+                }
+                _ => None,
+            })
+        })
+    }
+
+    /// Whether `answers` hold the final RRset for the current name.
+    pub(crate) fn is_answered(&self, answers: &[Record]) -> bool {
+        answers.iter().any(|record| self.is_final(record))
+    }
+
+    fn is_final(&self, record: &Record) -> bool {
+        record.name.eq_ignore_case(&self.name) && record.r#type() == self.query.r#type()
+    }
+
+    /// Completes the chain with the final RRset and status taken from `response`.
+    pub(crate) fn finish(self, response: &Message) -> Lookup {
+        let records = response
+            .answers()
+            .iter()
+            .filter(|record| self.is_final(record))
+            .cloned()
+            .collect();
+
+        Lookup {
+            query: self.query,
+            canonical_name: self.name,
+            response_code: response.response_code().clone(),
+            chain: self.records,
+            records,
+            authorities: response.authorities().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::protocol::Class;
+
+    fn record(name: &str, data: RecordData) -> Record {
+        Record::new(LabelSequence::new(name), Class::IN, 300, data)
+    }
+
+    fn query(name: &str) -> Query {
+        Query::new(LabelSequence::new(name), RecordType::A, Class::IN)
+    }
+
+    #[test]
+    fn follow_cname_then_dname_success() {
+        let answers = vec![
+            record(
+                "www.github.com",
+                RecordData::CNAME(LabelSequence::new("www.old.example")),
+            ),
+            record(
+                "old.example",
+                RecordData::DNAME(LabelSequence::new("new.example")),
+            ),
+            record("www.new.example", RecordData::A(Ipv4Addr::new(10, 0, 0, 1))),
+        ];
+
+        let mut chain = AliasChain::new(&query("www.github.com"), 8);
+        assert!(chain.follow(&answers).unwrap());
+        assert!(chain.is_answered(&answers));
+        assert_eq!(chain.name().label(), "www.new.example");
+    }
+
+    #[test]
+    fn follow_cname_loop_fails() {
+        let answers = vec![
+            record(
+                "a.example",
+                RecordData::CNAME(LabelSequence::new("b.example")),
+            ),
+            record(
+                "b.example",
+                RecordData::CNAME(LabelSequence::new("a.example")),
+            ),
+        ];
+
+        let mut chain = AliasChain::new(&query("a.example"), 8);
+        assert!(matches!(
+            chain.follow(&answers),
+            Err(NauticDnsError::AliasLoop(_))
+        ));
+    }
+
+    #[test]
+    fn follow_chain_longer_than_limit_fails() {
+        let answers = (0..4)
+            .map(|i| {
+                record(
+                    &format!("{i}.example"),
+                    RecordData::CNAME(LabelSequence::new(&format!("{}.example", i + 1))),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut chain = AliasChain::new(&query("0.example"), 3);
+        assert!(matches!(
+            chain.follow(&answers),
+            Err(NauticDnsError::RecursionLimitExceeded(_))
+        ));
+    }
+}
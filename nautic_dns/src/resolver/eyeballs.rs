@@ -0,0 +1,309 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    time::Duration,
+};
+
+use tokio::{
+    net::TcpStream,
+    task::JoinSet,
+    time::{self, Instant},
+};
+
+use crate::errors::NauticDnsError;
+
+pub(crate) type AddressFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Vec<IpAddr>, NauticDnsError>> + Send + 'a>>;
+
+enum Event {
+    Ipv6(Result<Vec<IpAddr>, NauticDnsError>),
+    Ipv4(Result<Vec<IpAddr>, NauticDnsError>),
+    ResolutionDelay,
+}
+
+/// The addresses of a name, resolved over A and AAAA queries sent in parallel and
+/// handed out as they arrive in the order of Happy Eyeballs (RFC 8305 section 3 and
+/// 4): IPv6 first, then alternating between the two families.
+pub struct IpLookup<'a> {
+    ipv6: Option<AddressFuture<'a>>,
+    ipv4: Option<AddressFuture<'a>>,
+    ipv6_addresses: VecDeque<IpAddr>,
+    ipv4_addresses: VecDeque<IpAddr>,
+    next_is_ipv6: bool,
+    started: bool,
+    resolution_delay: Duration,
+    resolution_deadline: Option<Instant>,
+    error: Option<NauticDnsError>,
+}
+
+impl<'a> IpLookup<'a> {
+    pub(crate) fn new(
+        ipv6: AddressFuture<'a>,
+        ipv4: AddressFuture<'a>,
+        resolution_delay: Duration,
+    ) -> Self {
+        Self {
+            ipv6: Some(ipv6),
+            ipv4: Some(ipv4),
+            ipv6_addresses: VecDeque::new(),
+            ipv4_addresses: VecDeque::new(),
+            next_is_ipv6: true,
+            started: false,
+            resolution_delay,
+            resolution_deadline: None,
+            error: None,
+        }
+    }
+
+    /// A lookup that yields `addresses` straight away, for names that are already
+    /// addresses.
+    pub(crate) fn ready(addresses: Vec<IpAddr>) -> Self {
+        let (ipv6, ipv4) = addresses
+            .into_iter()
+            .partition::<Vec<_>, _>(IpAddr::is_ipv6);
+
+        Self {
+            ipv6: None,
+            ipv4: None,
+            ipv6_addresses: ipv6.into(),
+            ipv4_addresses: ipv4.into(),
+            next_is_ipv6: true,
+            started: false,
+            resolution_delay: Duration::ZERO,
+            resolution_deadline: None,
+            error: None,
+        }
+    }
+
+    /// Returns the next address to try, or `None` once every address has been
+    /// handed out. Fails only when no address was found and a query failed.
+    ///
+    /// Cancelling the returned future loses nothing, so it can be raced against
+    /// other work.
+    pub async fn next(&mut self) -> Result<Option<IpAddr>, NauticDnsError> {
+        loop {
+            // An A answer arriving first is held back for the resolution delay, in
+            // case the AAAA answer is about to follow
+            let hold_ipv4 = !self.started
+                && self.ipv6.is_some()
+                && self.ipv6_addresses.is_empty()
+                && self
+                    .resolution_deadline
+                    .map_or(true, |deadline| Instant::now() < deadline);
+
+            if !hold_ipv4 {
+                if let Some(address) = self.pop() {
+                    self.started = true;
+                    return Ok(Some(address));
+                }
+            }
+
+            if self.ipv6.is_none() && self.ipv4.is_none() {
+                return match (self.started, self.error.take()) {
+                    (false, Some(error)) => Err(error),
+                    _ => Ok(None),
+                };
+            }
+
+            let deadline = self.resolution_deadline.unwrap_or_else(Instant::now);
+            let waiting = hold_ipv4 && self.resolution_deadline.is_some();
+            let event = tokio::select! {
+                // AAAA goes out first (RFC 8305 section 3)
+                biased;
+
+                result = resolve(&mut self.ipv6) => Event::Ipv6(result),
+                result = resolve(&mut self.ipv4) => Event::Ipv4(result),
+                _ = time::sleep_until(deadline), if waiting => Event::ResolutionDelay,
+            };
+
+            match event {
+                Event::Ipv6(result) => {
+                    self.ipv6 = None;
+                    self.receive(result, true);
+                }
+                Event::Ipv4(result) => {
+                    self.ipv4 = None;
+                    self.receive(result, false);
+                    self.resolution_deadline = Some(Instant::now() + self.resolution_delay);
+                }
+                Event::ResolutionDelay => {}
+            }
+        }
+    }
+
+    fn receive(&mut self, result: Result<Vec<IpAddr>, NauticDnsError>, ipv6: bool) {
+        let addresses = match ipv6 {
+            true => &mut self.ipv6_addresses,
+            false => &mut self.ipv4_addresses,
+        };
+
+        match result {
+            Ok(found) => addresses.extend(found),
+            Err(error) => self.error = Some(error),
+        }
+    }
+
+    /// Takes the next address, alternating families where both have addresses left.
+    fn pop(&mut self) -> Option<IpAddr> {
+        let (preferred, other) = match self.next_is_ipv6 {
+            true => (&mut self.ipv6_addresses, &mut self.ipv4_addresses),
+            false => (&mut self.ipv4_addresses, &mut self.ipv6_addresses),
+        };
+
+        let address = preferred.pop_front().or_else(|| other.pop_front())?;
+        self.next_is_ipv6 = !address.is_ipv6();
+
+        Some(address)
+    }
+}
+
+async fn resolve(future: &mut Option<AddressFuture<'_>>) -> Result<Vec<IpAddr>, NauticDnsError> {
+    match future {
+        Some(future) => future.await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Connects to `port` on the addresses of `lookup`, starting a new attempt every
+/// `attempt_delay` or as soon as the previous one fails, and keeps the first
+/// connection to succeed (RFC 8305 section 5).
+pub(crate) async fn race_connections(
+    mut lookup: IpLookup<'_>,
+    port: u16,
+    attempt_delay: Duration,
+) -> Result<TcpStream, NauticDnsError> {
+    let mut attempts = JoinSet::new();
+    let mut next_attempt = Instant::now();
+    let mut addresses_left = true;
+    let mut last_error = None;
+
+    loop {
+        let due = Instant::now() >= next_attempt;
+
+        tokio::select! {
+            address = lookup.next(), if addresses_left && due => match address {
+                Ok(Some(address)) => {
+                    attempts.spawn(TcpStream::connect(SocketAddr::new(address, port)));
+                    next_attempt = Instant::now() + attempt_delay;
+                }
+                Ok(None) => addresses_left = false,
+                Err(error) => {
+                    addresses_left = false;
+                    last_error = Some(error);
+                }
+            },
+            _ = time::sleep_until(next_attempt), if addresses_left && !due => {}
+            attempt = attempts.join_next(), if !attempts.is_empty() => match attempt {
+                Some(Ok(Ok(stream))) => return Ok(stream),
+                Some(Ok(Err(error))) => {
+                    last_error = Some(error.into());
+                    next_attempt = Instant::now();
+                }
+                Some(Err(error)) => {
+                    last_error = Some(io::Error::new(io::ErrorKind::Other, error).into());
+                    next_attempt = Instant::now();
+                }
+                None => {}
+            },
+            else => {
+                return Err(last_error.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "No addresses to connect to").into()
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn delayed(addresses: Vec<IpAddr>, delay: u64) -> AddressFuture<'static> {
+        Box::pin(async move {
+            time::sleep(Duration::from_millis(delay)).await;
+            Ok(addresses)
+        })
+    }
+
+    fn ipv6(last: u16) -> IpAddr {
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, last))
+    }
+
+    fn ipv4(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    async fn collect(mut lookup: IpLookup<'_>) -> Vec<IpAddr> {
+        let mut addresses = vec![];
+        while let Some(address) = lookup.next().await.unwrap() {
+            addresses.push(address);
+        }
+
+        addresses
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn late_aaaa_within_resolution_delay_goes_first() {
+        let lookup = IpLookup::new(
+            delayed(vec![ipv6(1), ipv6(2)], 30),
+            delayed(vec![ipv4(1), ipv4(2)], 0),
+            Duration::from_millis(50),
+        );
+
+        assert_eq!(
+            collect(lookup).await,
+            vec![ipv6(1), ipv4(1), ipv6(2), ipv4(2)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_aaaa_does_not_hold_back_ipv4() {
+        let started = Instant::now();
+        let mut lookup = IpLookup::new(
+            delayed(vec![ipv6(1)], 500),
+            delayed(vec![ipv4(1), ipv4(2)], 10),
+            Duration::from_millis(50),
+        );
+
+        assert_eq!(lookup.next().await.unwrap(), Some(ipv4(1)));
+        assert_eq!(started.elapsed(), Duration::from_millis(60));
+        assert_eq!(lookup.next().await.unwrap(), Some(ipv4(2)));
+        assert_eq!(lookup.next().await.unwrap(), Some(ipv6(1)));
+        assert_eq!(lookup.next().await.unwrap(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failure_is_returned_when_nothing_is_found() {
+        let mut lookup = IpLookup::new(
+            Box::pin(async { Err(NauticDnsError::Timeout) }),
+            delayed(vec![], 0),
+            Duration::from_millis(50),
+        );
+
+        assert!(matches!(lookup.next().await, Err(NauticDnsError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn race_skips_refused_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Nothing listens on the IPv6 loopback, so the first attempt is refused
+        let lookup = IpLookup::ready(vec![
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+        ]);
+        let stream = race_connections(lookup, port, Duration::from_secs(5))
+            .await
+            .unwrap();
+
+        assert_eq!(stream.peer_addr().unwrap().port(), port);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::broadcast;
+
+use super::CacheKey;
+use crate::{
+    errors::NauticDnsError,
+    protocol::{Message, Query},
+};
+
+type SharedResult = Result<Message, String>;
+
+/// Upstream queries currently in progress. Callers asking the same question while
+/// it is in flight wait for the first caller's answer instead of sending their own.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    queries: Mutex<HashMap<CacheKey, broadcast::Sender<SharedResult>>>,
+}
+
+impl InFlight {
+    /// Runs `fetch` for `query` unless the same query is already in flight, in which
+    /// case its result is shared. If the caller running the query gives up on it,
+    /// one of the waiting callers takes over.
+    pub(crate) async fn run<F, Fut>(
+        self: &Arc<Self>,
+        query: &Query,
+        fetch: F,
+    ) -> Result<Message, NauticDnsError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Message, NauticDnsError>>,
+    {
+        let key = CacheKey::from(query);
+
+        loop {
+            let waiting = {
+                let mut queries = self.queries.lock().unwrap();
+                match queries.get(&key) {
+                    Some(sender) => Some(sender.subscribe()),
+                    None => {
+                        queries.insert(key.clone(), broadcast::channel(1).0);
+                        None
+                    }
+                }
+            };
+
+            let Some(mut receiver) = waiting else {
+                let guard = InFlightGuard {
+                    in_flight: self.clone(),
+                    key: Some(key.clone()),
+                };
+
+                let result = fetch().await;
+                guard.finish(&result);
+
+                return result;
+            };
+
+            // A closed channel means the query was abandoned, so try again
+            if let Ok(result) = receiver.recv().await {
+                return result.map_err(NauticDnsError::InFlightFailure);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.queries.lock().unwrap().len()
+    }
+}
+
+/// Removes an in-flight query once it completes or is abandoned.
+struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+    key: Option<CacheKey>,
+}
+
+impl InFlightGuard {
+    fn finish(mut self, result: &Result<Message, NauticDnsError>) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+
+        let sender = self.in_flight.queries.lock().unwrap().remove(&key);
+        if let Some(sender) = sender {
+            let shared = match result {
+                Ok(response) => Ok(response.clone()),
+                Err(error) => Err(error.to_string()),
+            };
+
+            let _ = sender.send(shared);
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.in_flight.queries.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use tokio::time;
+
+    use super::*;
+    use crate::{
+        protocol::{Class, LabelSequence, RecordType, ResponseCode},
+        resolver::synthesize_response,
+    };
+
+    fn query() -> Query {
+        Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN)
+    }
+
+    /// Spawns `count` callers that all run a slow fetch for the same query.
+    fn spawn_callers(
+        in_flight: &Arc<InFlight>,
+        fetches: &Arc<AtomicUsize>,
+        count: usize,
+        fail: bool,
+    ) -> Vec<tokio::task::JoinHandle<Result<Message, NauticDnsError>>> {
+        (0..count)
+            .map(|_| {
+                let in_flight = in_flight.clone();
+                let fetches = fetches.clone();
+                tokio::spawn(async move {
+                    in_flight
+                        .run(&query(), || async {
+                            fetches.fetch_add(1, Ordering::SeqCst);
+                            time::sleep(Duration::from_secs(1)).await;
+                            match fail {
+                                true => Err(NauticDnsError::Timeout),
+                                false => Ok(synthesize_response(
+                                    &query(),
+                                    ResponseCode::NoError,
+                                    vec![],
+                                    vec![],
+                                )),
+                            }
+                        })
+                        .await
+                })
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_callers_share_one_fetch() {
+        let in_flight = Arc::new(InFlight::default());
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        for caller in spawn_callers(&in_flight, &fetches, 10, false) {
+            assert!(caller.await.unwrap().is_ok());
+        }
+
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        assert_eq!(in_flight.len(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failure_is_shared_with_waiting_callers() {
+        let in_flight = Arc::new(InFlight::default());
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let results = join_all(spawn_callers(&in_flight, &fetches, 3, true)).await;
+
+        assert!(matches!(results[0], Err(NauticDnsError::Timeout)));
+        assert!(matches!(
+            results[1],
+            Err(NauticDnsError::InFlightFailure(_))
+        ));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn abandoned_query_is_taken_over() {
+        let in_flight = Arc::new(InFlight::default());
+        let fetches = Arc::new(AtomicUsize::new(0));
+
+        let callers = spawn_callers(&in_flight, &fetches, 2, false);
+        time::sleep(Duration::from_millis(500)).await;
+        callers[0].abort();
+
+        let result = join_all(callers).await;
+
+        assert!(result[1].is_ok());
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+    }
+
+    async fn join_all(
+        callers: Vec<tokio::task::JoinHandle<Result<Message, NauticDnsError>>>,
+    ) -> Vec<Result<Message, NauticDnsError>> {
+        let mut results = vec![];
+        for caller in callers {
+            match caller.await {
+                Ok(result) => results.push(result),
+                Err(_) => results.push(Err(NauticDnsError::Timeout)),
+            }
+        }
+
+        results
+    }
+}
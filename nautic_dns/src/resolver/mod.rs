@@ -0,0 +1,1440 @@
+mod cache;
+mod chain;
+mod eyeballs;
+mod inflight;
+mod recursor;
+mod upstream;
+
+pub use cache::*;
+pub use chain::Lookup;
+pub use eyeballs::IpLookup;
+pub use recursor::*;
+
+use chain::AliasChain;
+use eyeballs::race_connections;
+use inflight::InFlight;
+use upstream::Upstreams;
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use derive_builder::Builder;
+use tokio::net::TcpStream;
+use url::{Host, Url};
+
+use crate::{
+    errors::NauticDnsError,
+    protocol::{
+        Class, FlagsBuilder, HeaderBuilder, LabelSequence, Message, MessageBuilder, MessageType,
+        Query, Record, RecordData, RecordType, ResponseCode,
+    },
+    transport::{
+        check_bootstrap, endpoint_address, DnsTransport, HttpMethod, Protocol, TlsConfig,
+        UpstreamSpec,
+    },
+    util::{parse_domain, search_candidates},
+};
+
+#[derive(Debug, Clone, Builder)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct ResolverConfig {
+    #[builder(default)]
+    name_servers: Vec<SocketAddr>,
+
+    /// Suffixes appended to relative names, as with `search` in resolv.conf.
+    #[builder(default)]
+    search: Vec<String>,
+
+    /// Names with at least this many dots are tried as-is before the search list.
+    #[builder(default = "1")]
+    ndots: u8,
+
+    #[builder(default = "Duration::from_secs(5)")]
+    timeout: Duration,
+
+    #[builder(default = "2")]
+    attempts: u8,
+
+    #[builder(default)]
+    protocol: Protocol,
+
+    /// Share one UDP socket per name server between all queries instead of sending
+    /// each query from a fresh random port. Stream protocols always share one
+    /// connection per name server.
+    #[builder(default)]
+    multiplex: bool,
+
+    /// Certificate verification for [`Protocol::Tls`], which requires it. With
+    /// [`Protocol::Https`] it replaces verification against the endpoint host.
+    #[builder(setter(strip_option), default)]
+    tls: Option<TlsConfig>,
+
+    /// `https://host/dns-query` URLs queried with [`Protocol::Https`], in place of
+    /// `name_servers`. Named hosts need their address in the fragment, e.g.
+    /// `https://dns.example/dns-query#addr=192.0.2.53`.
+    #[builder(default)]
+    https_endpoints: Vec<Url>,
+
+    #[builder(default)]
+    https_method: HttpMethod,
+
+    /// Upstreams parsed from URLs such as `udp://9.9.9.9` or `tls://1.1.1.1`,
+    /// each with its own protocol. When set, they replace `name_servers`,
+    /// `https_endpoints` and `protocol`.
+    #[builder(default)]
+    upstreams: Vec<UpstreamSpec>,
+
+    /// Encrypted upstreams given by IP address, which the TLS and HTTPS upstreams
+    /// given by host name are looked up through, e.g. `tls://9.9.9.9` for
+    /// `https://dns.example/dns-query`.
+    #[builder(default)]
+    bootstrap: Vec<UpstreamSpec>,
+
+    /// How long an A answer is held back waiting for the AAAA answer when it arrives
+    /// first (RFC 8305 section 3).
+    #[builder(default = "Duration::from_millis(50)")]
+    resolution_delay: Duration,
+
+    /// How long a connection attempt gets before the next address is tried in
+    /// parallel (RFC 8305 section 5).
+    #[builder(default = "Duration::from_millis(250)")]
+    connection_attempt_delay: Duration,
+
+    /// Stream connections with no queries pending are closed after this long.
+    #[builder(default = "Duration::from_secs(30)")]
+    idle_timeout: Duration,
+
+    /// Send queries with the letters of the name in random case and only accept
+    /// answers that echo it exactly.
+    #[builder(default)]
+    case_randomisation: bool,
+
+    /// Longest chain of CNAME and DNAME records followed before giving up.
+    #[builder(default = "8")]
+    max_cname_chain: u8,
+
+    /// Answers are cached in memory when set.
+    #[builder(setter(strip_option), default)]
+    cache: Option<CacheConfig>,
+
+    /// Resolve iteratively from the root hints instead of forwarding queries to
+    /// `name_servers` when set.
+    #[builder(setter(strip_option), default)]
+    recursion: Option<RecursorConfig>,
+}
+
+impl ResolverConfig {
+    pub fn name_servers(&self) -> &[SocketAddr] {
+        &self.name_servers
+    }
+    pub fn search(&self) -> &[String] {
+        &self.search
+    }
+    pub fn ndots(&self) -> u8 {
+        self.ndots
+    }
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+    pub fn multiplex(&self) -> bool {
+        self.multiplex
+    }
+    pub fn case_randomisation(&self) -> bool {
+        self.case_randomisation
+    }
+    pub fn max_cname_chain(&self) -> u8 {
+        self.max_cname_chain
+    }
+    pub fn cache(&self) -> Option<&CacheConfig> {
+        self.cache.as_ref()
+    }
+    pub fn recursion(&self) -> Option<&RecursorConfig> {
+        self.recursion.as_ref()
+    }
+    pub fn tls(&self) -> Option<&TlsConfig> {
+        self.tls.as_ref()
+    }
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+    pub fn https_endpoints(&self) -> &[Url] {
+        &self.https_endpoints
+    }
+    pub fn https_method(&self) -> HttpMethod {
+        self.https_method
+    }
+    pub fn upstreams(&self) -> &[UpstreamSpec] {
+        &self.upstreams
+    }
+    pub fn bootstrap(&self) -> &[UpstreamSpec] {
+        &self.bootstrap
+    }
+    pub fn resolution_delay(&self) -> Duration {
+        self.resolution_delay
+    }
+    pub fn connection_attempt_delay(&self) -> Duration {
+        self.connection_attempt_delay
+    }
+}
+
+impl ResolverConfigBuilder {
+    fn validate(&self) -> Result<(), String> {
+        let protocol = self.protocol.unwrap_or_default();
+        let upstreams = self.upstreams.as_deref().unwrap_or_default();
+        let encrypted = matches!(protocol, Protocol::Tls | Protocol::Https)
+            || upstreams.iter().any(UpstreamSpec::is_encrypted);
+
+        // Recursion talks plaintext to authoritative servers, which encryption is
+        // meant to rule out
+        if encrypted && matches!(self.recursion, Some(Some(_))) {
+            return Err("Recursion cannot be combined with encrypted DNS".into());
+        }
+
+        check_bootstrap(upstreams, self.bootstrap.as_deref().unwrap_or_default())?;
+
+        if !upstreams.is_empty() || !matches!(protocol, Protocol::Tls | Protocol::Https) {
+            return Ok(());
+        }
+
+        if protocol == Protocol::Tls && !matches!(self.tls, Some(Some(_))) {
+            return Err("DNS over TLS requires a TLS configuration".into());
+        }
+
+        let endpoints = self.https_endpoints.as_deref().unwrap_or_default();
+        if protocol == Protocol::Https && endpoints.is_empty() {
+            return Err("DNS over HTTPS requires at least one endpoint".into());
+        }
+
+        if let Some(url) = endpoints.iter().find(|url| url.scheme() != "https") {
+            return Err(format!("{url} is not an https:// URL"));
+        }
+
+        // Endpoint hosts are not looked up, see `HttpsClient::new`
+        if let Some(url) = endpoints.iter().find(|url| endpoint_address(url).is_err()) {
+            return Err(format!("{url} needs an IP address or #addr= for its host"));
+        }
+
+        Ok(())
+    }
+}
+
+pub struct DnsResolver {
+    config: Arc<ResolverConfig>,
+    upstreams: Arc<Upstreams>,
+    in_flight: Arc<InFlight>,
+    cache: Option<Arc<Mutex<DnsCache>>>,
+}
+
+impl DnsResolver {
+    pub fn new(config: ResolverConfig) -> Self {
+        let config = Arc::new(config);
+        let upstreams = Upstreams::new(config.clone());
+
+        Self::with_upstreams(config, upstreams)
+    }
+
+    /// Creates a resolver that sends its queries through `transports` instead of the
+    /// configured name servers, trying them in order. The protocol and name server
+    /// settings of `config` are ignored.
+    pub fn with_transports(config: ResolverConfig, transports: Vec<Arc<dyn DnsTransport>>) -> Self {
+        let config = Arc::new(config);
+        let upstreams = Upstreams::with_transports(config.clone(), transports);
+
+        Self::with_upstreams(config, upstreams)
+    }
+
+    fn with_upstreams(config: Arc<ResolverConfig>, upstreams: Upstreams) -> Self {
+        let cache = config
+            .cache()
+            .map(|cache| Arc::new(Mutex::new(DnsCache::new(cache.clone()))));
+
+        Self {
+            upstreams: Arc::new(upstreams),
+            in_flight: Arc::new(InFlight::default()),
+            config,
+            cache,
+        }
+    }
+
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    /// Resolves the host of `target` into its IPv4 and IPv6 addresses.
+    pub async fn resolve(&self, target: &Url) -> Result<Vec<IpAddr>, NauticDnsError> {
+        match target.host() {
+            Some(Host::Ipv4(address)) => return Ok(vec![IpAddr::V4(address)]),
+            Some(Host::Ipv6(address)) => return Ok(vec![IpAddr::V6(address)]),
+            _ => {}
+        }
+
+        let domain = parse_domain(target)?;
+
+        let mut addresses = self.addresses(&domain, RecordType::A).await?;
+        addresses.extend(self.addresses(&domain, RecordType::AAAA).await?);
+
+        Ok(addresses)
+    }
+
+    /// Resolves `name` into its addresses with A and AAAA queries sent in parallel,
+    /// handing each address out as soon as it should be tried, in Happy Eyeballs
+    /// order (RFC 8305).
+    pub fn lookup_ip<'a>(&'a self, name: &'a str) -> IpLookup<'a> {
+        if let Ok(address) = name.parse::<IpAddr>() {
+            return IpLookup::ready(vec![address]);
+        }
+
+        IpLookup::new(
+            Box::pin(self.addresses(name, RecordType::AAAA)),
+            Box::pin(self.addresses(name, RecordType::A)),
+            self.config.resolution_delay(),
+        )
+    }
+
+    /// Opens a TCP connection to `port` on `name`, racing connection attempts to
+    /// its addresses as they are resolved and keeping the first to succeed.
+    pub async fn connect_tcp(&self, name: &str, port: u16) -> Result<TcpStream, NauticDnsError> {
+        race_connections(
+            self.lookup_ip(name),
+            port,
+            self.config.connection_attempt_delay(),
+        )
+        .await
+    }
+
+    async fn addresses(
+        &self,
+        name: &str,
+        r#type: RecordType,
+    ) -> Result<Vec<IpAddr>, NauticDnsError> {
+        let lookup = self.lookup_chain(name, r#type).await?;
+
+        Ok(lookup
+            .records()
+            .iter()
+            .filter_map(|record| match record.data() {
+                RecordData::A(address) => Some(IpAddr::V4(*address)),
+                RecordData::AAAA(address) => Some(IpAddr::V6(*address)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the names the PTR records for `address` point to. Classless
+    /// delegations (RFC 2317) are followed through their CNAMEs.
+    pub async fn reverse_lookup(
+        &self,
+        address: IpAddr,
+    ) -> Result<Vec<LabelSequence>, NauticDnsError> {
+        let pointer = LabelSequence::reverse_pointer(address);
+        let lookup = self
+            .lookup_chain(&pointer.to_string(), RecordType::PTR)
+            .await?;
+
+        Ok(lookup
+            .records()
+            .iter()
+            .filter_map(|record| match record.data() {
+                RecordData::PTR(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Returns the names `address` reverse resolves to that also resolve forward to
+    /// `address`, which is forward-confirmed reverse DNS (FCrDNS). An empty list means
+    /// the address is not confirmed.
+    pub async fn forward_confirmed_names(
+        &self,
+        address: IpAddr,
+    ) -> Result<Vec<LabelSequence>, NauticDnsError> {
+        let r#type = match address {
+            IpAddr::V4(_) => RecordType::A,
+            IpAddr::V6(_) => RecordType::AAAA,
+        };
+
+        let mut confirmed = vec![];
+        for name in self.reverse_lookup(address).await? {
+            let lookup = self.lookup_chain(&name.to_string(), r#type).await?;
+            let matches = lookup.records().iter().any(|record| match record.data() {
+                RecordData::A(forward) => IpAddr::V4(*forward) == address,
+                RecordData::AAAA(forward) => IpAddr::V6(*forward) == address,
+                _ => false,
+            });
+
+            if matches {
+                confirmed.push(name);
+            }
+        }
+
+        Ok(confirmed)
+    }
+
+    /// Looks up `name`, expanding it with the configured search list. Candidates are
+    /// tried in order until one of them is answered with anything but NXDOMAIN.
+    pub async fn lookup(&self, name: &str, r#type: RecordType) -> Result<Message, NauticDnsError> {
+        let mut last_response = None;
+
+        for candidate in search_candidates(name, self.config.search(), self.config.ndots()) {
+            let response = self.query(Query::new(candidate, r#type, Class::IN)).await?;
+            if response.response_code() != &ResponseCode::NoDomain {
+                return Ok(response);
+            }
+
+            last_response = Some(response);
+        }
+
+        last_response.ok_or_else(|| NauticDnsError::InvalidTarget(name.to_owned()))
+    }
+
+    /// Looks up `name` like [`DnsResolver::lookup`], then keeps following its CNAME
+    /// and DNAME aliases with further queries until the final RRset is found.
+    pub async fn lookup_chain(
+        &self,
+        name: &str,
+        r#type: RecordType,
+    ) -> Result<Lookup, NauticDnsError> {
+        let mut response = self.lookup(name, r#type).await?;
+        let query = response
+            .question()
+            .cloned()
+            .ok_or_else(|| NauticDnsError::InvalidTarget(name.to_owned()))?;
+
+        let mut chain = AliasChain::new(&query, self.config.max_cname_chain());
+        while chain.follow(response.answers())?
+            && !chain.is_answered(response.answers())
+            && response.response_code() == &ResponseCode::NoError
+        {
+            let next = Query::new(chain.name().clone(), r#type, *query.class());
+            response = self.query(next).await?;
+        }
+
+        Ok(chain.finish(&response))
+    }
+
+    /// Answers a single fully qualified query from the cache, or from the configured
+    /// name servers when it is not cached. If the name servers cannot be reached, a
+    /// stale answer is served instead when the cache still holds one (RFC 8767).
+    pub async fn query(&self, query: Query) -> Result<Message, NauticDnsError> {
+        if !query.name().is_valid() {
+            return Err(NauticDnsError::InvalidTarget(query.name().to_string()));
+        }
+
+        let Some(cache) = &self.cache else {
+            return self
+                .in_flight
+                .run(&query, || self.upstreams.query(&query))
+                .await;
+        };
+
+        let cached = {
+            let mut cache = cache.lock().unwrap();
+            cache
+                .get(&query)
+                .map(|response| (response, cache.take_prefetch(&query)))
+        };
+
+        if let Some((response, prefetch)) = cached {
+            if prefetch {
+                self.prefetch(query);
+            }
+
+            return Ok(response);
+        }
+
+        // Concurrent misses for the same question share one upstream query, which
+        // keeps an expiring popular entry from sending a burst of identical queries
+        self.in_flight
+            .run(&query, || async {
+                match self.upstreams.query(&query).await {
+                    Ok(response) if response.response_code() == &ResponseCode::ServerFailure => {
+                        let stale = cache.lock().unwrap().get_stale(&query);
+                        Ok(stale.unwrap_or(response))
+                    }
+                    Ok(response) => {
+                        cache.lock().unwrap().insert(&query, &response);
+                        Ok(response)
+                    }
+                    Err(error) => cache.lock().unwrap().get_stale(&query).ok_or(error),
+                }
+            })
+            .await
+    }
+
+    /// Refreshes the cached answer for `query` in the background.
+    fn prefetch(&self, query: Query) {
+        let (Some(cache), upstreams) = (self.cache.clone(), self.upstreams.clone()) else {
+            return;
+        };
+
+        tokio::spawn(async move {
+            let response = upstreams.query(&query).await;
+
+            // A failed refresh leaves the old entry in place, which must stay
+            // eligible for the next attempt
+            let mut cache = cache.lock().unwrap();
+            if let Ok(response) = response {
+                cache.insert(&query, &response);
+            }
+            cache.end_prefetch(&query);
+        });
+    }
+}
+
+/// Builds the request for `query`, optionally with its name in random case.
+pub(crate) fn request(query: &Query, recursion_desired: bool, randomise_case: bool) -> Message {
+    let query = match randomise_case {
+        true => Query::new(
+            query.name().randomise_case(),
+            *query.r#type(),
+            *query.class(),
+        ),
+        false => query.clone(),
+    };
+
+    Message::query(query, recursion_desired)
+}
+
+/// Builds a response to `query` that did not come straight from an upstream, such as
+/// a cached or recursively resolved answer.
+pub(crate) fn synthesize_response(
+    query: &Query,
+    response_code: ResponseCode,
+    answers: Vec<Record>,
+    authorities: Vec<Record>,
+) -> Message {
+    let flags = FlagsBuilder::default()
+        .message_type(MessageType::Response)
+        .recursion_desired(true)
+        .recursion_available(true)
+        .response(response_code)
+        .build()
+        .expect("Flags have defaults for every field");
+
+    let header = HeaderBuilder::default()
+        .flags(flags)
+        .questions_size(1)
+        .answers_size(answers.len() as u16)
+        .name_servers_size(authorities.len() as u16)
+        .build()
+        .expect("Header has defaults for every field");
+
+    MessageBuilder::default()
+        .header(header)
+        .questions(vec![query.clone()])
+        .answers(answers)
+        .authorities(authorities)
+        .build()
+        .expect("Message has every field set")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use bytes::Bytes;
+    use tokio::{
+        net::{TcpListener, UdpSocket},
+        task::JoinHandle,
+        time,
+    };
+
+    use super::*;
+    use crate::{
+        protocol::ByteScanner,
+        transport::{
+            read_message, write_message, MockReply, MockTransport, TlsConfigBuilder,
+            MAX_UDP_PAYLOAD,
+        },
+    };
+
+    struct Upstream {
+        address: SocketAddr,
+        requests: Arc<AtomicUsize>,
+        task: JoinHandle<()>,
+    }
+
+    impl Upstream {
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Answers queries from `zone` and NXDOMAIN for names it does not cover, counting
+    /// the requests it receives. Names below a DNAME are answered with the DNAME alone,
+    /// leaving the substitution to the resolver.
+    async fn spawn_upstream(zone: HashMap<&'static str, RecordData>) -> Upstream {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        let task = tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_UDP_PAYLOAD];
+            loop {
+                let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let request = Message::try_scan(&buffer[..length], 0).unwrap();
+                let request = request.value();
+                let question = request.question().unwrap().clone();
+
+                let record = |name: &LabelSequence, data: &RecordData| {
+                    Record::new(name.clone(), Class::IN, 60, data.clone())
+                };
+
+                let dname = zone.iter().find_map(|(owner, data)| {
+                    let owner = LabelSequence::new(owner);
+                    match data {
+                        RecordData::DNAME(_)
+                            if question.name().is_subdomain_of(&owner)
+                                && !question.name().eq_ignore_case(&owner) =>
+                        {
+                            Some(record(&owner, data))
+                        }
+                        _ => None,
+                    }
+                });
+
+                let (response_code, answers) =
+                    match zone.get(question.name().to_lowercase().label()) {
+                        Some(data)
+                            if data.r#type() == *question.r#type()
+                                || matches!(data, RecordData::CNAME(_)) =>
+                        {
+                            (ResponseCode::NoError, vec![record(question.name(), data)])
+                        }
+                        Some(_) => (ResponseCode::NoError, vec![]),
+                        None => match dname {
+                            Some(dname) => (ResponseCode::NoError, vec![dname]),
+                            None => (ResponseCode::NoDomain, vec![]),
+                        },
+                    };
+
+                let flags = FlagsBuilder::default()
+                    .message_type(MessageType::Response)
+                    .response(response_code)
+                    .build()
+                    .unwrap();
+
+                let response = MessageBuilder::default()
+                    .header(
+                        HeaderBuilder::default()
+                            .id(request.header().id())
+                            .flags(flags)
+                            .build()
+                            .unwrap(),
+                    )
+                    .questions(vec![question])
+                    .answers(answers)
+                    .build()
+                    .unwrap();
+
+                socket.send_to(&Bytes::from(response), peer).await.unwrap();
+            }
+        });
+
+        Upstream {
+            address,
+            requests,
+            task,
+        }
+    }
+
+    fn resolver(upstream: SocketAddr, search: Vec<String>) -> DnsResolver {
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![upstream])
+            .search(search)
+            .timeout(Duration::from_millis(500))
+            .build()
+            .unwrap();
+
+        DnsResolver::new(config)
+    }
+
+    #[tokio::test]
+    async fn lookup_short_name_uses_search_list() {
+        let upstream = spawn_upstream(HashMap::from([(
+            "redis.cluster.local",
+            RecordData::A(Ipv4Addr::new(10, 0, 0, 7)),
+        )]))
+        .await;
+
+        let resolver = resolver(
+            upstream.address,
+            vec!["svc.cluster.local".into(), "cluster.local".into()],
+        );
+        let response = resolver.lookup("redis", RecordType::A).await.unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::NoError);
+        assert_eq!(response.answers()[0].name(), "redis.cluster.local");
+    }
+
+    #[tokio::test]
+    async fn lookup_unknown_name_returns_last_nxdomain() {
+        let upstream = spawn_upstream(HashMap::new()).await;
+
+        let resolver = resolver(upstream.address, vec!["cluster.local".into()]);
+        let response = resolver.lookup("redis", RecordType::A).await.unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::NoDomain);
+        assert_eq!(response.question().unwrap().name().label(), "redis");
+    }
+
+    #[tokio::test]
+    async fn resolve_url_returns_addresses() {
+        let upstream = spawn_upstream(HashMap::from([(
+            "www.github.com",
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        )]))
+        .await;
+
+        let resolver = resolver(upstream.address, vec![]);
+        let target = Url::parse("https://www.github.com/").unwrap();
+        let addresses = resolver.resolve(&target).await.unwrap();
+
+        assert_eq!(addresses, vec![IpAddr::V4(Ipv4Addr::new(140, 82, 121, 4))]);
+    }
+
+    #[tokio::test]
+    async fn query_is_answered_from_cache() {
+        let upstream = spawn_upstream(HashMap::from([(
+            "www.github.com",
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        )]))
+        .await;
+
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![upstream.address])
+            .cache(CacheConfig::default())
+            .build()
+            .unwrap();
+
+        let resolver = DnsResolver::new(config);
+        for _ in 0..3 {
+            let response = resolver
+                .lookup("www.github.com", RecordType::A)
+                .await
+                .unwrap();
+            assert_eq!(response.answers().len(), 1);
+        }
+
+        assert_eq!(upstream.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn query_serves_stale_answer_when_upstream_is_down() {
+        let upstream = spawn_upstream(HashMap::from([(
+            "www.github.com",
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        )]))
+        .await;
+
+        let cache = CacheConfigBuilder::default()
+            .max_ttl(1)
+            .max_stale_ttl(60)
+            .build()
+            .unwrap();
+
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![upstream.address])
+            .timeout(Duration::from_millis(200))
+            .cache(cache)
+            .build()
+            .unwrap();
+
+        let resolver = DnsResolver::new(config);
+        resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        upstream.task.abort();
+        time::sleep(Duration::from_millis(1100)).await;
+
+        let response = resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 30);
+    }
+
+    #[tokio::test]
+    async fn popular_answer_is_prefetched_before_expiry() {
+        let upstream = spawn_upstream(HashMap::from([(
+            "www.github.com",
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        )]))
+        .await;
+
+        let cache = CacheConfigBuilder::default()
+            .max_ttl(2)
+            .prefetch(true)
+            .prefetch_threshold(50)
+            .prefetch_min_hits(1)
+            .build()
+            .unwrap();
+
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![upstream.address])
+            .cache(cache)
+            .build()
+            .unwrap();
+
+        let resolver = DnsResolver::new(config);
+        resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+        resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+        assert_eq!(upstream.requests(), 1);
+
+        time::sleep(Duration::from_millis(1100)).await;
+        resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(upstream.requests(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_prefetch_is_retried() {
+        let answer = || {
+            MockReply::answer(vec![record(
+                "www.github.com",
+                RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+            )])
+        };
+        let transport = Arc::new(MockTransport::new(vec![
+            answer(),
+            MockReply::Fail,
+            answer(),
+        ]));
+
+        let cache = CacheConfigBuilder::default()
+            .prefetch(true)
+            .prefetch_threshold(50)
+            .prefetch_min_hits(1)
+            .build()
+            .unwrap();
+        let config = ResolverConfigBuilder::default()
+            .attempts(1)
+            .cache(cache)
+            .build()
+            .unwrap();
+        let resolver = DnsResolver::with_transports(config, vec![transport.clone()]);
+
+        let query = Query::new(
+            LabelSequence::new("www.github.com"),
+            RecordType::A,
+            Class::IN,
+        );
+        resolver.query(query.clone()).await.unwrap();
+
+        time::advance(Duration::from_secs(35)).await;
+        for _ in 0..2 {
+            resolver.query(query.clone()).await.unwrap();
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(transport.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn lookup_chain_follows_aliases_across_queries() {
+        let upstream = spawn_upstream(HashMap::from([
+            (
+                "www.github.com",
+                RecordData::CNAME(LabelSequence::new("www.old.example")),
+            ),
+            (
+                "old.example",
+                RecordData::DNAME(LabelSequence::new("new.example")),
+            ),
+            (
+                "www.new.example",
+                RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+            ),
+        ]))
+        .await;
+
+        let resolver = resolver(upstream.address, vec![]);
+        let lookup = resolver
+            .lookup_chain("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(lookup.chain().len(), 2);
+        assert_eq!(lookup.chain()[1].r#type(), &RecordType::DNAME);
+        assert_eq!(lookup.canonical_name().label(), "www.new.example");
+        assert_eq!(
+            lookup.records()[0].data(),
+            &RecordData::A(Ipv4Addr::new(140, 82, 121, 4))
+        );
+        assert_eq!(upstream.requests(), 3);
+    }
+
+    #[tokio::test]
+    async fn lookup_chain_detects_loop_across_queries() {
+        let upstream = spawn_upstream(HashMap::from([
+            (
+                "a.example",
+                RecordData::CNAME(LabelSequence::new("b.example")),
+            ),
+            (
+                "b.example",
+                RecordData::CNAME(LabelSequence::new("a.example")),
+            ),
+        ]))
+        .await;
+
+        let resolver = resolver(upstream.address, vec![]);
+        let lookup = resolver.lookup_chain("a.example", RecordType::A).await;
+
+        assert!(matches!(lookup, Err(NauticDnsError::AliasLoop(_))));
+    }
+
+    /// Answers every query with the responses built by `respond`, in order.
+    async fn spawn_responder<F>(respond: F) -> SocketAddr
+    where
+        F: Fn(&Message) -> Vec<Message> + Send + 'static,
+    {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_UDP_PAYLOAD];
+            loop {
+                let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let request = Message::try_scan(&buffer[..length], 0).unwrap();
+                for response in respond(request.value()) {
+                    socket.send_to(&Bytes::from(response), peer).await.unwrap();
+                }
+            }
+        });
+
+        address
+    }
+
+    fn answer(id: u16, question: Query, address: Ipv4Addr) -> Message {
+        let flags = FlagsBuilder::default()
+            .message_type(MessageType::Response)
+            .build()
+            .unwrap();
+
+        let record = Record::new(
+            question.name().clone(),
+            Class::IN,
+            60,
+            RecordData::A(address),
+        );
+
+        MessageBuilder::default()
+            .header(
+                HeaderBuilder::default()
+                    .id(id)
+                    .flags(flags)
+                    .build()
+                    .unwrap(),
+            )
+            .questions(vec![question])
+            .answers(vec![record])
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn query_discards_responses_with_wrong_id_or_question() {
+        let address = spawn_responder(|request| {
+            let id = request.header().id();
+            let question = request.question().unwrap().clone();
+            let forged = Query::new(
+                LabelSequence::new("evil.example"),
+                *question.r#type(),
+                *question.class(),
+            );
+
+            vec![
+                answer(id.wrapping_add(1), question, Ipv4Addr::new(6, 6, 6, 6)),
+                answer(id, forged, Ipv4Addr::new(6, 6, 6, 6)),
+                answer(
+                    id,
+                    request.question().unwrap().clone(),
+                    Ipv4Addr::new(140, 82, 121, 4),
+                ),
+            ]
+        })
+        .await;
+
+        let resolver = resolver(address, vec![]);
+        let response = resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.answers()[0].data(),
+            &RecordData::A(Ipv4Addr::new(140, 82, 121, 4))
+        );
+    }
+
+    #[tokio::test]
+    async fn query_with_randomised_case_rejects_normalised_question() {
+        let address = spawn_responder(|request| {
+            let question = request.question().unwrap();
+            let lowered = Query::new(
+                question.name().to_lowercase(),
+                *question.r#type(),
+                *question.class(),
+            );
+
+            vec![answer(
+                request.header().id(),
+                lowered,
+                Ipv4Addr::new(140, 82, 121, 4),
+            )]
+        })
+        .await;
+
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![address])
+            .timeout(Duration::from_millis(200))
+            .attempts(1)
+            .case_randomisation(true)
+            .build()
+            .unwrap();
+
+        let resolver = DnsResolver::new(config);
+        let response = resolver
+            .lookup("randomised.letters.everywhere.github.com", RecordType::A)
+            .await;
+
+        assert!(matches!(response, Err(NauticDnsError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn query_with_randomised_case_accepts_exact_echo() {
+        let upstream = spawn_upstream(HashMap::from([(
+            "www.github.com",
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        )]))
+        .await;
+
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![upstream.address])
+            .case_randomisation(true)
+            .build()
+            .unwrap();
+
+        let resolver = DnsResolver::new(config);
+        let response = resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn multiplexed_queries_are_answered() {
+        let upstream = spawn_upstream(HashMap::from([
+            ("a.github.com", RecordData::A(Ipv4Addr::new(10, 0, 0, 1))),
+            ("b.github.com", RecordData::A(Ipv4Addr::new(10, 0, 0, 2))),
+            ("c.github.com", RecordData::A(Ipv4Addr::new(10, 0, 0, 3))),
+        ]))
+        .await;
+
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![upstream.address])
+            .multiplex(true)
+            .build()
+            .unwrap();
+
+        let resolver = Arc::new(DnsResolver::new(config));
+        let lookups = ["a", "b", "c"]
+            .map(|label| {
+                let resolver = resolver.clone();
+                tokio::spawn(async move {
+                    resolver
+                        .lookup(&format!("{label}.github.com"), RecordType::A)
+                        .await
+                })
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        for (lookup, last_octet) in lookups.into_iter().zip(1..) {
+            let response = lookup.await.unwrap().unwrap();
+            assert_eq!(
+                response.answers()[0].data(),
+                &RecordData::A(Ipv4Addr::new(10, 0, 0, last_octet))
+            );
+        }
+
+        assert_eq!(upstream.requests(), 3);
+    }
+
+    /// Answers every query received over TCP on `listener` with `address`.
+    fn spawn_tcp_upstream(listener: TcpListener, address: Ipv4Addr) {
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    while let Ok(Some(request)) = read_message(&mut stream).await {
+                        let question = request.question().unwrap().clone();
+                        let response = answer(request.header().id(), question, address);
+                        write_message(&mut stream, &response).await.unwrap();
+                    }
+                });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn query_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        spawn_tcp_upstream(listener, Ipv4Addr::new(140, 82, 121, 4));
+
+        let config = ResolverConfigBuilder::default()
+            .name_servers(vec![address])
+            .protocol(Protocol::Tcp)
+            .build()
+            .unwrap();
+
+        let resolver = DnsResolver::new(config);
+        let response = resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn truncated_response_is_retried_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        spawn_tcp_upstream(listener, Ipv4Addr::new(140, 82, 121, 4));
+
+        let socket = UdpSocket::bind(address).await.unwrap();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_UDP_PAYLOAD];
+            loop {
+                let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let request = Message::try_scan(&buffer[..length], 0).unwrap();
+                let request = request.value();
+
+                let flags = FlagsBuilder::default()
+                    .message_type(MessageType::Response)
+                    .truncation(true)
+                    .build()
+                    .unwrap();
+
+                let response = MessageBuilder::default()
+                    .header(
+                        HeaderBuilder::default()
+                            .id(request.header().id())
+                            .flags(flags)
+                            .build()
+                            .unwrap(),
+                    )
+                    .questions(request.questions().to_vec())
+                    .build()
+                    .unwrap();
+
+                socket.send_to(&Bytes::from(response), peer).await.unwrap();
+            }
+        });
+
+        let resolver = resolver(address, vec![]);
+        let response = resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        assert!(!response.header().flags().truncation());
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_send_one_query() {
+        let upstream = spawn_upstream(HashMap::from([(
+            "www.github.com",
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        )]))
+        .await;
+
+        let resolver = Arc::new(resolver(upstream.address, vec![]));
+        let lookups = (0..10)
+            .map(|_| {
+                let resolver = resolver.clone();
+                tokio::spawn(async move { resolver.lookup("www.github.com", RecordType::A).await })
+            })
+            .collect::<Vec<_>>();
+
+        for lookup in lookups {
+            let response = lookup.await.unwrap().unwrap();
+            assert_eq!(response.answers().len(), 1);
+        }
+
+        assert_eq!(upstream.requests(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_query_fails_over_to_next_transport() {
+        let record = Record::new(
+            LabelSequence::new("www.github.com"),
+            Class::IN,
+            60,
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        );
+        let silent = Arc::new(MockTransport::new(vec![MockReply::Drop]));
+        let answering = Arc::new(MockTransport::new(vec![MockReply::answer(vec![record])]));
+
+        let config = ResolverConfigBuilder::default()
+            .timeout(Duration::from_secs(2))
+            .build()
+            .unwrap();
+        let resolver = DnsResolver::with_transports(config, vec![silent.clone(), answering]);
+
+        let started = time::Instant::now();
+        let response = resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(silent.requests().len(), 1);
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_transport_times_out_every_attempt() {
+        let slow = Arc::new(
+            MockTransport::new(vec![])
+                .with_fallback(MockReply::answer(vec![]).after(Duration::from_secs(10))),
+        );
+
+        let config = ResolverConfigBuilder::default()
+            .timeout(Duration::from_secs(1))
+            .attempts(3)
+            .build()
+            .unwrap();
+        let resolver = DnsResolver::with_transports(config, vec![slow.clone()]);
+
+        let result = resolver.lookup("www.github.com", RecordType::A).await;
+
+        assert!(matches!(result, Err(NauticDnsError::Timeout)));
+        assert_eq!(slow.requests().len(), 3);
+    }
+
+    fn record(name: &str, data: RecordData) -> Record {
+        Record::new(LabelSequence::new(name), Class::IN, 60, data)
+    }
+
+    #[tokio::test]
+    async fn reverse_lookup_queries_pointer_name() {
+        let address = IpAddr::V6("2001:db8::25".parse().unwrap());
+        let pointer = LabelSequence::reverse_pointer(address);
+        let transport = Arc::new(MockTransport::new(vec![MockReply::answer(vec![record(
+            pointer.label(),
+            RecordData::PTR(LabelSequence::new("mail.example")),
+        )])]));
+
+        let config = ResolverConfigBuilder::default().build().unwrap();
+        let resolver = DnsResolver::with_transports(config, vec![transport.clone()]);
+
+        let names = resolver.reverse_lookup(address).await.unwrap();
+
+        assert_eq!(names, vec![LabelSequence::new("mail.example")]);
+        let question = transport.requests()[0].question().unwrap().clone();
+        assert!(question.name().eq_ignore_case(&pointer));
+        assert_eq!(question.r#type(), &RecordType::PTR);
+    }
+
+    #[tokio::test]
+    async fn forward_confirmed_names_keep_matching_names_only() {
+        let address = Ipv4Addr::new(192, 0, 2, 25);
+        let pointer = LabelSequence::reverse_pointer(IpAddr::V4(address));
+        let transport = Arc::new(MockTransport::new(vec![
+            MockReply::answer(vec![
+                record(
+                    pointer.label(),
+                    RecordData::PTR(LabelSequence::new("mail.example")),
+                ),
+                record(
+                    pointer.label(),
+                    RecordData::PTR(LabelSequence::new("spoofed.example")),
+                ),
+            ]),
+            MockReply::answer(vec![record("mail.example", RecordData::A(address))]),
+            MockReply::answer(vec![record(
+                "spoofed.example",
+                RecordData::A(Ipv4Addr::new(198, 51, 100, 1)),
+            )]),
+        ]));
+
+        let config = ResolverConfigBuilder::default().build().unwrap();
+        let resolver = DnsResolver::with_transports(config, vec![transport]);
+
+        let names = resolver
+            .forward_confirmed_names(IpAddr::V4(address))
+            .await
+            .unwrap();
+
+        assert_eq!(names, vec![LabelSequence::new("mail.example")]);
+    }
+
+    #[tokio::test]
+    async fn connect_tcp_races_resolved_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Nothing listens on the IPv6 loopback, so only the IPv4 attempt connects
+        let transport = Arc::new(MockTransport::new(vec![
+            MockReply::answer(vec![record(
+                "app.example",
+                RecordData::AAAA(Ipv6Addr::LOCALHOST),
+            )]),
+            MockReply::answer(vec![record(
+                "app.example",
+                RecordData::A(Ipv4Addr::LOCALHOST),
+            )]),
+        ]));
+
+        let config = ResolverConfigBuilder::default().build().unwrap();
+        let resolver = DnsResolver::with_transports(config, vec![transport.clone()]);
+
+        let stream = resolver.connect_tcp("app.example", port).await.unwrap();
+
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+        let types = transport
+            .requests()
+            .iter()
+            .map(|request| *request.question().unwrap().r#type())
+            .collect::<Vec<_>>();
+        assert_eq!(types, vec![RecordType::AAAA, RecordType::A]);
+    }
+
+    #[tokio::test]
+    async fn upstreams_from_urls_use_their_own_protocol() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp = listener.local_addr().unwrap();
+        spawn_tcp_upstream(listener, Ipv4Addr::new(140, 82, 121, 4));
+
+        // Nothing listens on UDP, so only the TCP upstream can answer
+        let config = ResolverConfigBuilder::default()
+            .upstreams(vec![
+                "udp://127.0.0.1:1".parse().unwrap(),
+                format!("tcp://{tcp}").parse().unwrap(),
+            ])
+            .timeout(Duration::from_millis(500))
+            .attempts(1)
+            .build()
+            .unwrap();
+        let resolver = DnsResolver::new(config);
+
+        let response = resolver
+            .lookup("www.github.com", RecordType::A)
+            .await
+            .unwrap();
+
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[test]
+    fn recursion_is_rejected_with_encrypted_upstream() {
+        let config = ResolverConfigBuilder::default()
+            .upstreams(vec!["tls://dns.example?addr=192.0.2.53".parse().unwrap()])
+            .recursion(RecursorConfig::default())
+            .build();
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn tls_config_is_required_for_tls() {
+        let config = ResolverConfigBuilder::default()
+            .protocol(Protocol::Tls)
+            .build();
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn recursion_is_rejected_with_tls() {
+        let tls = TlsConfigBuilder::default()
+            .server_name("dns.example")
+            .build()
+            .unwrap();
+
+        let config = ResolverConfigBuilder::default()
+            .protocol(Protocol::Tls)
+            .tls(tls)
+            .recursion(RecursorConfig::default())
+            .build();
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn plaintext_https_endpoint_is_rejected() {
+        let config = ResolverConfigBuilder::default()
+            .protocol(Protocol::Https)
+            .https_endpoints(vec![Url::parse("http://dns.example/dns-query").unwrap()])
+            .build();
+
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn https_endpoint_needs_address_for_named_host() {
+        let config = |url: &str| {
+            ResolverConfigBuilder::default()
+                .protocol(Protocol::Https)
+                .https_endpoints(vec![Url::parse(url).unwrap()])
+                .build()
+        };
+
+        assert!(config("https://dns.example/dns-query").is_err());
+        assert!(config("https://dns.example/dns-query#addr=192.0.2.53").is_ok());
+        assert!(config("https://[2001:db8::53]/dns-query").is_ok());
+    }
+
+    #[test]
+    fn named_upstreams_need_encrypted_bootstrap() {
+        let config = |bootstrap: &[&str]| {
+            ResolverConfigBuilder::default()
+                .upstreams(vec!["https://dns.example/dns-query".parse().unwrap()])
+                .bootstrap(bootstrap.iter().map(|spec| spec.parse().unwrap()).collect())
+                .build()
+        };
+
+        assert!(config(&[]).is_err());
+        assert!(config(&["udp://9.9.9.9"]).is_err());
+        assert!(config(&["tls://9.9.9.9", "https://1.1.1.1/dns-query"]).is_ok());
+    }
+}
@@ -0,0 +1,1031 @@
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::Mutex,
+    time::Duration,
+};
+
+use derive_builder::Builder;
+use lru::LruCache;
+use rand::seq::SliceRandom;
+use tokio::time::{self, Instant};
+
+use super::{chain::AliasChain, request};
+use crate::{
+    errors::NauticDnsError,
+    protocol::{Class, LabelSequence, Message, Query, RecordData, RecordType, ResponseCode},
+    transport::{exchange, MultiplexedStream},
+};
+
+type ResolveFuture<'a> = Pin<Box<dyn Future<Output = Result<Message, NauticDnsError>> + Send + 'a>>;
+
+/// IPv4 addresses of the root servers, a.root-servers.net through m.root-servers.net.
+pub const ROOT_HINTS: [Ipv4Addr; 13] = [
+    Ipv4Addr::new(198, 41, 0, 4),
+    Ipv4Addr::new(170, 247, 170, 2),
+    Ipv4Addr::new(192, 33, 4, 12),
+    Ipv4Addr::new(199, 7, 91, 13),
+    Ipv4Addr::new(192, 203, 230, 10),
+    Ipv4Addr::new(192, 5, 5, 241),
+    Ipv4Addr::new(192, 112, 36, 4),
+    Ipv4Addr::new(198, 97, 190, 53),
+    Ipv4Addr::new(192, 36, 148, 17),
+    Ipv4Addr::new(192, 58, 128, 30),
+    Ipv4Addr::new(193, 0, 14, 129),
+    Ipv4Addr::new(199, 7, 83, 42),
+    Ipv4Addr::new(202, 12, 27, 33),
+];
+
+#[derive(Debug, Clone, Builder)]
+pub struct RecursorConfig {
+    #[builder(default = "ROOT_HINTS.iter().copied().map(IpAddr::V4).collect()")]
+    root_hints: Vec<IpAddr>,
+
+    /// Port used to reach every authoritative server, including the root hints.
+    #[builder(default = "53")]
+    port: u16,
+
+    /// Time to wait for each authoritative server before trying the next one.
+    #[builder(default = "Duration::from_secs(2)")]
+    timeout: Duration,
+
+    /// Referrals followed for a single name before giving up.
+    #[builder(default = "30")]
+    max_referrals: u8,
+
+    /// How deeply name server names without glue may be resolved recursively.
+    #[builder(default = "6")]
+    max_depth: u8,
+
+    #[builder(default = "8")]
+    max_cname_chain: u8,
+
+    /// Only send each zone the labels it needs to see (RFC 9156).
+    #[builder(default = "true")]
+    qname_minimisation: bool,
+
+    /// Send queries with the letters of the name in random case and only accept
+    /// answers that echo it exactly.
+    #[builder(default)]
+    case_randomisation: bool,
+
+    /// Zone cuts remembered between queries, so that resolution can start from
+    /// the closest known one instead of the root. The least recently used are
+    /// evicted first.
+    #[builder(default = "1_000")]
+    max_delegations: usize,
+}
+
+impl RecursorConfig {
+    pub fn root_hints(&self) -> &[IpAddr] {
+        &self.root_hints
+    }
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    pub fn max_referrals(&self) -> u8 {
+        self.max_referrals
+    }
+    pub fn max_depth(&self) -> u8 {
+        self.max_depth
+    }
+    pub fn max_cname_chain(&self) -> u8 {
+        self.max_cname_chain
+    }
+    pub fn qname_minimisation(&self) -> bool {
+        self.qname_minimisation
+    }
+    pub fn case_randomisation(&self) -> bool {
+        self.case_randomisation
+    }
+    pub fn max_delegations(&self) -> usize {
+        self.max_delegations
+    }
+}
+
+impl Default for RecursorConfig {
+    fn default() -> Self {
+        RecursorConfigBuilder::default()
+            .build()
+            .expect("Recursor config has defaults for every field")
+    }
+}
+
+/// Servers for the closest zone cut known so far.
+#[derive(Clone)]
+struct Delegation {
+    zone: LabelSequence,
+    names: Vec<LabelSequence>,
+    servers: Vec<SocketAddr>,
+    /// Lowest TTL of the NS records making up the delegation.
+    ttl: u32,
+}
+
+/// A delegation learnt from a referral, valid until its NS records expire.
+struct CachedDelegation {
+    delegation: Delegation,
+    expires: Instant,
+}
+
+/// What an authoritative server told us about the name being resolved.
+enum Step {
+    Answer(Message),
+    Referral(Delegation),
+}
+
+/// Iterative resolver that walks the delegation tree starting from the root hints,
+/// or from the closest zone cut it learnt from an earlier referral.
+pub struct Recursor {
+    config: RecursorConfig,
+    delegations: Mutex<LruCache<LabelSequence, CachedDelegation>>,
+}
+
+impl Recursor {
+    pub fn new(config: RecursorConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.max_delegations()).unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            config,
+            delegations: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn config(&self) -> &RecursorConfig {
+        &self.config
+    }
+
+    /// Resolves `query` starting at the closest known zone cut, following referrals
+    /// and CNAME chains.
+    pub async fn resolve(&self, query: &Query) -> Result<Message, NauticDnsError> {
+        self.resolve_at_depth(query.clone(), 0).await
+    }
+
+    fn resolve_at_depth(&self, query: Query, depth: u8) -> ResolveFuture<'_> {
+        Box::pin(async move {
+            if depth > self.config.max_depth() {
+                return Err(NauticDnsError::RecursionLimitExceeded(
+                    query.name().to_string(),
+                ));
+            }
+
+            let mut chain = AliasChain::new(&query, self.config.max_cname_chain());
+
+            loop {
+                let current = Query::new(chain.name().clone(), *query.r#type(), *query.class());
+                let response = self.resolve_name(&current, depth).await?;
+
+                let moved = chain.follow(response.answers())?;
+
+                // Keep chasing the chain only when this server could not finish it
+                if !chain.is_answered(response.answers())
+                    && moved
+                    && response.response_code() == &ResponseCode::NoError
+                {
+                    continue;
+                }
+
+                return Ok(chain.finish(&response).into_response());
+            }
+        })
+    }
+
+    /// Walks from the closest known zone cut down to the servers authoritative for
+    /// `query` and returns their final answer, without following CNAMEs. Zone cuts
+    /// found on the way are remembered for later queries.
+    ///
+    /// With QNAME minimisation (RFC 9156) each zone is only shown the query name up
+    /// to one label below its apex, asking for A records, until the zone holding the
+    /// full name is found. Servers that answer such queries with NXDOMAIN or errors
+    /// make the rest of the walk fall back to the full query name.
+    async fn resolve_name(&self, query: &Query, depth: u8) -> Result<Message, NauticDnsError> {
+        let mut delegation = self.closest_delegation(query.name());
+
+        let total_labels = query.name().label_count();
+        let mut minimise = self.config.qname_minimisation();
+        let mut revealed = 0;
+        let mut referrals = 0;
+
+        loop {
+            revealed = revealed.max(delegation.zone.label_count() + 1);
+
+            let step = if minimise && revealed < total_labels {
+                let minimised =
+                    Query::new(query.name().suffix(revealed), RecordType::A, *query.class());
+
+                match self.ask(&delegation, &minimised).await {
+                    Ok(Step::Referral(referral)) => Step::Referral(referral),
+                    Ok(Step::Answer(response))
+                        if response.response_code() == &ResponseCode::NoError =>
+                    {
+                        // Not a zone cut, so reveal one more label to the same servers
+                        revealed += 1;
+                        continue;
+                    }
+                    _ => {
+                        minimise = false;
+                        continue;
+                    }
+                }
+            } else {
+                match self.ask(&delegation, query).await {
+                    Ok(step) => step,
+                    Err(error) => {
+                        // Its servers may have moved, so walk down again next time
+                        self.forget(&delegation.zone);
+                        return Err(error);
+                    }
+                }
+            };
+
+            match step {
+                Step::Answer(response) => return Ok(response),
+                Step::Referral(mut referral) => {
+                    referrals += 1;
+                    if referrals > self.config.max_referrals() {
+                        return Err(NauticDnsError::RecursionLimitExceeded(
+                            query.name().to_string(),
+                        ));
+                    }
+
+                    if referral.servers.is_empty() {
+                        referral.servers = self.resolve_servers(&referral, depth).await?;
+                    }
+
+                    self.remember(&referral);
+                    delegation = referral;
+                }
+            }
+        }
+    }
+
+    /// The cached delegation for the closest enclosing zone of `name`, or the root
+    /// hints when none is cached.
+    fn closest_delegation(&self, name: &LabelSequence) -> Delegation {
+        let now = Instant::now();
+        let mut delegations = self.delegations.lock().unwrap();
+
+        for count in (1..=name.label_count()).rev() {
+            let zone = name.suffix(count).to_lowercase();
+            match delegations.get(&zone) {
+                Some(cached) if cached.expires > now => return cached.delegation.clone(),
+                Some(_) => {
+                    delegations.pop(&zone);
+                }
+                None => {}
+            }
+        }
+
+        let port = self.config.port();
+        Delegation {
+            zone: LabelSequence::root(),
+            names: vec![],
+            servers: self
+                .config
+                .root_hints()
+                .iter()
+                .map(|address| SocketAddr::new(*address, port))
+                .collect(),
+            ttl: 0,
+        }
+    }
+
+    fn remember(&self, delegation: &Delegation) {
+        let cached = CachedDelegation {
+            delegation: delegation.clone(),
+            expires: Instant::now() + Duration::from_secs(u64::from(delegation.ttl)),
+        };
+
+        self.delegations
+            .lock()
+            .unwrap()
+            .put(delegation.zone.to_lowercase(), cached);
+    }
+
+    fn forget(&self, zone: &LabelSequence) {
+        self.delegations.lock().unwrap().pop(&zone.to_lowercase());
+    }
+
+    /// Queries the servers of `delegation` in random order until one of them gives
+    /// either an answer or a referral to a zone closer to the queried name.
+    async fn ask(&self, delegation: &Delegation, query: &Query) -> Result<Step, NauticDnsError> {
+        let mut servers = delegation.servers.clone();
+        servers.shuffle(&mut rand::thread_rng());
+
+        for server in servers {
+            let request = request(query, false, self.config.case_randomisation());
+            let Ok(response) = self.exchange(server, &request).await else {
+                continue;
+            };
+
+            match response.response_code() {
+                ResponseCode::NoError | ResponseCode::NoDomain => {}
+                _ => continue,
+            }
+
+            if let Some(referral) = self.referral(&delegation.zone, query, &response) {
+                return Ok(Step::Referral(referral));
+            }
+
+            if is_lame(&response) {
+                continue;
+            }
+
+            return Ok(Step::Answer(response));
+        }
+
+        Err(NauticDnsError::NoUsableNameServers(
+            delegation.zone.to_string(),
+        ))
+    }
+
+    /// Sends `request` to `server` over UDP, and once more over TCP when the
+    /// response comes back truncated (RFC 7766 section 5).
+    async fn exchange(
+        &self,
+        server: SocketAddr,
+        request: &Message,
+    ) -> Result<Message, NauticDnsError> {
+        let timeout = self.config.timeout();
+        let response = time::timeout(timeout, exchange(server, request))
+            .await
+            .map_err(|_| NauticDnsError::Timeout)??;
+
+        if !response.header().flags().truncation() {
+            return Ok(response);
+        }
+
+        let over_tcp = async {
+            MultiplexedStream::connect_tcp(server, None)
+                .await?
+                .exchange(request)
+                .await
+        };
+
+        time::timeout(timeout, over_tcp)
+            .await
+            .map_err(|_| NauticDnsError::Timeout)?
+    }
+
+    /// Extracts a referral from `response`. Only delegations strictly below `zone`
+    /// and above the queried name are followed, which rules out referral loops, and
+    /// glue is only trusted when it falls within `zone`.
+    fn referral(
+        &self,
+        zone: &LabelSequence,
+        query: &Query,
+        response: &Message,
+    ) -> Option<Delegation> {
+        if !response.answers().is_empty() || response.response_code() != &ResponseCode::NoError {
+            return None;
+        }
+
+        let name_servers = response
+            .authorities()
+            .iter()
+            .filter_map(|record| match record.data() {
+                RecordData::NS(name) => Some((&record.name, name)),
+                _ => None,
+            })
+            .filter(|(owner, _)| {
+                query.name().is_subdomain_of(owner)
+                    && owner.is_subdomain_of(zone)
+                    && !owner.eq_ignore_case(zone)
+            })
+            .collect::<Vec<_>>();
+
+        let child = name_servers.first()?.0.clone();
+        let port = self.config.port();
+
+        let names = name_servers
+            .iter()
+            .filter(|(owner, _)| owner.eq_ignore_case(&child))
+            .map(|(_, name)| (*name).clone())
+            .collect::<Vec<_>>();
+
+        let ttl = response
+            .authorities()
+            .iter()
+            .filter(|record| {
+                record.r#type() == &RecordType::NS && record.name.eq_ignore_case(&child)
+            })
+            .map(|record| record.ttl())
+            .min()
+            .unwrap_or(0);
+
+        let servers = names
+            .iter()
+            .filter(|name| name.is_subdomain_of(zone))
+            .flat_map(|name| {
+                response
+                    .additionals()
+                    .iter()
+                    .filter(move |record| record.name.eq_ignore_case(name))
+                    .filter_map(|record| match record.data() {
+                        RecordData::A(address) => Some(IpAddr::V4(*address)),
+                        RecordData::AAAA(address) => Some(IpAddr::V6(*address)),
+                        _ => None,
+                    })
+            })
+            .map(|address| SocketAddr::new(address, port))
+            .collect();
+
+        Some(Delegation {
+            zone: child,
+            names,
+            servers,
+            ttl,
+        })
+    }
+
+    /// Resolves the addresses of name servers that came without usable glue.
+    async fn resolve_servers(
+        &self,
+        delegation: &Delegation,
+        depth: u8,
+    ) -> Result<Vec<SocketAddr>, NauticDnsError> {
+        let port = self.config.port();
+
+        for name in &delegation.names {
+            // A server named inside the zone it serves cannot be found without glue
+            if name.is_subdomain_of(&delegation.zone) {
+                continue;
+            }
+
+            let query = Query::new(name.clone(), RecordType::A, Class::IN);
+            let Ok(response) = self.resolve_at_depth(query, depth + 1).await else {
+                continue;
+            };
+
+            let servers = response
+                .answers()
+                .iter()
+                .filter_map(|record| match record.data() {
+                    RecordData::A(address) => Some(SocketAddr::new(IpAddr::V4(*address), port)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            if !servers.is_empty() {
+                return Ok(servers);
+            }
+        }
+
+        Err(NauticDnsError::NoUsableNameServers(
+            delegation.zone.to_string(),
+        ))
+    }
+}
+
+/// An empty answer that is neither a referral we can follow nor an authoritative
+/// negative answer, e.g. a server referring us back up the tree.
+fn is_lame(response: &Message) -> bool {
+    response.answers().is_empty()
+        && response.response_code() == &ResponseCode::NoError
+        && !response.header().flags().authoritative_answer()
+        && !response
+            .authorities()
+            .iter()
+            .any(|record| record.r#type() == &RecordType::SOA)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+    use tokio::net::{TcpListener, UdpSocket};
+
+    use super::*;
+    use crate::{
+        protocol::{
+            ByteScanner, FlagsBuilder, HeaderBuilder, MessageBuilder, MessageType, Record,
+            StartOfAuthority,
+        },
+        transport::{read_message, write_message},
+    };
+
+    /// Minimal authoritative server data for one zone.
+    struct Authority {
+        origin: &'static str,
+        records: Vec<Record>,
+        delegations: Vec<(&'static str, &'static str, Option<Ipv4Addr>)>,
+    }
+
+    fn record(name: &str, data: RecordData) -> Record {
+        Record::new(LabelSequence::new(name), Class::IN, 300, data)
+    }
+
+    fn name(value: &str) -> LabelSequence {
+        LabelSequence::new(value)
+    }
+
+    impl Authority {
+        fn answer(&self, request: &Message) -> Message {
+            let question = request.question().unwrap().clone();
+            let qname = question.name();
+
+            let soa = record(
+                self.origin,
+                RecordData::SOA(StartOfAuthority {
+                    mname: name(&format!("ns.{}", self.origin)),
+                    rname: name(&format!("hostmaster.{}", self.origin)),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 600,
+                    expire: 86400,
+                    minimum: 60,
+                }),
+            );
+
+            let mut answers = vec![];
+            let mut authorities = vec![];
+            let mut additionals = vec![];
+            let mut response_code = ResponseCode::NoError;
+            let mut authoritative = true;
+
+            let delegation = self
+                .delegations
+                .iter()
+                .find(|(zone, _, _)| qname.is_subdomain_of(&name(zone)));
+
+            if let Some((zone, _, _)) = delegation {
+                authoritative = false;
+                for (_, server, glue) in self.delegations.iter().filter(|(z, _, _)| z == zone) {
+                    authorities.push(record(zone, RecordData::NS(name(server))));
+                    if let Some(glue) = glue {
+                        additionals.push(record(server, RecordData::A(*glue)));
+                    }
+                }
+            } else {
+                let mut owner = qname.clone();
+                for _ in 0..8 {
+                    let matches = self
+                        .records
+                        .iter()
+                        .filter(|record| record.name.eq_ignore_case(&owner))
+                        .collect::<Vec<_>>();
+
+                    let cname = matches.iter().find_map(|record| match record.data() {
+                        RecordData::CNAME(target) if question.r#type() != &RecordType::CNAME => {
+                            Some(target.clone())
+                        }
+                        _ => None,
+                    });
+
+                    answers.extend(
+                        matches
+                            .iter()
+                            .filter(|record| {
+                                record.r#type() == question.r#type()
+                                    || record.r#type() == &RecordType::CNAME
+                            })
+                            .map(|record| (*record).clone()),
+                    );
+
+                    if matches.is_empty() && answers.is_empty() {
+                        response_code = ResponseCode::NoDomain;
+                    }
+
+                    match cname {
+                        Some(target) if target.is_subdomain_of(&name(self.origin)) => {
+                            owner = target
+                        }
+                        _ => break,
+                    }
+                }
+
+                if answers.is_empty() {
+                    authorities.push(soa);
+                }
+            }
+
+            let flags = FlagsBuilder::default()
+                .message_type(MessageType::Response)
+                .authoritative_answer(authoritative)
+                .response(response_code)
+                .build()
+                .unwrap();
+
+            MessageBuilder::default()
+                .header(
+                    HeaderBuilder::default()
+                        .id(request.header().id())
+                        .flags(flags)
+                        .build()
+                        .unwrap(),
+                )
+                .questions(vec![question])
+                .answers(answers)
+                .authorities(authorities)
+                .additionals(additionals)
+                .build()
+                .unwrap()
+        }
+    }
+
+    type QueryLog = Arc<Mutex<Vec<String>>>;
+
+    /// Serves `authority` on `address`, logging every query name it is asked for.
+    async fn spawn_authority(address: SocketAddr, authority: Authority) -> QueryLog {
+        let socket = UdpSocket::bind(address).await.unwrap();
+        let log = QueryLog::default();
+        let queries = log.clone();
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 4096];
+            loop {
+                let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let request = Message::try_scan(&buffer[..length], 0).unwrap();
+                let request = request.value();
+
+                let name = request.question().unwrap().name().label().to_owned();
+                queries.lock().unwrap().push(name);
+
+                let response = authority.answer(request);
+                socket.send_to(&Bytes::from(response), peer).await.unwrap();
+            }
+        });
+
+        log
+    }
+
+    /// Serves `authority` over TCP on `address`, and over UDP with every response
+    /// truncated to its question.
+    async fn spawn_truncating_authority(address: SocketAddr, authority: Authority) {
+        let socket = UdpSocket::bind(address).await.unwrap();
+        let listener = TcpListener::bind(address).await.unwrap();
+        let authority = Arc::new(authority);
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 4096];
+            loop {
+                let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let request = Message::try_scan(&buffer[..length], 0).unwrap();
+                let request = request.value();
+
+                let flags = FlagsBuilder::default()
+                    .message_type(MessageType::Response)
+                    .truncation(true)
+                    .build()
+                    .unwrap();
+                let response = MessageBuilder::default()
+                    .header(
+                        HeaderBuilder::default()
+                            .id(request.header().id())
+                            .flags(flags)
+                            .build()
+                            .unwrap(),
+                    )
+                    .questions(request.questions().to_vec())
+                    .build()
+                    .unwrap();
+
+                socket.send_to(&Bytes::from(&response), peer).await.unwrap();
+            }
+        });
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let authority = authority.clone();
+
+                tokio::spawn(async move {
+                    while let Ok(Some(request)) = read_message(&mut stream).await {
+                        let response = authority.answer(&request);
+                        write_message(&mut stream, &response).await.unwrap();
+                    }
+                });
+            }
+        });
+    }
+
+    /// Spawns root, com., net., example.com., other.com., lame.com. and broken.com.
+    /// servers on 127.0.0.1-7, all listening on the same port, and returns a recursor
+    /// pointed at them along with the query log of each server.
+    async fn spawn_tree() -> (Recursor, Vec<QueryLog>) {
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let server = |last: u8| SocketAddr::from(([127, 0, 0, last], port));
+        let mut logs = vec![];
+
+        logs.push(
+            spawn_authority(
+                server(1),
+                Authority {
+                    origin: "",
+                    records: vec![],
+                    delegations: vec![
+                        ("com", "a.gtld.com", Some(Ipv4Addr::new(127, 0, 0, 2))),
+                        ("net", "a.gtld.net", Some(Ipv4Addr::new(127, 0, 0, 4))),
+                    ],
+                },
+            )
+            .await,
+        );
+
+        logs.push(
+            spawn_authority(
+                server(2),
+                Authority {
+                    origin: "com",
+                    records: vec![],
+                    delegations: vec![
+                        (
+                            "example.com",
+                            "ns1.example.com",
+                            Some(Ipv4Addr::new(127, 0, 0, 3)),
+                        ),
+                        ("other.com", "ns.hosting.net", None),
+                        ("lame.com", "ns.lame.net", None),
+                        (
+                            "broken.com",
+                            "ns.broken.com",
+                            Some(Ipv4Addr::new(127, 0, 0, 7)),
+                        ),
+                    ],
+                },
+            )
+            .await,
+        );
+
+        logs.push(
+            spawn_authority(
+                server(3),
+                Authority {
+                    origin: "example.com",
+                    records: vec![
+                        record(
+                            "www.example.com",
+                            RecordData::A(Ipv4Addr::new(192, 0, 2, 10)),
+                        ),
+                        record(
+                            "alias.example.com",
+                            RecordData::CNAME(name("www.example.com")),
+                        ),
+                        record("far.example.com", RecordData::CNAME(name("host.other.com"))),
+                    ],
+                    delegations: vec![],
+                },
+            )
+            .await,
+        );
+
+        logs.push(
+            spawn_authority(
+                server(4),
+                Authority {
+                    origin: "net",
+                    records: vec![
+                        record("ns.hosting.net", RecordData::A(Ipv4Addr::new(127, 0, 0, 5))),
+                        record("ns.lame.net", RecordData::A(Ipv4Addr::new(127, 0, 0, 6))),
+                    ],
+                    delegations: vec![],
+                },
+            )
+            .await,
+        );
+
+        logs.push(
+            spawn_authority(
+                server(5),
+                Authority {
+                    origin: "other.com",
+                    records: vec![
+                        record(
+                            "host.other.com",
+                            RecordData::A(Ipv4Addr::new(192, 0, 2, 20)),
+                        ),
+                        record("loop.other.com", RecordData::CNAME(name("loop2.other.com"))),
+                        record("loop2.other.com", RecordData::CNAME(name("loop.other.com"))),
+                    ],
+                    delegations: vec![],
+                },
+            )
+            .await,
+        );
+
+        // Refers every query back up to the root
+        logs.push(
+            spawn_authority(
+                server(6),
+                Authority {
+                    origin: "lame.com",
+                    records: vec![],
+                    delegations: vec![("", "a.root-servers.net", None)],
+                },
+            )
+            .await,
+        );
+
+        // Answers NXDOMAIN for the empty non-terminal b.broken.com.
+        logs.push(
+            spawn_authority(
+                server(7),
+                Authority {
+                    origin: "broken.com",
+                    records: vec![record(
+                        "a.b.broken.com",
+                        RecordData::A(Ipv4Addr::new(192, 0, 2, 30)),
+                    )],
+                    delegations: vec![],
+                },
+            )
+            .await,
+        );
+
+        let config = RecursorConfigBuilder::default()
+            .root_hints(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])
+            .port(port)
+            .timeout(Duration::from_millis(500))
+            .build()
+            .unwrap();
+
+        (Recursor::new(config), logs)
+    }
+
+    fn query(value: &str) -> Query {
+        Query::new(name(value), RecordType::A, Class::IN)
+    }
+
+    #[tokio::test]
+    async fn resolve_follows_referrals_with_glue() {
+        let (recursor, _) = spawn_tree().await;
+        let response = recursor.resolve(&query("www.example.com")).await.unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data(),
+            &RecordData::A(Ipv4Addr::new(192, 0, 2, 10))
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_follows_cname_within_zone() {
+        let (recursor, _) = spawn_tree().await;
+        let response = recursor.resolve(&query("alias.example.com")).await.unwrap();
+
+        let types = response
+            .answers()
+            .iter()
+            .map(|record| *record.r#type())
+            .collect::<Vec<_>>();
+
+        assert_eq!(types, vec![RecordType::CNAME, RecordType::A]);
+    }
+
+    #[tokio::test]
+    async fn resolve_follows_cname_to_out_of_bailiwick_name_server() {
+        let (recursor, _) = spawn_tree().await;
+        let response = recursor.resolve(&query("far.example.com")).await.unwrap();
+
+        assert_eq!(response.answers().len(), 2);
+        assert_eq!(response.answers()[1].name(), "host.other.com");
+        assert_eq!(
+            response.answers()[1].data(),
+            &RecordData::A(Ipv4Addr::new(192, 0, 2, 20))
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_unknown_name_returns_nxdomain_with_soa() {
+        let (recursor, _) = spawn_tree().await;
+        let response = recursor
+            .resolve(&query("missing.example.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::NoDomain);
+        assert_eq!(response.authorities()[0].r#type(), &RecordType::SOA);
+    }
+
+    #[tokio::test]
+    async fn resolve_cname_loop_fails() {
+        let (recursor, _) = spawn_tree().await;
+        let response = recursor.resolve(&query("loop.other.com")).await;
+
+        assert!(matches!(response, Err(NauticDnsError::AliasLoop(_))));
+    }
+
+    #[tokio::test]
+    async fn resolve_upward_referral_fails() {
+        let (recursor, _) = spawn_tree().await;
+        let response = recursor.resolve(&query("www.lame.com")).await;
+
+        assert!(matches!(
+            response,
+            Err(NauticDnsError::NoUsableNameServers(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn resolve_minimises_query_names() {
+        let (recursor, logs) = spawn_tree().await;
+        recursor.resolve(&query("www.example.com")).await.unwrap();
+
+        let seen = |server: usize| logs[server].lock().unwrap().clone();
+
+        assert_eq!(seen(0), vec!["com"]);
+        assert_eq!(seen(1), vec!["example.com"]);
+        assert_eq!(seen(2), vec!["www.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_without_minimisation_sends_full_name() {
+        let (recursor, logs) = spawn_tree().await;
+        let config = RecursorConfigBuilder::default()
+            .root_hints(recursor.config().root_hints().to_vec())
+            .port(recursor.config().port())
+            .qname_minimisation(false)
+            .build()
+            .unwrap();
+
+        Recursor::new(config)
+            .resolve(&query("www.example.com"))
+            .await
+            .unwrap();
+
+        assert_eq!(logs[0].lock().unwrap().clone(), vec!["www.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_falls_back_to_full_name_on_nxdomain() {
+        let (recursor, logs) = spawn_tree().await;
+        let response = recursor.resolve(&query("a.b.broken.com")).await.unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::NoError);
+        assert_eq!(
+            response.answers()[0].data(),
+            &RecordData::A(Ipv4Addr::new(192, 0, 2, 30))
+        );
+        assert_eq!(
+            logs[6].lock().unwrap().clone(),
+            vec!["b.broken.com", "a.b.broken.com"]
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_starts_from_cached_delegation() {
+        let (recursor, logs) = spawn_tree().await;
+        recursor.resolve(&query("www.example.com")).await.unwrap();
+        recursor.resolve(&query("alias.example.com")).await.unwrap();
+
+        let seen = |server: usize| logs[server].lock().unwrap().clone();
+
+        assert_eq!(seen(0), vec!["com"]);
+        assert_eq!(seen(1), vec!["example.com"]);
+        assert_eq!(seen(2), vec!["www.example.com", "alias.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn truncated_response_is_retried_over_tcp() {
+        let address = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+        let port = UdpSocket::bind(address)
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        spawn_truncating_authority(
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            Authority {
+                origin: "",
+                records: vec![record(
+                    "www.example",
+                    RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                )],
+                delegations: vec![],
+            },
+        )
+        .await;
+
+        let config = RecursorConfigBuilder::default()
+            .root_hints(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])
+            .port(port)
+            .qname_minimisation(false)
+            .timeout(Duration::from_millis(500))
+            .build()
+            .unwrap();
+        let response = Recursor::new(config)
+            .resolve(&query("www.example"))
+            .await
+            .unwrap();
+
+        assert!(!response.header().flags().truncation());
+        assert_eq!(
+            response.answers()[0].data(),
+            &RecordData::A(Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+}
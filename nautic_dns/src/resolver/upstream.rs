@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use tokio::time;
+
+use super::{request, Recursor, ResolverConfig};
+use crate::{
+    errors::NauticDnsError,
+    protocol::{Message, Query},
+    transport::{DnsTransport, HttpsClient, Protocol, StreamTransport, UdpTransport, UpstreamSpec},
+};
+
+/// The configured name servers, each reached through its own transport, or the
+/// recursor when resolving iteratively. The recursor lives as long as the resolver
+/// so the zone cuts it learns are shared by every query.
+pub(crate) struct Upstreams {
+    config: Arc<ResolverConfig>,
+    transports: Vec<Arc<dyn DnsTransport>>,
+    recursor: Option<Recursor>,
+}
+
+impl Upstreams {
+    /// Builds a transport to every configured upstream, or failing that to every
+    /// configured name server over the configured protocol.
+    pub(crate) fn new(config: Arc<ResolverConfig>) -> Self {
+        let idle_timeout = Some(config.idle_timeout());
+        if !config.upstreams().is_empty() {
+            let transports = |specs: &[UpstreamSpec], bootstrap: &[Arc<dyn DnsTransport>]| {
+                specs
+                    .iter()
+                    .map(|spec| {
+                        spec.transport(
+                            config.multiplex(),
+                            idle_timeout,
+                            config.https_method(),
+                            bootstrap,
+                        )
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let bootstrap = transports(config.bootstrap(), &[]);
+            let transports = transports(config.upstreams(), &bootstrap);
+
+            return Self::with_transports(config, transports);
+        }
+
+        let transports = match config.protocol() {
+            Protocol::Https => config
+                .https_endpoints()
+                .iter()
+                .map(|url| {
+                    let client =
+                        HttpsClient::new(url.clone(), config.https_method(), config.tls().cloned())
+                            .expect("Endpoints are validated when the config is built");
+                    Arc::new(client) as Arc<dyn DnsTransport>
+                })
+                .collect(),
+            protocol => config
+                .name_servers()
+                .iter()
+                .map(|&server| -> Arc<dyn DnsTransport> {
+                    match (protocol, config.tls()) {
+                        (Protocol::Tls, Some(tls)) => {
+                            Arc::new(StreamTransport::tls(server, tls.clone(), idle_timeout))
+                        }
+                        (Protocol::Tcp, _) => Arc::new(StreamTransport::tcp(server, idle_timeout)),
+                        _ => Arc::new(UdpTransport::new(server, config.multiplex(), idle_timeout)),
+                    }
+                })
+                .collect(),
+        };
+
+        Self::with_transports(config, transports)
+    }
+
+    pub(crate) fn with_transports(
+        config: Arc<ResolverConfig>,
+        transports: Vec<Arc<dyn DnsTransport>>,
+    ) -> Self {
+        let recursor = config.recursion().cloned().map(Recursor::new);
+
+        Self {
+            config,
+            transports,
+            recursor,
+        }
+    }
+
+    /// Resolves `query` recursively when configured to, otherwise sends it through
+    /// the transports in turn, moving on to the next whenever one fails to answer in
+    /// time.
+    pub(crate) async fn query(&self, query: &Query) -> Result<Message, NauticDnsError> {
+        if let Some(recursor) = &self.recursor {
+            return recursor.resolve(query).await;
+        }
+
+        if self.transports.is_empty() {
+            return Err(NauticDnsError::NoNameServers);
+        }
+
+        let mut last_error = NauticDnsError::Timeout;
+        for _ in 0..self.config.attempts().max(1) {
+            for transport in &self.transports {
+                let request = request(query, true, self.config.case_randomisation());
+
+                match time::timeout(self.config.timeout(), transport.exchange(&request)).await {
+                    Ok(Ok(response)) => return Ok(response),
+                    Ok(Err(error)) => last_error = error,
+                    Err(_) => last_error = NauticDnsError::Timeout,
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
@@ -0,0 +1,267 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use derive_builder::Builder;
+
+use super::TsigKey;
+use crate::{
+    policy::IpNetwork,
+    protocol::{ByteScanner, LabelSequence},
+};
+
+/// Query types asking for a zone transfer (RFC 1995, RFC 5936).
+const IXFR: u16 = 251;
+const AXFR: u16 = 252;
+
+/// What an access rule matches requests by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessRule {
+    /// Clients with an address within the network.
+    Network(IpNetwork),
+    /// Requests signed with the TSIG key of this name, once the signature verified.
+    Key(LabelSequence),
+}
+
+impl AccessRule {
+    fn matches(&self, client: IpAddr, key: Option<&LabelSequence>) -> bool {
+        match self {
+            AccessRule::Network(network) => network.contains(client),
+            AccessRule::Key(name) => key.is_some_and(|key| key.eq_ignore_case(name)),
+        }
+    }
+}
+
+/// Which clients may make one kind of request. Rules are checked in the order
+/// they were added and the first one matching the client address or the key the
+/// request is signed with decides; requests matching no rule are refused.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessList {
+    rules: Vec<(AccessRule, bool)>,
+}
+
+impl AccessList {
+    /// Refuses every client.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Allows every client.
+    pub fn any() -> Self {
+        Self::none()
+            .allow(network(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+            .allow(network(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0))
+    }
+
+    /// Allows clients on the loopback interface only.
+    pub fn localhost() -> Self {
+        Self::none()
+            .allow(network(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 0)), 8))
+            .allow(network(IpAddr::V6(Ipv6Addr::LOCALHOST), 128))
+    }
+
+    pub fn allow(mut self, network: IpNetwork) -> Self {
+        self.rules.push((AccessRule::Network(network), true));
+        self
+    }
+
+    pub fn deny(mut self, network: IpNetwork) -> Self {
+        self.rules.push((AccessRule::Network(network), false));
+        self
+    }
+
+    /// Allows requests signed with the TSIG key named `name`, from any address.
+    pub fn allow_key(mut self, name: LabelSequence) -> Self {
+        self.rules.push((AccessRule::Key(name), true));
+        self
+    }
+
+    pub fn deny_key(mut self, name: LabelSequence) -> Self {
+        self.rules.push((AccessRule::Key(name), false));
+        self
+    }
+
+    /// The rules in order, each with whether it allows the request.
+    pub fn rules(&self) -> &[(AccessRule, bool)] {
+        &self.rules
+    }
+
+    /// Whether a request from `client`, signed with the verified TSIG key named
+    /// `key` if any, is allowed.
+    pub fn allows(&self, client: IpAddr, key: Option<&LabelSequence>) -> bool {
+        let client = unmapped(client);
+
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(client, key))
+            .is_some_and(|(_, allowed)| *allowed)
+    }
+}
+
+/// The IPv4 address behind an IPv4-mapped IPv6 address, as dual-stack sockets see
+/// IPv4 clients, or `address` itself.
+pub(crate) fn unmapped(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+        IpAddr::V4(_) => address,
+    }
+}
+
+fn network(address: IpAddr, prefix: u8) -> IpNetwork {
+    IpNetwork::new(address, prefix).expect("Prefix fits the address")
+}
+
+/// Which clients the server answers, for each kind of request. By default anyone
+/// may query, only the local host may ask for recursion, and nobody may transfer
+/// zones, send NOTIFY or UPDATE.
+#[derive(Debug, Clone, Builder)]
+pub struct AccessControl {
+    /// Keys requests may be signed with. Signed requests are verified before the
+    /// access lists are checked, and answered with NOTAUTH when that fails.
+    #[builder(default)]
+    keys: Vec<TsigKey>,
+
+    #[builder(default = "AccessList::any()")]
+    query: AccessList,
+
+    /// Clients allowed recursion. Others may still query, but handlers that
+    /// recurse or forward refuse them, see [`Request::recursion_allowed`].
+    ///
+    /// [`Request::recursion_allowed`]: super::Request::recursion_allowed
+    #[builder(default = "AccessList::localhost()")]
+    recursion: AccessList,
+
+    /// Clients allowed AXFR and IXFR queries.
+    #[builder(default)]
+    transfer: AccessList,
+
+    #[builder(default)]
+    notify: AccessList,
+
+    #[builder(default)]
+    update: AccessList,
+}
+
+impl AccessControl {
+    pub fn keys(&self) -> &[TsigKey] {
+        &self.keys
+    }
+    pub fn query(&self) -> &AccessList {
+        &self.query
+    }
+    pub fn recursion(&self) -> &AccessList {
+        &self.recursion
+    }
+    pub fn transfer(&self) -> &AccessList {
+        &self.transfer
+    }
+    pub fn notify(&self) -> &AccessList {
+        &self.notify
+    }
+    pub fn update(&self) -> &AccessList {
+        &self.update
+    }
+
+    pub(crate) fn allows(
+        &self,
+        kind: RequestKind,
+        client: IpAddr,
+        key: Option<&LabelSequence>,
+    ) -> bool {
+        let list = match kind {
+            RequestKind::Query => &self.query,
+            RequestKind::Recursion => &self.recursion,
+            RequestKind::Transfer => &self.transfer,
+            RequestKind::Notify => &self.notify,
+            RequestKind::Update => &self.update,
+        };
+
+        list.allows(client, key)
+    }
+}
+
+impl Default for AccessControl {
+    fn default() -> Self {
+        AccessControlBuilder::default()
+            .build()
+            .expect("Access control has defaults for every field")
+    }
+}
+
+/// The kinds of requests with their own access list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RequestKind {
+    Query,
+    Recursion,
+    Transfer,
+    Notify,
+    Update,
+}
+
+/// Whether the raw standard query `query` asks for a zone transfer. The question is
+/// read by hand, as the transfer types are not record types the protocol module
+/// parses.
+pub(crate) fn is_transfer(query: &[u8], cursor: usize) -> bool {
+    let Ok(scan) = LabelSequence::try_scan(query, cursor) else {
+        return false;
+    };
+
+    let start = cursor + scan.total_bytes();
+    match query.get(start..start + 2) {
+        Some(&[high, low]) => matches!(u16::from_be_bytes([high, low]), IXFR | AXFR),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(text: &str) -> IpAddr {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let list = AccessList::none()
+            .deny(network(address("192.0.2.128"), 25))
+            .allow(network(address("192.0.2.0"), 24))
+            .allow(network(address("2001:db8::"), 32));
+
+        assert!(list.allows(address("192.0.2.1"), None));
+        assert!(!list.allows(address("192.0.2.200"), None));
+        assert!(!list.allows(address("198.51.100.1"), None));
+        assert!(list.allows(address("2001:db8::53"), None));
+        assert!(list.allows(address("::ffff:192.0.2.1"), None));
+        assert!(!AccessList::none().allows(address("192.0.2.1"), None));
+    }
+
+    #[test]
+    fn recursion_is_local_by_default() {
+        let access = AccessControl::default();
+
+        assert!(access.allows(RequestKind::Query, address("198.51.100.1"), None));
+        assert!(access.allows(RequestKind::Recursion, address("127.0.0.1"), None));
+        assert!(access.allows(RequestKind::Recursion, address("::1"), None));
+        assert!(!access.allows(RequestKind::Recursion, address("198.51.100.1"), None));
+        assert!(!access.allows(RequestKind::Transfer, address("127.0.0.1"), None));
+        assert!(!access.allows(RequestKind::Notify, address("127.0.0.1"), None));
+        assert!(!access.allows(RequestKind::Update, address("127.0.0.1"), None));
+    }
+
+    #[test]
+    fn key_rules_match_signed_requests_in_order() {
+        let key = LabelSequence::new("transfer.key");
+        let list = AccessList::none()
+            .deny(network(address("198.51.100.0"), 24))
+            .allow_key(LabelSequence::new("Transfer.Key"))
+            .allow(network(address("192.0.2.0"), 24));
+
+        assert!(list.allows(address("203.0.113.1"), Some(&key)));
+        assert!(!list.allows(address("198.51.100.1"), Some(&key)));
+        assert!(!list.allows(address("203.0.113.1"), None));
+        assert!(!list.allows(
+            address("203.0.113.1"),
+            Some(&LabelSequence::new("other.key"))
+        ));
+        assert!(list.allows(address("192.0.2.1"), None));
+    }
+}
@@ -0,0 +1,424 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use derive_builder::Builder;
+use tokio::time;
+
+use super::{Request, RequestHandler};
+use crate::{
+    protocol::{LabelSequence, Message, ResponseCode},
+    transport::{check_bootstrap, DnsTransport, HttpMethod, UpstreamSpec},
+};
+
+/// Sends queries for names under `suffix` to `upstreams`, e.g. `corp.internal` to
+/// the office resolver.
+#[derive(Debug, Clone)]
+pub struct ForwardRule {
+    suffix: LabelSequence,
+    upstreams: Vec<UpstreamSpec>,
+}
+
+impl ForwardRule {
+    pub fn new(suffix: LabelSequence, upstreams: Vec<UpstreamSpec>) -> Self {
+        Self { suffix, upstreams }
+    }
+
+    pub fn suffix(&self) -> &LabelSequence {
+        &self.suffix
+    }
+    pub fn upstreams(&self) -> &[UpstreamSpec] {
+        &self.upstreams
+    }
+}
+
+#[derive(Debug, Clone, Builder)]
+#[builder(build_fn(validate = "Self::validate"))]
+pub struct ForwarderConfig {
+    /// Upstreams for names no rule matches. Without any, those queries are refused.
+    #[builder(default)]
+    upstreams: Vec<UpstreamSpec>,
+
+    /// Rules for names under particular suffixes. The rule with the longest matching
+    /// suffix wins.
+    #[builder(default)]
+    rules: Vec<ForwardRule>,
+
+    /// Encrypted upstreams given by IP address, which the TLS and HTTPS upstreams
+    /// given by host name are looked up through.
+    #[builder(default)]
+    bootstrap: Vec<UpstreamSpec>,
+
+    /// How long to wait for each upstream before trying the next.
+    #[builder(default = "Duration::from_secs(2)")]
+    timeout: Duration,
+
+    /// Rounds through the upstreams of a rule before answering SERVFAIL.
+    #[builder(default = "2")]
+    attempts: u8,
+
+    /// Share one UDP socket per upstream between all queries instead of sending each
+    /// query from a fresh random port. This saves sockets under load, but every query
+    /// then leaves from the same port, so forged answers are far easier to land.
+    /// Stream protocols always share one connection per upstream.
+    #[builder(default)]
+    multiplex: bool,
+
+    /// Connections to upstreams are closed after being idle this long.
+    #[builder(default = "Duration::from_secs(10)")]
+    idle_timeout: Duration,
+
+    #[builder(default)]
+    https_method: HttpMethod,
+}
+
+impl ForwarderConfig {
+    pub fn upstreams(&self) -> &[UpstreamSpec] {
+        &self.upstreams
+    }
+    pub fn rules(&self) -> &[ForwardRule] {
+        &self.rules
+    }
+    pub fn bootstrap(&self) -> &[UpstreamSpec] {
+        &self.bootstrap
+    }
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+    pub fn attempts(&self) -> u8 {
+        self.attempts
+    }
+    pub fn multiplex(&self) -> bool {
+        self.multiplex
+    }
+    pub fn idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+    pub fn https_method(&self) -> HttpMethod {
+        self.https_method
+    }
+}
+
+impl ForwarderConfigBuilder {
+    fn validate(&self) -> Result<(), String> {
+        let upstreams = self.upstreams.as_deref().unwrap_or_default();
+        let rules = self.rules.as_deref().unwrap_or_default();
+
+        check_bootstrap(
+            upstreams
+                .iter()
+                .chain(rules.iter().flat_map(ForwardRule::upstreams)),
+            self.bootstrap.as_deref().unwrap_or_default(),
+        )
+    }
+}
+
+/// The transports for the names under one suffix. The root stands for the default
+/// upstreams.
+struct Route {
+    suffix: LabelSequence,
+    transports: Vec<Arc<dyn DnsTransport>>,
+}
+
+/// Forwards every query to the upstreams of the rule matching its name, relaying
+/// the response back as it came. Queries leave under a fresh id and with the RD bit
+/// the client set.
+pub struct Forwarder {
+    config: ForwarderConfig,
+    routes: Vec<Route>,
+}
+
+impl Forwarder {
+    pub fn new(config: ForwarderConfig) -> Self {
+        let transports = |upstreams: &[UpstreamSpec], bootstrap: &[Arc<dyn DnsTransport>]| {
+            upstreams
+                .iter()
+                .map(|spec| {
+                    spec.transport(
+                        config.multiplex(),
+                        Some(config.idle_timeout()),
+                        config.https_method(),
+                        bootstrap,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let bootstrap = transports(config.bootstrap(), &[]);
+
+        let mut routes = config
+            .rules()
+            .iter()
+            .map(|rule| {
+                let transports = transports(rule.upstreams(), &bootstrap);
+                (rule.suffix().clone(), transports)
+            })
+            .collect::<Vec<_>>();
+
+        if !config.upstreams().is_empty() {
+            routes.push((
+                LabelSequence::root(),
+                transports(config.upstreams(), &bootstrap),
+            ));
+        }
+
+        Self::with_transports(config, routes)
+    }
+
+    /// Creates a forwarder sending the names under each suffix through the given
+    /// transports instead of the configured upstreams. The root suffix catches all
+    /// other names.
+    pub fn with_transports(
+        config: ForwarderConfig,
+        routes: Vec<(LabelSequence, Vec<Arc<dyn DnsTransport>>)>,
+    ) -> Self {
+        let mut routes = routes
+            .into_iter()
+            .map(|(suffix, transports)| Route { suffix, transports })
+            .collect::<Vec<_>>();
+
+        // Longest suffixes first, so the first match is the most specific one
+        routes.sort_by_key(|route| std::cmp::Reverse(route.suffix.label_count()));
+
+        Self { config, routes }
+    }
+
+    pub fn config(&self) -> &ForwarderConfig {
+        &self.config
+    }
+
+    fn route(&self, name: &LabelSequence) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| name.is_subdomain_of(&route.suffix))
+    }
+}
+
+#[async_trait]
+impl RequestHandler for Forwarder {
+    async fn handle(&self, request: &Request) -> Option<Message> {
+        let question = request.message().question()?;
+        if !request.recursion_allowed() {
+            return request.reply(ResponseCode::Refused).build().ok();
+        }
+
+        let Some(route) = self.route(question.name()) else {
+            return request.reply(ResponseCode::Refused).build().ok();
+        };
+
+        let client = request.message().header();
+        let query = Message::query(question.clone(), client.flags().recursion_desired());
+
+        for _ in 0..self.config.attempts().max(1) {
+            for transport in &route.transports {
+                let exchange = transport.exchange(&query);
+                if let Ok(Ok(response)) = time::timeout(self.config.timeout(), exchange).await {
+                    return Some(response.with_id(client.id()));
+                }
+            }
+        }
+
+        request.reply(ResponseCode::ServerFailure).build().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use bytes::Bytes;
+    use tokio::net::UdpSocket;
+
+    use super::*;
+    use crate::{
+        protocol::{ByteScanner, Class, Query, Record, RecordData, RecordType},
+        transport::{MockReply, MockTransport, Protocol},
+    };
+
+    fn answer(address: Ipv4Addr) -> MockReply {
+        let record = Record::new(
+            LabelSequence::new("host.corp.internal"),
+            Class::IN,
+            60,
+            RecordData::A(address),
+        );
+
+        MockReply::answer(vec![record])
+    }
+
+    fn request(name: &str, recursion_desired: bool) -> Request {
+        let query = Query::new(LabelSequence::new(name), RecordType::A, Class::IN);
+        let message = Message::query(query, recursion_desired).with_id(0x1234);
+
+        Request::new(
+            message,
+            SocketAddr::from(([127, 0, 0, 1], 5353)),
+            Protocol::Udp,
+        )
+    }
+
+    fn config() -> ForwarderConfig {
+        ForwarderConfigBuilder::default()
+            .timeout(Duration::from_millis(100))
+            .attempts(1)
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn longest_suffix_rule_is_used() {
+        let corp = Arc::new(MockTransport::new(vec![answer(Ipv4Addr::new(10, 0, 0, 1))]));
+        let public = Arc::new(MockTransport::new(vec![answer(Ipv4Addr::new(
+            192, 0, 2, 1,
+        ))]));
+        let forwarder = Forwarder::with_transports(
+            config(),
+            vec![
+                (
+                    LabelSequence::root(),
+                    vec![public.clone() as Arc<dyn DnsTransport>],
+                ),
+                (
+                    LabelSequence::new("corp.internal"),
+                    vec![corp.clone() as Arc<dyn DnsTransport>],
+                ),
+            ],
+        );
+
+        let response = forwarder
+            .handle(&request("host.CORP.internal", true))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.answers()[0].data(),
+            &RecordData::A(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert!(public.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn id_is_rewritten_and_rd_preserved() {
+        let upstream =
+            Arc::new(MockTransport::new(vec![]).with_fallback(answer(Ipv4Addr::LOCALHOST)));
+        let forwarder = Forwarder::with_transports(
+            config(),
+            vec![(
+                LabelSequence::root(),
+                vec![upstream.clone() as Arc<dyn DnsTransport>],
+            )],
+        );
+
+        for recursion_desired in [true, false] {
+            let response = forwarder
+                .handle(&request("www.example", recursion_desired))
+                .await
+                .unwrap();
+
+            let sent = upstream.requests().pop().unwrap();
+            assert_ne!(sent.header().id(), 0x1234);
+            assert_eq!(sent.header().flags().recursion_desired(), recursion_desired);
+            assert_eq!(response.header().id(), 0x1234);
+            assert_eq!(
+                response.header().flags().recursion_desired(),
+                recursion_desired
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unanswered_query_is_servfail() {
+        let upstream = Arc::new(MockTransport::new(vec![MockReply::Drop]));
+        let forwarder = Forwarder::with_transports(
+            config(),
+            vec![(
+                LabelSequence::new("corp.internal"),
+                vec![upstream as Arc<dyn DnsTransport>],
+            )],
+        );
+
+        let response = forwarder
+            .handle(&request("host.corp.internal", true))
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::ServerFailure);
+        assert_eq!(response.header().id(), 0x1234);
+
+        let response = forwarder
+            .handle(&request("www.example", true))
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::Refused);
+    }
+
+    #[tokio::test]
+    async fn unknown_record_types_are_relayed() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = upstream.local_addr().unwrap();
+        let https = RecordData::Unknown {
+            r#type: 65,
+            data: vec![0x00, 0x01, 0x00],
+        };
+
+        let answer = https.clone();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 512];
+            let (length, client) = upstream.recv_from(&mut buffer).await.unwrap();
+            let query = Message::try_scan(&buffer[..length], 0)
+                .unwrap()
+                .value()
+                .clone();
+            let request = Request::new(query, client, Protocol::Udp);
+
+            let question = request.message().question().unwrap();
+            let record = Record::new(question.name().clone(), Class::IN, 60, answer);
+            let response = request
+                .reply(ResponseCode::NoError)
+                .answers(vec![record])
+                .build()
+                .unwrap();
+            upstream
+                .send_to(&Bytes::from(&response), client)
+                .await
+                .unwrap();
+        });
+
+        let config = ForwarderConfigBuilder::default()
+            .upstreams(vec![UpstreamSpec::Udp(address)])
+            .build()
+            .unwrap();
+        let forwarder = Forwarder::new(config);
+
+        let query = Query::new(
+            LabelSequence::new("www.example"),
+            RecordType::Unknown(65),
+            Class::IN,
+        );
+        let request = Request::new(
+            Message::query(query, true),
+            SocketAddr::from(([127, 0, 0, 1], 5353)),
+            Protocol::Udp,
+        );
+        let response = forwarder.handle(&request).await.unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::NoError);
+        assert_eq!(response.answers()[0].data(), &https);
+    }
+
+    #[tokio::test]
+    async fn client_without_recursion_is_refused() {
+        let upstream =
+            Arc::new(MockTransport::new(vec![]).with_fallback(answer(Ipv4Addr::LOCALHOST)));
+        let forwarder = Forwarder::with_transports(
+            config(),
+            vec![(
+                LabelSequence::root(),
+                vec![upstream.clone() as Arc<dyn DnsTransport>],
+            )],
+        );
+
+        let request = request("www.example", true).with_recursion_allowed(false);
+        let response = forwarder.handle(&request).await.unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::Refused);
+        assert!(upstream.requests().is_empty());
+    }
+}
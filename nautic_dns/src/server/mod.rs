@@ -0,0 +1,706 @@
+mod acl;
+mod forward;
+mod rate_limit;
+mod tcp;
+mod tsig;
+mod udp;
+
+pub use acl::{AccessControl, AccessControlBuilder, AccessList, AccessRule};
+pub use forward::*;
+pub use rate_limit::{RateLimitConfig, RateLimitConfigBuilder};
+pub use tsig::{TsigAlgorithm, TsigKey};
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use acl::RequestKind;
+use async_trait::async_trait;
+use bytes::Bytes;
+use derive_builder::Builder;
+use rate_limit::RateLimiter;
+use tokio::net::{TcpListener, UdpSocket};
+
+use crate::{
+    errors::NauticDnsError,
+    protocol::{
+        ByteScanner, FlagsBuilder, HeaderBuilder, Message, MessageBuilder, MessageType, OpCode,
+        ResponseCode,
+    },
+    transport::Protocol,
+};
+
+/// Largest response sent over UDP, however large a buffer the client advertises,
+/// so responses are never fragmented. Also the size the server advertises in its
+/// own OPT records.
+pub(crate) const EDNS_UDP_PAYLOAD: u16 = 1232;
+
+/// Size of the fixed message header.
+const HEADER_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Builder)]
+pub struct ServerConfig {
+    /// Address the server listens on, over both UDP and TCP.
+    #[builder(default = "SocketAddr::from(([0, 0, 0, 0], 53))")]
+    listen: SocketAddr,
+
+    /// UDP queries answered at once. Further queries are dropped until one of them
+    /// is answered.
+    #[builder(default = "1024")]
+    max_udp_requests: usize,
+
+    /// TCP connections accepted at once. Further connections wait in the listen
+    /// backlog until one of them ends.
+    #[builder(default = "128")]
+    max_tcp_connections: usize,
+
+    /// Queries answered at once on each TCP connection. Further queries on that
+    /// connection are not read until one of them is answered.
+    #[builder(default = "16")]
+    max_tcp_requests: usize,
+
+    /// TCP connections are closed once no query arrives for this long (RFC 7766
+    /// section 6.2.3).
+    #[builder(default = "Duration::from_secs(10)")]
+    tcp_idle_timeout: Duration,
+
+    /// Limits how fast identical responses are sent to one client over UDP when set.
+    /// TCP is never limited, as its clients cannot spoof their address.
+    #[builder(setter(strip_option), default)]
+    rate_limit: Option<RateLimitConfig>,
+
+    /// Which clients may query, recurse, transfer zones, send NOTIFY or UPDATE.
+    /// Requests from anyone else are answered with REFUSED.
+    #[builder(default)]
+    access: AccessControl,
+}
+
+impl ServerConfig {
+    pub fn listen(&self) -> SocketAddr {
+        self.listen
+    }
+    pub fn max_udp_requests(&self) -> usize {
+        self.max_udp_requests
+    }
+    pub fn max_tcp_connections(&self) -> usize {
+        self.max_tcp_connections
+    }
+    pub fn max_tcp_requests(&self) -> usize {
+        self.max_tcp_requests
+    }
+    pub fn tcp_idle_timeout(&self) -> Duration {
+        self.tcp_idle_timeout
+    }
+    pub fn rate_limit(&self) -> Option<&RateLimitConfig> {
+        self.rate_limit.as_ref()
+    }
+    pub fn access(&self) -> &AccessControl {
+        &self.access
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfigBuilder::default()
+            .build()
+            .expect("Server config has defaults for every field")
+    }
+}
+
+/// A query received by the server, along with where it came from.
+#[derive(Debug, Clone)]
+pub struct Request {
+    message: Message,
+    source: SocketAddr,
+    protocol: Protocol,
+    recursion_allowed: bool,
+}
+
+impl Request {
+    /// A request that may be answered with recursion, see
+    /// [`Request::with_recursion_allowed`].
+    pub fn new(message: Message, source: SocketAddr, protocol: Protocol) -> Self {
+        Self {
+            message,
+            source,
+            protocol,
+            recursion_allowed: true,
+        }
+    }
+
+    /// Sets whether the client may be answered with recursion. The server clears it
+    /// for clients missing from the recursion access list.
+    pub fn with_recursion_allowed(mut self, recursion_allowed: bool) -> Self {
+        self.recursion_allowed = recursion_allowed;
+        self
+    }
+
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+    pub fn source(&self) -> SocketAddr {
+        self.source
+    }
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+    /// Whether handlers that recurse or forward may answer this request. Others,
+    /// such as authoritative zones, answer regardless.
+    pub fn recursion_allowed(&self) -> bool {
+        self.recursion_allowed
+    }
+
+    /// Starts the response to this request with `response_code`, echoing its id,
+    /// opcode, RD bit and question. Records are added to the returned builder.
+    pub fn reply(&self, response_code: ResponseCode) -> MessageBuilder {
+        self.reply_with(response_code, false)
+    }
+
+    /// Like [`Request::reply`], with the AA bit set for answers from a zone the
+    /// server is authoritative for.
+    pub fn authoritative_reply(&self, response_code: ResponseCode) -> MessageBuilder {
+        self.reply_with(response_code, true)
+    }
+
+    fn reply_with(&self, response_code: ResponseCode, authoritative: bool) -> MessageBuilder {
+        let header = self.message.header();
+        let flags = FlagsBuilder::default()
+            .message_type(MessageType::Response)
+            .op(header.flags().op().clone())
+            .authoritative_answer(authoritative)
+            .recursion_desired(header.flags().recursion_desired())
+            .response(response_code)
+            .build()
+            .expect("Flags have defaults for every field");
+
+        let mut builder = MessageBuilder::default();
+        builder
+            .header(
+                HeaderBuilder::default()
+                    .id(header.id())
+                    .flags(flags)
+                    .build()
+                    .expect("Header has defaults for every field"),
+            )
+            .questions(self.message.questions().to_vec());
+
+        builder
+    }
+}
+
+/// Answers the queries a [`DnsServer`] receives.
+#[async_trait]
+pub trait RequestHandler: Send + Sync + 'static {
+    /// Returns the response to `request`, or `None` to send nothing back.
+    async fn handle(&self, request: &Request) -> Option<Message>;
+}
+
+/// A DNS server handing every well-formed standard query to a [`RequestHandler`],
+/// over UDP and TCP on the same port. Requests with a TSIG signature that does not
+/// verify are answered with NOTAUTH, requests the access lists do not allow with
+/// REFUSED, malformed queries with FORMERR, and zone transfers and other opcodes
+/// with NOTIMP, all without reaching the handler. Responses to signed requests are
+/// signed with the same key.
+pub struct DnsServer<H> {
+    config: ServerConfig,
+    handler: Arc<H>,
+    udp: Arc<UdpSocket>,
+    tcp: TcpListener,
+}
+
+impl<H: RequestHandler> DnsServer<H> {
+    /// Binds the configured address. Nothing is answered until the server is run.
+    pub async fn bind(config: ServerConfig, handler: H) -> Result<Self, NauticDnsError> {
+        let udp = UdpSocket::bind(config.listen())
+            .await
+            .map_err(|_| NauticDnsError::ServerBindingFailure)?;
+
+        // Binding the address UDP got keeps both on the same port when it was 0
+        let tcp = TcpListener::bind(udp.local_addr()?)
+            .await
+            .map_err(|_| NauticDnsError::ServerBindingFailure)?;
+
+        Ok(Self {
+            config,
+            handler: Arc::new(handler),
+            udp: Arc::new(udp),
+            tcp,
+        })
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// The address the server is bound to, e.g. to find the port picked for port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, NauticDnsError> {
+        Ok(self.udp.local_addr()?)
+    }
+
+    /// Answers queries over UDP and TCP. Errors receiving a datagram or accepting
+    /// a connection are logged and never stop the server.
+    pub async fn run(self) -> Result<(), NauticDnsError> {
+        let access = Arc::new(self.config.access().clone());
+        let tcp = tcp::serve(
+            self.tcp,
+            self.handler.clone(),
+            access.clone(),
+            self.config.max_tcp_connections(),
+            self.config.max_tcp_requests(),
+            self.config.tcp_idle_timeout(),
+        );
+
+        let limiter = self
+            .config
+            .rate_limit()
+            .map(|config| Arc::new(RateLimiter::new(config.clone())));
+
+        tokio::try_join!(
+            udp::serve(
+                self.udp,
+                self.handler,
+                access,
+                limiter,
+                self.config.max_udp_requests(),
+            ),
+            tcp
+        )
+        .map(|_| ())
+    }
+}
+
+/// Works out the response to the raw message `query`, or `None` when it should be
+/// dropped without an answer.
+pub(crate) async fn respond<H: RequestHandler>(
+    handler: &H,
+    access: &AccessControl,
+    query: &[u8],
+    source: SocketAddr,
+    protocol: Protocol,
+) -> Option<Message> {
+    // Too short to answer, or a response, which is never answered to avoid loops
+    if query.len() < HEADER_SIZE || query[2] & 0x80 != 0 {
+        return None;
+    }
+
+    let kind = match OpCode::try_from((query[2] >> 3) & 0x0f) {
+        Ok(OpCode::Query) if acl::is_transfer(query, HEADER_SIZE) => RequestKind::Transfer,
+        Ok(OpCode::Query) => RequestKind::Query,
+        Ok(OpCode::Notify) => RequestKind::Notify,
+        Ok(OpCode::Update) => RequestKind::Update,
+        _ => return Some(error_response(query, ResponseCode::NotImplemented)),
+    };
+
+    let notauth = || error_response(query, ResponseCode::NotAuth);
+    let signed = match tsig::verify(access.keys(), query, notauth, tsig::now()) {
+        Ok(signed) => signed,
+        Err(response) => return Some(response),
+    };
+
+    // Every response to a signed request is signed in turn (RFC 8945 section 5.3)
+    let finish = |response: Message| match &signed {
+        Some(signed) => signed.sign(response),
+        None => response,
+    };
+
+    let client = source.ip();
+    let key = signed.as_ref().map(|signed| signed.key().name());
+    if !access.allows(kind, client, key) {
+        log::debug!("Refusing {kind:?} request from {client}");
+        return Some(finish(error_response(query, ResponseCode::Refused)));
+    }
+
+    // Allowed clients learn these are not served, rather than being refused
+    if kind != RequestKind::Query {
+        return Some(finish(error_response(query, ResponseCode::NotImplemented)));
+    }
+
+    let message = match Message::try_scan(query, 0) {
+        Ok(scan) => scan.value().clone(),
+        Err(_) => return Some(finish(error_response(query, ResponseCode::FormatError))),
+    };
+
+    // Standard queries carry exactly one question (RFC 9619)
+    if message.questions().len() != 1 {
+        return Some(finish(error_response(query, ResponseCode::FormatError)));
+    }
+
+    let edns = message.edns().is_some();
+    let limit = message.udp_payload_size().min(EDNS_UDP_PAYLOAD) as usize;
+    let request = Request::new(message, source, protocol).with_recursion_allowed(access.allows(
+        RequestKind::Recursion,
+        client,
+        key,
+    ));
+
+    let mut response = handler.handle(&request).await?;
+
+    // Clients using EDNS get an OPT record back (RFC 6891 section 6.1.1)
+    if edns {
+        response = response.with_edns(EDNS_UDP_PAYLOAD);
+    }
+
+    // Responses too large for the client are cut down to the question, so it
+    // retries over TCP
+    let mut signed_response = finish(response.clone());
+    if protocol == Protocol::Udp && Bytes::from(&signed_response).len() > limit {
+        response = udp::truncated(&response);
+        if edns {
+            response = response.with_edns(EDNS_UDP_PAYLOAD);
+        }
+        signed_response = finish(response);
+    }
+
+    Some(signed_response)
+}
+
+/// An empty response to a query that could not be parsed, built from its raw
+/// header alone.
+fn error_response(query: &[u8], response_code: ResponseCode) -> Message {
+    let id = u16::from_be_bytes([query[0], query[1]]);
+    let opcode = OpCode::try_from((query[2] >> 3) & 0x0f).unwrap_or(OpCode::Query);
+
+    let flags = FlagsBuilder::default()
+        .message_type(MessageType::Response)
+        .op(opcode)
+        .recursion_desired(query[2] & 0x01 != 0)
+        .response(response_code)
+        .build()
+        .expect("Flags have defaults for every field");
+
+    MessageBuilder::default()
+        .header(
+            HeaderBuilder::default()
+                .id(id)
+                .flags(flags)
+                .build()
+                .expect("Header has defaults for every field"),
+        )
+        .build()
+        .expect("Message has every field set")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::protocol::{Class, LabelSequence, Query, Record, RecordData, RecordType};
+
+    /// Answers every A query with 192.0.2.1.
+    struct StaticHandler;
+
+    #[async_trait]
+    impl RequestHandler for StaticHandler {
+        async fn handle(&self, request: &Request) -> Option<Message> {
+            let question = request.message().question()?;
+            let answer = Record::new(
+                question.name().clone(),
+                Class::IN,
+                60,
+                RecordData::A(Ipv4Addr::new(192, 0, 2, 1)),
+            );
+
+            request
+                .reply(ResponseCode::NoError)
+                .answers(vec![answer])
+                .build()
+                .ok()
+        }
+    }
+
+    fn source() -> SocketAddr {
+        SocketAddr::from(([192, 0, 2, 53], 5353))
+    }
+
+    fn query() -> Message {
+        let query = Query::new(LabelSequence::new("www.example"), RecordType::A, Class::IN);
+        Message::query(query, true)
+    }
+
+    #[tokio::test]
+    async fn query_is_handed_to_handler() {
+        let request = query();
+        let wire = Bytes::from(&request);
+
+        let response = respond(
+            &StaticHandler,
+            &AccessControl::default(),
+            &wire,
+            source(),
+            Protocol::Udp,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.header().id(), request.header().id());
+        assert_eq!(
+            response.header().flags().message_type(),
+            &MessageType::Response
+        );
+        assert!(response.header().flags().recursion_desired());
+        assert_eq!(response.questions(), request.questions());
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn query_with_bytes_outside_ascii_is_answered() {
+        let mut wire = Bytes::from(&query()).to_vec();
+        wire.truncate(HEADER_SIZE);
+        wire.extend([2, 0xc3, 0xa9, 7]);
+        wire.extend(b"example");
+        wire.extend([0, 0, 1, 0, 1]);
+
+        let response = respond(
+            &StaticHandler,
+            &AccessControl::default(),
+            &wire,
+            source(),
+            Protocol::Udp,
+        )
+        .await
+        .unwrap();
+
+        let name = response.question().unwrap().name();
+        assert_eq!(name.label(), "\\195\\169.example");
+        assert_eq!(response.answers()[0].name, *name);
+        assert_eq!(
+            &Bytes::from(&response)[HEADER_SIZE..wire.len()],
+            &wire[HEADER_SIZE..]
+        );
+    }
+
+    #[tokio::test]
+    async fn query_of_unknown_type_is_handed_to_handler() {
+        let query = Query::new(
+            LabelSequence::new("www.example"),
+            RecordType::Unknown(65),
+            Class::IN,
+        );
+        let wire = Bytes::from(&Message::query(query, true));
+
+        let response = respond(
+            &StaticHandler,
+            &AccessControl::default(),
+            &wire,
+            source(),
+            Protocol::Udp,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.response_code(), &ResponseCode::NoError);
+        assert_eq!(
+            response.question().unwrap().r#type(),
+            &RecordType::Unknown(65)
+        );
+        assert!(response.edns().is_none());
+    }
+
+    #[tokio::test]
+    async fn malformed_query_is_answered_with_formerr() {
+        let mut wire = Bytes::from(&query()).to_vec();
+        wire.truncate(wire.len() - 3);
+
+        let response = respond(
+            &StaticHandler,
+            &AccessControl::default(),
+            &wire,
+            source(),
+            Protocol::Udp,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.header().id(), query_id(&wire));
+        assert_eq!(response.response_code(), &ResponseCode::FormatError);
+        assert!(response.questions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unsupported_opcode_is_answered_with_notimp() {
+        for opcode in [OpCode::Status as u8, 0b1111] {
+            let mut wire = Bytes::from(&query()).to_vec();
+            wire[2] = (wire[2] & 0x87) | opcode << 3;
+
+            let response = respond(
+                &StaticHandler,
+                &AccessControl::default(),
+                &wire,
+                source(),
+                Protocol::Udp,
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(response.response_code(), &ResponseCode::NotImplemented);
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_outside_access_lists_are_refused() {
+        let local = SocketAddr::from(([127, 0, 0, 1], 5353));
+        let access = AccessControlBuilder::default()
+            .query(AccessList::localhost())
+            .notify(AccessList::localhost())
+            .build()
+            .unwrap();
+
+        let wire = Bytes::from(&query());
+        let response = respond(&StaticHandler, &access, &wire, source(), Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::Refused);
+        assert_eq!(response.header().id(), query_id(&wire));
+
+        let response = respond(&StaticHandler, &access, &wire, local, Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(response.answers().len(), 1);
+
+        // NOTIFY gets through its list but is not served, UPDATE has a list of its own
+        let mut wire = wire.to_vec();
+        for (opcode, response_code) in [
+            (OpCode::Notify, ResponseCode::NotImplemented),
+            (OpCode::Update, ResponseCode::Refused),
+        ] {
+            wire[2] = (wire[2] & 0x87) | (opcode as u8) << 3;
+            let response = respond(&StaticHandler, &access, &wire, local, Protocol::Udp)
+                .await
+                .unwrap();
+            assert_eq!(response.response_code(), &response_code);
+        }
+    }
+
+    #[tokio::test]
+    async fn zone_transfers_are_checked_against_transfer_list() {
+        let mut wire = Bytes::from(&query()).to_vec();
+        let r#type = wire.len() - 4;
+        wire[r#type..r#type + 2].copy_from_slice(&252u16.to_be_bytes());
+
+        let response = respond(
+            &StaticHandler,
+            &AccessControl::default(),
+            &wire,
+            source(),
+            Protocol::Tcp,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::Refused);
+
+        let access = AccessControlBuilder::default()
+            .transfer(AccessList::any())
+            .build()
+            .unwrap();
+        let response = respond(&StaticHandler, &access, &wire, source(), Protocol::Tcp)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::NotImplemented);
+    }
+
+    #[tokio::test]
+    async fn signed_requests_are_verified_and_matched_by_key() {
+        let key = TsigKey::new(
+            LabelSequence::new("transfer.key"),
+            TsigAlgorithm::HmacSha256,
+            b"not so secret".to_vec(),
+        );
+        let access = AccessControlBuilder::default()
+            .keys(vec![key.clone()])
+            .query(AccessList::none().allow_key(key.name().clone()))
+            .build()
+            .unwrap();
+
+        let response = respond(
+            &StaticHandler,
+            &access,
+            &Bytes::from(&query()),
+            source(),
+            Protocol::Udp,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::Refused);
+
+        let mut wire = Bytes::from(&tsig::sign(query(), &key, None, tsig::now())).to_vec();
+        let response = respond(&StaticHandler, &access, &wire, source(), Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.additionals().last().map(Record::r#type),
+            Some(&RecordType::TSIG)
+        );
+
+        // A MAC that does not verify never reaches the access lists
+        let last = wire.len() - 10;
+        wire[last] ^= 0xff;
+        let response = respond(&StaticHandler, &access, &wire, source(), Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::NotAuth);
+        assert!(response.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn recursion_is_allowed_by_access_list() {
+        /// Answers REFUSED unless recursion is allowed.
+        struct Recursive;
+
+        #[async_trait]
+        impl RequestHandler for Recursive {
+            async fn handle(&self, request: &Request) -> Option<Message> {
+                let response_code = match request.recursion_allowed() {
+                    true => ResponseCode::NoError,
+                    false => ResponseCode::Refused,
+                };
+
+                request.reply(response_code).build().ok()
+            }
+        }
+
+        let wire = Bytes::from(&query());
+        let access = AccessControl::default();
+        let local = SocketAddr::from(([127, 0, 0, 1], 5353));
+
+        let response = respond(&Recursive, &access, &wire, local, Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::NoError);
+
+        let response = respond(&Recursive, &access, &wire, source(), Protocol::Udp)
+            .await
+            .unwrap();
+        assert_eq!(response.response_code(), &ResponseCode::Refused);
+    }
+
+    #[tokio::test]
+    async fn responses_and_runts_are_dropped() {
+        let mut wire = Bytes::from(&query()).to_vec();
+        wire[2] |= 0x80;
+
+        assert!(respond(
+            &StaticHandler,
+            &AccessControl::default(),
+            &wire,
+            source(),
+            Protocol::Udp
+        )
+        .await
+        .is_none());
+        assert!(respond(
+            &StaticHandler,
+            &AccessControl::default(),
+            &wire[..5],
+            source(),
+            Protocol::Udp
+        )
+        .await
+        .is_none());
+    }
+
+    fn query_id(wire: &[u8]) -> u16 {
+        u16::from_be_bytes([wire[0], wire[1]])
+    }
+}
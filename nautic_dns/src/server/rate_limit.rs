@@ -0,0 +1,382 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    num::NonZeroUsize,
+    sync::Mutex,
+};
+
+use derive_builder::Builder;
+use lru::LruCache;
+use tokio::time::Instant;
+
+use super::acl::unmapped;
+use crate::protocol::{LabelSequence, Message, RecordType, ResponseCode};
+
+#[derive(Debug, Clone, Builder)]
+pub struct RateLimitConfig {
+    /// Identical responses sent to one client network each second. Short bursts up
+    /// to this many are let through at once.
+    #[builder(default = "5")]
+    responses_per_second: u32,
+
+    /// Every this many responses over the limit, one is sent truncated instead of
+    /// dropped, so legitimate clients retry over TCP. Zero drops them all.
+    #[builder(default = "2")]
+    slip: u32,
+
+    /// Clients are grouped into networks of this prefix length.
+    #[builder(default = "24")]
+    ipv4_prefix: u8,
+
+    #[builder(default = "56")]
+    ipv6_prefix: u8,
+
+    /// Only log which responses would be limited, and send them all anyway.
+    #[builder(default)]
+    log_only: bool,
+
+    /// Client networks tracked at once. Beyond this, the least recently seen are
+    /// forgotten.
+    #[builder(default = "100_000")]
+    max_entries: usize,
+}
+
+impl RateLimitConfig {
+    pub fn responses_per_second(&self) -> u32 {
+        self.responses_per_second
+    }
+    pub fn slip(&self) -> u32 {
+        self.slip
+    }
+    pub fn ipv4_prefix(&self) -> u8 {
+        self.ipv4_prefix
+    }
+    pub fn ipv6_prefix(&self) -> u8 {
+        self.ipv6_prefix
+    }
+    pub fn log_only(&self) -> bool {
+        self.log_only
+    }
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfigBuilder::default()
+            .build()
+            .expect("Rate limit config has defaults for every field")
+    }
+}
+
+/// What to do with a response about to be sent over UDP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Send,
+    /// Send it truncated, so the client retries over TCP.
+    Slip,
+    Drop,
+}
+
+/// Responses are counted separately for each kind, as attackers tend to repeat
+/// one of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ResponseKind {
+    Answer,
+    Referral,
+    NoData,
+    NxDomain,
+    Error,
+}
+
+impl fmt::Display for ResponseKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResponseKind::Answer => write!(f, "answer"),
+            ResponseKind::Referral => write!(f, "referral"),
+            ResponseKind::NoData => write!(f, "NODATA"),
+            ResponseKind::NxDomain => write!(f, "NXDOMAIN"),
+            ResponseKind::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Key {
+    network: IpAddr,
+    kind: ResponseKind,
+    name: LabelSequence,
+}
+
+/// A token bucket refilled at the configured rate.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Responses over the limit since the bucket last had room.
+    limited: u32,
+}
+
+/// Response rate limiting (RRL), which keeps the server from being used to reflect
+/// and amplify traffic at a spoofed source address.
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<LruCache<Key, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimitConfig) -> Self {
+        let capacity = NonZeroUsize::new(config.max_entries()).unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            buckets: Mutex::new(LruCache::new(capacity)),
+            config,
+        }
+    }
+
+    /// Counts `response` against what `client` has been sent lately.
+    pub(crate) fn check(&self, client: IpAddr, response: &Message) -> Verdict {
+        let key = key(&self.config, client, response);
+        let rate = self.config.responses_per_second() as f64;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.get_or_insert_mut(key.clone(), || Bucket {
+            tokens: rate,
+            updated: now,
+            limited: 0,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.limited = 0;
+            return Verdict::Send;
+        }
+
+        bucket.limited = bucket.limited.saturating_add(1);
+        if bucket.limited == 1 {
+            let action = match self.config.log_only() {
+                true => "Would rate limit",
+                false => "Rate limiting",
+            };
+
+            log::info!(
+                "{action} {} responses for {} to {}",
+                key.kind,
+                key.name,
+                key.network
+            );
+        }
+
+        let slip = self.config.slip();
+        match self.config.log_only() {
+            true => Verdict::Send,
+            false if slip > 0 && bucket.limited % slip == 0 => Verdict::Slip,
+            false => Verdict::Drop,
+        }
+    }
+}
+
+/// Groups `response` with the others `client` is sent that an attacker could
+/// repeat. Negative answers are grouped by zone, so random names in one zone count
+/// together.
+fn key(config: &RateLimitConfig, client: IpAddr, response: &Message) -> Key {
+    let network = match unmapped(client) {
+        IpAddr::V4(address) => {
+            let mask = u32::MAX.checked_shl(32 - config.ipv4_prefix().min(32) as u32);
+            IpAddr::V4(Ipv4Addr::from(u32::from(address) & mask.unwrap_or(0)))
+        }
+        IpAddr::V6(address) => {
+            let mask = u128::MAX.checked_shl(128 - config.ipv6_prefix().min(128) as u32);
+            IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask.unwrap_or(0)))
+        }
+    };
+
+    let query_name = response
+        .question()
+        .map(|question| question.name().to_lowercase())
+        .unwrap_or_else(LabelSequence::root);
+    let owner = |r#type: RecordType| {
+        response
+            .authorities()
+            .iter()
+            .find(|record| record.r#type() == &r#type)
+            .map(|record| record.name.to_lowercase())
+    };
+
+    let (kind, name) = match response.response_code() {
+        ResponseCode::NoError if !response.answers().is_empty() => {
+            (ResponseKind::Answer, query_name)
+        }
+        ResponseCode::NoError => match (owner(RecordType::SOA), owner(RecordType::NS)) {
+            (None, Some(cut)) => (ResponseKind::Referral, cut),
+            (zone, _) => (ResponseKind::NoData, zone.unwrap_or(query_name)),
+        },
+        ResponseCode::NoDomain => (
+            ResponseKind::NxDomain,
+            owner(RecordType::SOA).unwrap_or(query_name),
+        ),
+        _ => (ResponseKind::Error, LabelSequence::root()),
+    };
+
+    Key {
+        network,
+        kind,
+        name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::protocol::{
+        Class, FlagsBuilder, HeaderBuilder, MessageBuilder, MessageType, Query, Record, RecordData,
+        StartOfAuthority,
+    };
+
+    fn response(name: &str, response_code: ResponseCode, authorities: Vec<Record>) -> Message {
+        let flags = FlagsBuilder::default()
+            .message_type(MessageType::Response)
+            .response(response_code)
+            .build()
+            .unwrap();
+
+        MessageBuilder::default()
+            .header(HeaderBuilder::default().flags(flags).build().unwrap())
+            .questions(vec![Query::new(
+                LabelSequence::new(name),
+                RecordType::A,
+                Class::IN,
+            )])
+            .authorities(authorities)
+            .build()
+            .unwrap()
+    }
+
+    fn soa(zone: &str) -> Record {
+        let soa = StartOfAuthority {
+            mname: LabelSequence::new("ns.example"),
+            rname: LabelSequence::new("host.example"),
+            serial: 1,
+            refresh: 2,
+            retry: 3,
+            expire: 4,
+            minimum: 5,
+        };
+
+        Record::new(
+            LabelSequence::new(zone),
+            Class::IN,
+            60,
+            RecordData::SOA(soa),
+        )
+    }
+
+    fn limiter(mut config: RateLimitConfigBuilder) -> RateLimiter {
+        RateLimiter::new(config.responses_per_second(2).build().unwrap())
+    }
+
+    fn client(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(198, 51, 100, last))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn responses_over_rate_are_slipped_and_dropped() {
+        let limiter = limiter(RateLimitConfigBuilder::default());
+        let nxdomain = |name: &str| response(name, ResponseCode::NoDomain, vec![soa("example")]);
+
+        // Random names in one zone from one network share a bucket
+        let verdicts = (0..6u8)
+            .map(|i| limiter.check(client(i), &nxdomain(&format!("r{i}.example"))))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Send,
+                Verdict::Send,
+                Verdict::Drop,
+                Verdict::Slip,
+                Verdict::Drop,
+                Verdict::Slip
+            ]
+        );
+
+        // Other clients and other kinds of responses are counted separately
+        assert_eq!(
+            limiter.check(
+                IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1)),
+                &nxdomain("x.example")
+            ),
+            Verdict::Send
+        );
+        assert_eq!(
+            limiter.check(
+                client(1),
+                &response("x.example", ResponseCode::ServerFailure, vec![])
+            ),
+            Verdict::Send
+        );
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(
+            limiter.check(client(1), &nxdomain("y.example")),
+            Verdict::Send
+        );
+        assert_eq!(
+            limiter.check(client(1), &nxdomain("y.example")),
+            Verdict::Drop
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn mapped_ipv4_clients_are_grouped_by_ipv4_network() {
+        let limiter = limiter(RateLimitConfigBuilder::default());
+        let answer = response("www.example", ResponseCode::NoError, vec![]);
+        let mapped = |address: Ipv4Addr| IpAddr::V6(address.to_ipv6_mapped());
+
+        let abusive = mapped(Ipv4Addr::new(198, 51, 100, 1));
+        for _ in 0..4 {
+            limiter.check(abusive, &answer);
+        }
+        assert_eq!(limiter.check(abusive, &answer), Verdict::Drop);
+
+        // Another IPv4 network behind the same dual-stack socket is unaffected
+        let other = mapped(Ipv4Addr::new(203, 0, 113, 1));
+        assert_eq!(limiter.check(other, &answer), Verdict::Send);
+
+        // And the mapped client shares its bucket with its plain IPv4 form
+        assert_eq!(limiter.check(client(2), &answer), Verdict::Slip);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn log_only_sends_everything() {
+        let mut config = RateLimitConfigBuilder::default();
+        config.log_only(true);
+        let limiter = limiter(config);
+        let answer = response("www.example", ResponseCode::NoError, vec![]);
+
+        for _ in 0..10 {
+            assert_eq!(limiter.check(client(1), &answer), Verdict::Send);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_slip_drops_everything() {
+        let mut config = RateLimitConfigBuilder::default();
+        config.slip(0);
+        let limiter = limiter(config);
+        let answer = response("www.example", ResponseCode::NoError, vec![]);
+
+        let verdicts = (0..6)
+            .map(|_| limiter.check(client(1), &answer))
+            .collect::<Vec<_>>();
+        assert_eq!(verdicts[2..], [Verdict::Drop; 4]);
+    }
+}
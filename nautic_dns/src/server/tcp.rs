@@ -0,0 +1,273 @@
+use std::{io, sync::Arc, time::Duration};
+
+use tokio::{
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    sync::{Mutex, Semaphore},
+    time,
+};
+
+use super::{respond, AccessControl, RequestHandler};
+use crate::{
+    errors::NauticDnsError,
+    protocol::Message,
+    transport::{read_frame, write_message, Protocol},
+};
+
+/// Pause after failing to accept a connection for lack of resources, so the
+/// listener does not spin while file descriptors or buffers run out.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Accepts connections on `listener` for as long as the server runs. While
+/// `max_connections` are open no further ones are accepted, which leaves them
+/// waiting in the listen backlog. Each connection answers up to `max_requests`
+/// queries at once.
+pub(crate) async fn serve<H: RequestHandler>(
+    listener: TcpListener,
+    handler: Arc<H>,
+    access: Arc<AccessControl>,
+    max_connections: usize,
+    max_requests: usize,
+    idle_timeout: Duration,
+) -> Result<(), NauticDnsError> {
+    let connections = Arc::new(Semaphore::new(max_connections));
+
+    loop {
+        let permit = match connections.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                log::warn!("Reached {max_connections} TCP connections, waiting for one to end");
+                connections
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .expect("The semaphore is never closed")
+            }
+        };
+
+        let stream = accept(&listener).await;
+        let handler = handler.clone();
+        let access = access.clone();
+        tokio::spawn(async move {
+            serve_connection(stream, handler, access, max_requests, idle_timeout).await;
+            drop(permit);
+        });
+    }
+}
+
+/// Answers the queries pipelined on one connection (RFC 7766 section 6.2.1). Each
+/// query is answered as soon as it is ready, so responses may leave out of order.
+/// While `max_requests` queries are being answered no further ones are read, which
+/// leaves the client to wait on TCP flow control. The connection is closed once
+/// no query arrives for `idle_timeout`.
+async fn serve_connection<H: RequestHandler>(
+    stream: TcpStream,
+    handler: Arc<H>,
+    access: Arc<AccessControl>,
+    max_requests: usize,
+    idle_timeout: Duration,
+) {
+    let Ok(source) = stream.peer_addr() else {
+        return;
+    };
+
+    let _ = stream.set_nodelay(true);
+    let (mut reader, writer) = stream.into_split();
+    let writer = Arc::new(Mutex::new(writer));
+    let requests = Arc::new(Semaphore::new(max_requests.max(1)));
+
+    loop {
+        let Ok(permit) = requests.clone().acquire_owned().await else {
+            return;
+        };
+
+        let Ok(Ok(Some(query))) = time::timeout(idle_timeout, read_frame(&mut reader)).await else {
+            return;
+        };
+
+        let handler = handler.clone();
+        let access = access.clone();
+        let writer = writer.clone();
+
+        tokio::spawn(async move {
+            let response = respond(handler.as_ref(), &access, &query, source, Protocol::Tcp).await;
+            if let Some(response) = response {
+                send(&writer, &response).await;
+            }
+
+            drop(permit);
+        });
+    }
+}
+
+/// Waits for the next connection on `listener`. Errors such as running out of file
+/// descriptors or a client aborting before it was accepted are logged and
+/// retried, as they never concern the listener itself.
+async fn accept(listener: &TcpListener) -> TcpStream {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => return stream,
+            Err(error) => {
+                log::warn!("Failed to accept a TCP connection: {error}");
+                if let Some(pause) = backoff(&error) {
+                    time::sleep(pause).await;
+                }
+            }
+        }
+    }
+}
+
+/// How long to wait before accepting again after `error`. Errors about a single
+/// connection are retried at once, anything else after [`ACCEPT_BACKOFF`].
+fn backoff(error: &io::Error) -> Option<Duration> {
+    match error.kind() {
+        io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::Interrupted => None,
+        _ => Some(ACCEPT_BACKOFF),
+    }
+}
+
+async fn send(writer: &Mutex<OwnedWriteHalf>, response: &Message) {
+    // The client may have closed the connection already
+    let _ = write_message(&mut *writer.lock().await, response).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use async_trait::async_trait;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::{
+        protocol::{Class, LabelSequence, Query, RecordType, ResponseCode},
+        server::{DnsServer, Request, ServerConfigBuilder},
+        transport::read_message,
+    };
+
+    /// Answers queries for `slow.example` after a second and everything else at once.
+    struct DelayHandler;
+
+    #[async_trait]
+    impl RequestHandler for DelayHandler {
+        async fn handle(&self, request: &Request) -> Option<Message> {
+            let name = request.message().question()?.name().label().to_owned();
+            if name == "slow.example" {
+                time::sleep(Duration::from_secs(1)).await;
+            }
+
+            request.reply(ResponseCode::NoError).build().ok()
+        }
+    }
+
+    async fn spawn_server(max_connections: usize, idle_timeout: Duration) -> SocketAddr {
+        let config = ServerConfigBuilder::default()
+            .listen(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .max_tcp_connections(max_connections)
+            .tcp_idle_timeout(idle_timeout)
+            .build()
+            .unwrap();
+
+        let server = DnsServer::bind(config, DelayHandler).await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        address
+    }
+
+    fn query(name: &str) -> Message {
+        let query = Query::new(LabelSequence::new(name), RecordType::A, Class::IN);
+        Message::query(query, false)
+    }
+
+    #[tokio::test]
+    async fn pipelined_queries_are_answered_as_they_complete() {
+        let server = spawn_server(8, Duration::from_secs(10)).await;
+        let mut stream = TcpStream::connect(server).await.unwrap();
+
+        let slow = query("slow.example");
+        let fast = query("fast.example");
+        write_message(&mut stream, &slow).await.unwrap();
+        write_message(&mut stream, &fast).await.unwrap();
+
+        let first = read_message(&mut stream).await.unwrap().unwrap();
+        let second = read_message(&mut stream).await.unwrap().unwrap();
+
+        assert_eq!(first.header().id(), fast.header().id());
+        assert_eq!(second.header().id(), slow.header().id());
+    }
+
+    #[tokio::test]
+    async fn queries_beyond_limit_wait_for_earlier_answers() {
+        let config = ServerConfigBuilder::default()
+            .listen(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .max_tcp_requests(1)
+            .build()
+            .unwrap();
+        let server = DnsServer::bind(config, DelayHandler).await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let slow = query("slow.example");
+        let fast = query("fast.example");
+        write_message(&mut stream, &slow).await.unwrap();
+        write_message(&mut stream, &fast).await.unwrap();
+
+        let first = read_message(&mut stream).await.unwrap().unwrap();
+        let second = read_message(&mut stream).await.unwrap().unwrap();
+
+        assert_eq!(first.header().id(), slow.header().id());
+        assert_eq!(second.header().id(), fast.header().id());
+    }
+
+    #[tokio::test]
+    async fn idle_connection_is_closed() {
+        let server = spawn_server(8, Duration::from_millis(100)).await;
+        let mut stream = TcpStream::connect(server).await.unwrap();
+
+        let mut buffer = [0u8; 1];
+        let read = time::timeout(Duration::from_secs(2), stream.read(&mut buffer))
+            .await
+            .unwrap();
+
+        assert_eq!(read.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn connections_beyond_limit_wait_for_one_to_end() {
+        let server = spawn_server(1, Duration::from_secs(10)).await;
+        let mut first = TcpStream::connect(server).await.unwrap();
+        write_message(&mut first, &query("fast.example"))
+            .await
+            .unwrap();
+        assert!(read_message(&mut first).await.unwrap().is_some());
+
+        let mut second = TcpStream::connect(server).await.unwrap();
+        write_message(&mut second, &query("fast.example"))
+            .await
+            .unwrap();
+        let waiting = time::timeout(Duration::from_millis(300), read_message(&mut second)).await;
+        assert!(waiting.is_err());
+
+        drop(first);
+        let response = time::timeout(Duration::from_secs(2), read_message(&mut second))
+            .await
+            .unwrap();
+        assert!(response.unwrap().is_some());
+    }
+
+    #[test]
+    fn accept_errors_are_retried() {
+        let retried = |kind| backoff(&io::Error::from(kind));
+
+        assert_eq!(retried(io::ErrorKind::ConnectionAborted), None);
+        assert_eq!(retried(io::ErrorKind::ConnectionReset), None);
+        assert_eq!(retried(io::ErrorKind::OutOfMemory), Some(ACCEPT_BACKOFF));
+
+        // EMFILE, out of file descriptors
+        let error = io::Error::from_raw_os_error(24);
+        assert_eq!(backoff(&error), Some(ACCEPT_BACKOFF));
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Sha512};
+
+use crate::protocol::{
+    ByteScanner, Class, LabelSequence, Message, Record, RecordData, RecordType,
+    TransactionSignature,
+};
+
+/// Seconds of clock difference allowed between client and server (RFC 8945
+/// section 10).
+const FUDGE: u16 = 300;
+
+/// TSIG errors reported back to the client (RFC 8945 section 3).
+const BADSIG: u16 = 16;
+const BADKEY: u16 = 17;
+const BADTIME: u16 = 18;
+
+/// MAC algorithms TSIG keys may use (RFC 8945 section 6).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum TsigAlgorithm {
+    #[default]
+    HmacSha256,
+    HmacSha512,
+}
+
+impl TsigAlgorithm {
+    /// The name identifying this algorithm in TSIG records.
+    pub fn name(&self) -> LabelSequence {
+        match self {
+            TsigAlgorithm::HmacSha256 => LabelSequence::new("hmac-sha256"),
+            TsigAlgorithm::HmacSha512 => LabelSequence::new("hmac-sha512"),
+        }
+    }
+
+    fn mac(&self, secret: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            TsigAlgorithm::HmacSha256 => compute::<Hmac<Sha256>>(secret, data),
+            TsigAlgorithm::HmacSha512 => compute::<Hmac<Sha512>>(secret, data),
+        }
+    }
+
+    /// Compares in constant time, so the MAC cannot be guessed byte by byte.
+    fn verify(&self, secret: &[u8], data: &[u8], mac: &[u8]) -> bool {
+        match self {
+            TsigAlgorithm::HmacSha256 => check::<Hmac<Sha256>>(secret, data, mac),
+            TsigAlgorithm::HmacSha512 => check::<Hmac<Sha512>>(secret, data, mac),
+        }
+    }
+}
+
+fn compute<M: Mac + hmac::digest::KeyInit>(secret: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn check<M: Mac + hmac::digest::KeyInit>(secret: &[u8], data: &[u8], expected: &[u8]) -> bool {
+    let mut mac = <M as Mac>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(data);
+    mac.verify_slice(expected).is_ok()
+}
+
+/// A secret shared with the clients allowed to sign their requests with it. Access
+/// lists refer to keys by name.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TsigKey {
+    name: LabelSequence,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl TsigKey {
+    pub fn new(name: LabelSequence, algorithm: TsigAlgorithm, secret: Vec<u8>) -> Self {
+        Self {
+            name,
+            algorithm,
+            secret,
+        }
+    }
+
+    pub fn name(&self) -> &LabelSequence {
+        &self.name
+    }
+    pub fn algorithm(&self) -> TsigAlgorithm {
+        self.algorithm
+    }
+
+    /// Whether `tsig` claims to be made with this key.
+    fn matches(&self, name: &LabelSequence, tsig: &TransactionSignature) -> bool {
+        self.name.eq_ignore_case(name) && self.algorithm.name().eq_ignore_case(&tsig.algorithm)
+    }
+}
+
+// Keeps the secret out of logged configurations
+impl fmt::Debug for TsigKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TsigKey")
+            .field("name", &self.name)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A request whose signature verified, which its response has to be signed for.
+#[derive(Debug, Clone)]
+pub(crate) struct SignedRequest {
+    key: TsigKey,
+    mac: Vec<u8>,
+}
+
+impl SignedRequest {
+    pub(crate) fn key(&self) -> &TsigKey {
+        &self.key
+    }
+
+    /// Signs `response` with the request's key, covering the request MAC too (RFC
+    /// 8945 section 5.3).
+    pub(crate) fn sign(&self, response: Message) -> Message {
+        sign(response, &self.key, Some(&self.mac), now())
+    }
+}
+
+/// Checks the TSIG record ending the raw message `query` against `keys` (RFC 8945
+/// section 5.2). Returns `None` for unsigned requests and the verified request
+/// otherwise. Requests with an unknown key, a wrong MAC or a time signed too far
+/// off `now` are rejected with the response from `notauth`, carrying a TSIG record
+/// with the error.
+pub(crate) fn verify(
+    keys: &[TsigKey],
+    query: &[u8],
+    notauth: impl Fn() -> Message,
+    now: u64,
+) -> Result<Option<SignedRequest>, Message> {
+    let Some((start, record)) = find_tsig(query) else {
+        return Ok(None);
+    };
+    let RecordData::TSIG(tsig) = record.data() else {
+        return Ok(None);
+    };
+
+    // Errors about signatures that cannot be checked go back unsigned
+    let unsigned = |error: u16| {
+        log::debug!(
+            "Rejecting request signed with {}: TSIG error {error}",
+            record.name
+        );
+
+        let tsig = TransactionSignature {
+            mac: vec![],
+            error,
+            other: vec![],
+            ..tsig.clone()
+        };
+        notauth().with_additional(tsig_record(&record.name, tsig))
+    };
+
+    let Some(key) = keys.iter().find(|key| key.matches(&record.name, tsig)) else {
+        return Err(unsigned(BADKEY));
+    };
+
+    // The MAC covers the message as it was before signing
+    let mut data = BytesMut::from(&query[..start]);
+    data[..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+    let additionals = u16::from_be_bytes([data[10], data[11]]) - 1;
+    data[10..12].copy_from_slice(&additionals.to_be_bytes());
+    data.put_slice(&variables(&key.name, tsig));
+
+    if !key.algorithm.verify(&key.secret, &data, &tsig.mac) {
+        return Err(unsigned(BADSIG));
+    }
+
+    if now.abs_diff(tsig.time_signed) > tsig.fudge as u64 {
+        log::debug!(
+            "Rejecting request signed with {}: TSIG error {BADTIME}",
+            key.name
+        );
+
+        // Signed, so the client can trust the server time in it
+        let error = TransactionSignature {
+            mac: vec![],
+            error: BADTIME,
+            other: now.to_be_bytes()[2..].to_vec(),
+            ..tsig.clone()
+        };
+        return Err(sign_with(notauth(), key, error, Some(&tsig.mac)));
+    }
+
+    Ok(Some(SignedRequest {
+        key: key.clone(),
+        mac: tsig.mac.clone(),
+    }))
+}
+
+/// Finds the last record of the raw message `query` and where it starts, when it
+/// is a TSIG record. The other records are skipped without decoding their RDATA.
+fn find_tsig(query: &[u8]) -> Option<(usize, Record)> {
+    let count = |at: usize| u16::from_be_bytes([query[at], query[at + 1]]) as usize;
+    if query.len() < 12 || count(10) == 0 {
+        return None;
+    }
+
+    let mut position = 12;
+    for _ in 0..count(4) {
+        position += LabelSequence::try_scan(query, position).ok()?.total_bytes() + 4;
+    }
+
+    for _ in 1..count(6) + count(8) + count(10) {
+        position += LabelSequence::try_scan(query, position).ok()?.total_bytes();
+        let length = query.get(position + 8..position + 10)?;
+        position += 10 + u16::from_be_bytes([length[0], length[1]]) as usize;
+    }
+
+    let record = Record::try_scan(query, position).ok()?;
+    let ends_message = position + record.total_bytes() == query.len();
+
+    (ends_message && record.value().r#type() == &RecordType::TSIG)
+        .then(|| (position, record.value().clone()))
+}
+
+/// Signs `message` with `key` at `time`, as the response to a request signed with
+/// `request_mac`, or as a request when there is none.
+pub(crate) fn sign(
+    message: Message,
+    key: &TsigKey,
+    request_mac: Option<&[u8]>,
+    time: u64,
+) -> Message {
+    let tsig = TransactionSignature {
+        algorithm: key.algorithm.name(),
+        time_signed: time,
+        fudge: FUDGE,
+        mac: vec![],
+        original_id: message.header().id(),
+        error: 0,
+        other: vec![],
+    };
+
+    sign_with(message, key, tsig, request_mac)
+}
+
+/// Adds `tsig` to the end of `message`, with the MAC filled in.
+fn sign_with(
+    message: Message,
+    key: &TsigKey,
+    mut tsig: TransactionSignature,
+    request_mac: Option<&[u8]>,
+) -> Message {
+    let mut data = BytesMut::new();
+    if let Some(request_mac) = request_mac {
+        data.put_u16(request_mac.len() as u16);
+        data.put_slice(request_mac);
+    }
+    data.put_slice(&Bytes::from(&message));
+    data.put_slice(&variables(&key.name, &tsig));
+
+    tsig.mac = key.algorithm.mac(&key.secret, &data);
+    message.with_additional(tsig_record(&key.name, tsig))
+}
+
+/// The TSIG fields covered by the MAC besides the message itself (RFC 8945 section
+/// 4.3.3), with names in canonical form.
+fn variables(name: &LabelSequence, tsig: &TransactionSignature) -> Bytes {
+    let mut buffer = BytesMut::new();
+    buffer.put_slice(&Bytes::from(&name.to_lowercase()));
+    buffer.put_u16(u16::from(Class::Any));
+    buffer.put_u32(0);
+    buffer.put_slice(&Bytes::from(&tsig.algorithm.to_lowercase()));
+    buffer.put_slice(&tsig.time_signed.to_be_bytes()[2..]);
+    buffer.put_u16(tsig.fudge);
+    buffer.put_u16(tsig.error);
+    buffer.put_u16(tsig.other.len() as u16);
+    buffer.put_slice(&tsig.other);
+
+    buffer.freeze()
+}
+
+fn tsig_record(name: &LabelSequence, tsig: TransactionSignature) -> Record {
+    Record::new(name.clone(), Class::Any, 0, RecordData::TSIG(tsig))
+}
+
+/// Seconds since the epoch, as TSIG records carry the time.
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{protocol::Query, protocol::ResponseCode, server::error_response};
+
+    const TIME: u64 = 1_700_000_000;
+
+    fn key() -> TsigKey {
+        TsigKey::new(
+            LabelSequence::new("transfer.key"),
+            TsigAlgorithm::HmacSha256,
+            b"not so secret".to_vec(),
+        )
+    }
+
+    fn query() -> Message {
+        let query = Query::new(LabelSequence::new("www.example"), RecordType::A, Class::IN);
+        Message::query(query, true)
+    }
+
+    fn notauth() -> Message {
+        error_response(&Bytes::from(&query()), ResponseCode::NotAuth)
+    }
+
+    fn tsig(message: &Message) -> TransactionSignature {
+        match message.additionals().last().map(Record::data) {
+            Some(RecordData::TSIG(tsig)) => tsig.clone(),
+            _ => panic!("expected a TSIG record"),
+        }
+    }
+
+    #[test]
+    fn signed_request_verifies_and_response_is_signed_back() {
+        let request = sign(query(), &key(), None, TIME);
+        let wire = Bytes::from(&request);
+
+        let signed = verify(&[key()], &wire, notauth, TIME + 10)
+            .unwrap()
+            .unwrap();
+        assert_eq!(signed.key().name(), key().name());
+
+        // The client checks the response against the MAC of its request
+        let response = error_response(&wire, ResponseCode::NoError);
+        let request_mac = tsig(&request).mac;
+        let signature = tsig(&signed.sign(response.clone()));
+
+        let mut data = BytesMut::new();
+        data.put_u16(request_mac.len() as u16);
+        data.put_slice(&request_mac);
+        data.put_slice(&Bytes::from(&response));
+        data.put_slice(&variables(key().name(), &signature));
+        assert!(key()
+            .algorithm()
+            .verify(&key().secret, &data, &signature.mac));
+    }
+
+    #[test]
+    fn unsigned_request_passes_through() {
+        let wire = Bytes::from(&query());
+
+        assert!(verify(&[key()], &wire, notauth, TIME).unwrap().is_none());
+    }
+
+    #[test]
+    fn bad_signatures_are_rejected() {
+        let mut wire = Bytes::from(&sign(query(), &key(), None, TIME)).to_vec();
+        let error = |response: Message| {
+            assert_eq!(response.response_code(), &ResponseCode::NotAuth);
+            let tsig = tsig(&response);
+            (tsig.error, !tsig.mac.is_empty())
+        };
+
+        let response = verify(&[], &wire, notauth, TIME).unwrap_err();
+        assert_eq!(error(response), (BADKEY, false));
+
+        let other = TsigKey::new(key().name().clone(), TsigAlgorithm::HmacSha256, vec![1]);
+        let response = verify(&[other], &wire, notauth, TIME).unwrap_err();
+        assert_eq!(error(response), (BADSIG, false));
+
+        // Only the time is off, so the server signs and tells its own
+        let response = verify(&[key()], &wire, notauth, TIME + 301).unwrap_err();
+        assert_eq!(error(response), (BADTIME, true));
+
+        // Tampering with the question breaks the MAC
+        wire[13] ^= 0x20;
+        let response = verify(&[key()], &wire, notauth, TIME).unwrap_err();
+        assert_eq!(error(response), (BADSIG, false));
+    }
+}
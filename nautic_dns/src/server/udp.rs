@@ -0,0 +1,262 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use bytes::Bytes;
+use tokio::{net::UdpSocket, sync::Semaphore};
+
+use super::{
+    rate_limit::{RateLimiter, Verdict},
+    respond, AccessControl, RequestHandler,
+};
+use crate::{
+    errors::NauticDnsError,
+    protocol::{FlagsBuilder, HeaderBuilder, Message, MessageBuilder},
+    transport::{Protocol, MAX_UDP_PAYLOAD},
+};
+
+/// Answers every datagram arriving on `socket`, each in its own task so one slow
+/// answer never holds up the others. Datagrams arriving while `max_requests` are
+/// being answered are dropped.
+pub(crate) async fn serve<H: RequestHandler>(
+    socket: Arc<UdpSocket>,
+    handler: Arc<H>,
+    access: Arc<AccessControl>,
+    limiter: Option<Arc<RateLimiter>>,
+    max_requests: usize,
+) -> Result<(), NauticDnsError> {
+    let requests = Arc::new(Semaphore::new(max_requests));
+    let mut buffer = vec![0u8; MAX_UDP_PAYLOAD];
+
+    loop {
+        // Errors such as ICMP port unreachable for an earlier response or a full
+        // send buffer only concern one datagram, never the socket
+        let (length, source) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(error) => {
+                log::debug!("Failed to receive a UDP query: {error}");
+                continue;
+            }
+        };
+
+        let Ok(permit) = requests.clone().try_acquire_owned() else {
+            continue;
+        };
+
+        let query = buffer[..length].to_vec();
+        let socket = socket.clone();
+        let handler = handler.clone();
+        let access = access.clone();
+        let limiter = limiter.clone();
+
+        tokio::spawn(async move {
+            let response = respond(handler.as_ref(), &access, &query, source, Protocol::Udp);
+            let Some(response) = response.await else {
+                return;
+            };
+
+            let verdict = match &limiter {
+                Some(limiter) => limiter.check(source.ip(), &response),
+                None => Verdict::Send,
+            };
+
+            match verdict {
+                Verdict::Send => send(&socket, &response, source).await,
+                Verdict::Slip => send(&socket, &truncated(&response), source).await,
+                Verdict::Drop => {}
+            }
+
+            drop(permit);
+        });
+    }
+}
+
+async fn send(socket: &UdpSocket, response: &Message, destination: SocketAddr) {
+    // The client may be gone already, which is no concern of the server
+    let _ = socket.send_to(&Bytes::from(response), destination).await;
+}
+
+/// Returns `response` with only its question left and the TC bit set.
+pub(crate) fn truncated(response: &Message) -> Message {
+    let header = response.header();
+    let flags = header.flags();
+    let flags = FlagsBuilder::default()
+        .message_type(flags.message_type().clone())
+        .op(flags.op().clone())
+        .authoritative_answer(flags.authoritative_answer())
+        .truncation(true)
+        .recursion_desired(flags.recursion_desired())
+        .recursion_available(flags.recursion_available())
+        .answer_authenticated(flags.answer_authenticated())
+        .non_authenticated_data(flags.non_authenticated_data())
+        .response(flags.response().clone())
+        .build()
+        .expect("Flags have every field set");
+
+    MessageBuilder::default()
+        .header(
+            HeaderBuilder::default()
+                .id(header.id())
+                .flags(flags)
+                .build()
+                .expect("Header has defaults for every field"),
+        )
+        .questions(response.questions().to_vec())
+        .build()
+        .expect("Message has every field set")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use async_trait::async_trait;
+    use tokio::time::{self, Duration};
+
+    use super::*;
+    use crate::{
+        protocol::{
+            ByteScanner, Class, LabelSequence, MessageType, Query, Record, RecordData, RecordType,
+            ResponseCode,
+        },
+        server::{DnsServer, Request, ServerConfigBuilder},
+    };
+
+    /// Answers with `count` A records for the queried name.
+    struct CountHandler(u8);
+
+    #[async_trait]
+    impl RequestHandler for CountHandler {
+        async fn handle(&self, request: &Request) -> Option<Message> {
+            let name = request.message().question()?.name().clone();
+            let answers = (0..self.0)
+                .map(|i| {
+                    Record::new(
+                        name.clone(),
+                        Class::IN,
+                        60,
+                        RecordData::A(Ipv4Addr::new(192, 0, 2, i)),
+                    )
+                })
+                .collect();
+
+            request
+                .reply(ResponseCode::NoError)
+                .answers(answers)
+                .build()
+                .ok()
+        }
+    }
+
+    async fn spawn_server(count: u8) -> SocketAddr {
+        let config = ServerConfigBuilder::default()
+            .listen(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .build()
+            .unwrap();
+
+        let server = DnsServer::bind(config, CountHandler(count)).await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        address
+    }
+
+    async fn ask(server: SocketAddr, query: &Message) -> Message {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.send_to(&Bytes::from(query), server).await.unwrap();
+
+        let mut buffer = vec![0u8; MAX_UDP_PAYLOAD];
+        let (length, _) = time::timeout(Duration::from_secs(2), socket.recv_from(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+
+        Message::try_scan(&buffer[..length], 0)
+            .unwrap()
+            .value()
+            .clone()
+    }
+
+    fn query() -> Message {
+        let query = Query::new(LabelSequence::new("www.example"), RecordType::A, Class::IN);
+        Message::query(query, true)
+    }
+
+    #[tokio::test]
+    async fn server_answers_over_udp() {
+        let server = spawn_server(2).await;
+
+        let request = query();
+        let response = ask(server, &request).await;
+
+        assert_eq!(response.header().id(), request.header().id());
+        assert_eq!(
+            response.header().flags().message_type(),
+            &MessageType::Response
+        );
+        assert_eq!(response.answers().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn oversized_response_is_truncated() {
+        let server = spawn_server(40).await;
+
+        let response = ask(server, &query()).await;
+
+        assert!(response.header().flags().truncation());
+        assert!(response.answers().is_empty());
+        assert_eq!(response.questions(), query().questions());
+    }
+
+    #[tokio::test]
+    async fn queries_beyond_limit_are_dropped() {
+        /// Takes a while over every answer.
+        struct SlowHandler;
+
+        #[async_trait]
+        impl RequestHandler for SlowHandler {
+            async fn handle(&self, request: &Request) -> Option<Message> {
+                time::sleep(Duration::from_millis(200)).await;
+                request.reply(ResponseCode::NoError).build().ok()
+            }
+        }
+
+        let config = ServerConfigBuilder::default()
+            .listen(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .max_udp_requests(1)
+            .build()
+            .unwrap();
+        let server = DnsServer::bind(config, SlowHandler).await.unwrap();
+        let address = server.local_addr().unwrap();
+        tokio::spawn(server.run());
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (first, second) = (query(), query());
+        for query in [&first, &second] {
+            socket.send_to(&Bytes::from(query), address).await.unwrap();
+        }
+
+        let mut buffer = vec![0u8; MAX_UDP_PAYLOAD];
+        let (length, _) = socket.recv_from(&mut buffer).await.unwrap();
+        let response = Message::try_scan(&buffer[..length], 0).unwrap();
+        assert_eq!(response.value().header().id(), first.header().id());
+
+        let late = time::timeout(Duration::from_millis(500), socket.recv_from(&mut buffer)).await;
+        assert!(late.is_err());
+    }
+
+    #[tokio::test]
+    async fn edns_client_gets_larger_response_and_opt() {
+        let server = spawn_server(40).await;
+
+        let request = MessageBuilder::default()
+            .header(query().header().clone())
+            .questions(query().questions().to_vec())
+            .additionals(vec![Record::edns(4096)])
+            .build()
+            .unwrap();
+        let response = ask(server, &request).await;
+
+        assert!(!response.header().flags().truncation());
+        assert_eq!(response.answers().len(), 40);
+        assert_eq!(response.udp_payload_size(), 1232);
+    }
+}
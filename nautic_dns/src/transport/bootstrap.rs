@@ -0,0 +1,197 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+use super::DnsTransport;
+use crate::{
+    errors::NauticDnsError,
+    protocol::{Class, LabelSequence, Message, Query, RecordData, RecordType},
+};
+
+type Connect = dyn Fn(SocketAddr) -> Arc<dyn DnsTransport> + Send + Sync;
+
+/// A transport to an upstream known by host name. The name is looked up through
+/// the `bootstrap` transports on first use, never through the system resolver, and
+/// looked up again after the upstream fails, in case it moved.
+pub struct BootstrapTransport {
+    host: String,
+    port: u16,
+    bootstrap: Vec<Arc<dyn DnsTransport>>,
+    connect: Box<Connect>,
+    transport: Mutex<Option<Arc<dyn DnsTransport>>>,
+}
+
+impl BootstrapTransport {
+    /// Reaches `host` on `port` through the transport `connect` builds for its
+    /// address. The bootstrap transports should be encrypted and reach their server
+    /// by IP address, or the lookup would leak the name in plaintext.
+    pub fn new(
+        host: impl Into<String>,
+        port: u16,
+        bootstrap: Vec<Arc<dyn DnsTransport>>,
+        connect: impl Fn(SocketAddr) -> Arc<dyn DnsTransport> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            bootstrap,
+            connect: Box::new(connect),
+            transport: Mutex::new(None),
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the transport to the upstream, looking its address up first when
+    /// there is none yet.
+    async fn transport(&self) -> Result<Arc<dyn DnsTransport>, NauticDnsError> {
+        let mut transport = self.transport.lock().await;
+        if let Some(transport) = transport.as_ref() {
+            return Ok(transport.clone());
+        }
+
+        let address = SocketAddr::new(self.lookup().await?, self.port);
+        log::debug!("Reaching {} at {address}", self.host);
+
+        let connected = (self.connect)(address);
+        *transport = Some(connected.clone());
+
+        Ok(connected)
+    }
+
+    /// Asks the bootstrap transports in turn for an IPv4 address of the host, then
+    /// for an IPv6 one.
+    async fn lookup(&self) -> Result<IpAddr, NauticDnsError> {
+        if self.bootstrap.is_empty() {
+            return Err(NauticDnsError::NoNameServers);
+        }
+
+        let name = LabelSequence::new(&self.host);
+        for r#type in [RecordType::A, RecordType::AAAA] {
+            let request = Message::query(Query::new(name.clone(), r#type, Class::IN), true);
+
+            for bootstrap in &self.bootstrap {
+                let Ok(response) = bootstrap.exchange(&request).await else {
+                    continue;
+                };
+
+                let address = response
+                    .answers()
+                    .iter()
+                    .find_map(|record| match record.data() {
+                        RecordData::A(address) => Some(IpAddr::V4(*address)),
+                        RecordData::AAAA(address) => Some(IpAddr::V6(*address)),
+                        _ => None,
+                    });
+
+                if let Some(address) = address {
+                    return Ok(address);
+                }
+            }
+        }
+
+        Err(NauticDnsError::NoUsableNameServers(self.host.clone()))
+    }
+}
+
+#[async_trait]
+impl DnsTransport for BootstrapTransport {
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        let transport = self.transport().await?;
+        let response = transport.exchange(request).await;
+
+        if response.is_err() {
+            self.transport.lock().await.take();
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, sync::Mutex as SyncMutex};
+
+    use super::*;
+    use crate::{
+        protocol::Record,
+        transport::{MockReply, MockTransport},
+    };
+
+    fn answer(address: Ipv4Addr) -> MockReply {
+        MockReply::answer(vec![Record::new(
+            LabelSequence::new("dns.example"),
+            Class::IN,
+            300,
+            RecordData::A(address),
+        )])
+    }
+
+    fn request() -> Message {
+        let query = Query::new(LabelSequence::new("www.example"), RecordType::A, Class::IN);
+        Message::query(query, true)
+    }
+
+    #[tokio::test]
+    async fn host_is_looked_up_through_bootstrap_and_again_after_failure() {
+        let unreachable = Arc::new(MockTransport::new(vec![]));
+        let bootstrap = Arc::new(
+            MockTransport::new(vec![answer(Ipv4Addr::new(192, 0, 2, 53))])
+                .with_fallback(answer(Ipv4Addr::new(192, 0, 2, 54))),
+        );
+        let upstream = Arc::new(MockTransport::new(vec![
+            MockReply::answer(vec![]),
+            MockReply::Fail,
+        ]));
+        let connected = Arc::new(SyncMutex::new(vec![]));
+
+        let transport = {
+            let connected = connected.clone();
+            BootstrapTransport::new(
+                "dns.example",
+                853,
+                vec![unreachable, bootstrap.clone()],
+                move |address| {
+                    connected.lock().unwrap().push(address);
+                    upstream.clone() as Arc<dyn DnsTransport>
+                },
+            )
+        };
+
+        assert!(transport.exchange(&request()).await.is_ok());
+        assert!(transport.exchange(&request()).await.is_err());
+        let _ = transport.exchange(&request()).await;
+
+        assert_eq!(
+            *connected.lock().unwrap(),
+            [
+                SocketAddr::from((Ipv4Addr::new(192, 0, 2, 53), 853)),
+                SocketAddr::from((Ipv4Addr::new(192, 0, 2, 54), 853)),
+            ]
+        );
+
+        let lookup = bootstrap.requests()[0].question().unwrap().clone();
+        assert_eq!(lookup.name().label(), "dns.example");
+        assert_eq!(lookup.r#type(), &RecordType::A);
+    }
+
+    #[tokio::test]
+    async fn host_without_address_fails() {
+        let bootstrap =
+            Arc::new(MockTransport::new(vec![]).with_fallback(MockReply::answer(vec![])));
+        let transport = BootstrapTransport::new("dns.example", 853, vec![bootstrap], |_| {
+            unreachable!("There is no address to connect to")
+        });
+
+        assert!(matches!(
+            transport.exchange(&request()).await,
+            Err(NauticDnsError::NoUsableNameServers(_))
+        ));
+    }
+}
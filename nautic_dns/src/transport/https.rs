@@ -0,0 +1,436 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    client::conn::http2::{self, SendRequest},
+    header, Request, StatusCode,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_rustls::{rustls::pki_types::ServerName, TlsConnector};
+use url::{form_urlencoded, Url};
+
+use super::{DnsTransport, TlsConfig, TlsConfigBuilder};
+use crate::{
+    errors::NauticDnsError,
+    protocol::{ByteScanner, Message},
+};
+
+/// Media type of DNS messages carried over HTTPS (RFC 8484 section 6).
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// How queries are encoded into DNS over HTTPS requests (RFC 8484 section 4.1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HttpMethod {
+    /// The query is sent base64url encoded in the `dns` query parameter, which lets
+    /// HTTP caches store the answer.
+    Get,
+    /// The query is sent as the request body.
+    #[default]
+    Post,
+}
+
+/// A DNS over HTTPS client for one `https://host/dns-query` endpoint. Every query is
+/// sent as its own stream over a single HTTP/2 connection, which is reopened
+/// whenever the server closes it.
+pub struct HttpsClient {
+    url: Url,
+    address: SocketAddr,
+    method: HttpMethod,
+    tls: TlsConfig,
+    connection: Mutex<Option<SendRequest<Full<Bytes>>>>,
+}
+
+impl HttpsClient {
+    /// Creates a client for `url`, verifying the server with `tls` when given, or
+    /// against the URL host and the bundled roots otherwise. No connection is made
+    /// until the first query.
+    ///
+    /// The host is never looked up, as that would go through the system resolver
+    /// in plaintext. A named host must come with the address to connect to in the
+    /// fragment, e.g. `https://dns.example/dns-query#addr=192.0.2.53`, or be looked
+    /// up through a [`BootstrapTransport`](super::BootstrapTransport) that calls
+    /// [`HttpsClient::with_address`].
+    pub fn new(
+        url: Url,
+        method: HttpMethod,
+        tls: Option<TlsConfig>,
+    ) -> Result<Self, NauticDnsError> {
+        let address = endpoint_address(&url)?;
+
+        Self::with_address(url, address, method, tls)
+    }
+
+    /// Like [`HttpsClient::new`], connecting to `address` whatever the URL host is.
+    pub fn with_address(
+        mut url: Url,
+        address: SocketAddr,
+        method: HttpMethod,
+        tls: Option<TlsConfig>,
+    ) -> Result<Self, NauticDnsError> {
+        if url.scheme() != "https" {
+            return Err(NauticDnsError::InvalidTarget(url.to_string()));
+        }
+
+        url.set_fragment(None);
+
+        let host = url
+            .host_str()
+            .ok_or_else(|| NauticDnsError::InvalidTarget(url.to_string()))?;
+
+        let tls = match tls {
+            Some(tls) => tls,
+            None => TlsConfigBuilder::default()
+                .server_name(host.trim_start_matches('[').trim_end_matches(']'))
+                .build()
+                .expect("TLS config has defaults for every other field"),
+        };
+
+        Ok(Self {
+            url,
+            address,
+            method,
+            tls,
+            connection: Mutex::new(None),
+        })
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Sends `request` and waits for its response. The id goes out as 0, as RFC 8484
+    /// recommends for cacheability, and is restored on the response.
+    pub async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        let id = request.header().id();
+        let wire = Bytes::from(&request.clone().with_id(0));
+
+        let http_request = match self.method {
+            HttpMethod::Get => {
+                let mut url = self.url.clone();
+                url.query_pairs_mut()
+                    .append_pair("dns", &URL_SAFE_NO_PAD.encode(&wire));
+
+                Request::get(url.as_str())
+                    .header(header::ACCEPT, DNS_MESSAGE)
+                    .body(Full::new(Bytes::new()))
+            }
+            HttpMethod::Post => Request::post(self.url.as_str())
+                .header(header::ACCEPT, DNS_MESSAGE)
+                .header(header::CONTENT_TYPE, DNS_MESSAGE)
+                .body(Full::new(wire)),
+        }
+        .map_err(|error| http_failure(error.to_string()))?;
+
+        let mut sender = self.sender().await?;
+        let response = sender
+            .send_request(http_request)
+            .await
+            .map_err(|error| http_failure(error.to_string()))?;
+
+        if response.status() != StatusCode::OK {
+            return Err(http_failure(format!(
+                "Server answered with status {}",
+                response.status()
+            )));
+        }
+
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|error| http_failure(error.to_string()))?
+            .to_bytes();
+
+        let response = Message::try_scan(&body, 0)?.value().clone();
+        if response.questions() != request.questions() {
+            return Err(http_failure("Response does not match the question".into()));
+        }
+
+        Ok(response.with_id(id))
+    }
+
+    /// Returns a handle to the open HTTP/2 connection, connecting first if there is
+    /// none or the previous one was closed.
+    async fn sender(&self) -> Result<SendRequest<Full<Bytes>>, NauticDnsError> {
+        let mut connection = self.connection.lock().await;
+        if let Some(sender) = connection.as_ref() {
+            if !sender.is_closed() {
+                return Ok(sender.clone());
+            }
+        }
+
+        let sender = self.connect().await?;
+        *connection = Some(sender.clone());
+
+        Ok(sender)
+    }
+
+    async fn connect(&self) -> Result<SendRequest<Full<Bytes>>, NauticDnsError> {
+        let server_name = ServerName::try_from(self.tls.server_name().to_owned())
+            .map_err(|_| NauticDnsError::InvalidTarget(self.tls.server_name().to_owned()))?;
+
+        let mut client_config = self.tls.client_config()?;
+        client_config.alpn_protocols = vec![b"h2".to_vec()];
+
+        let stream = TcpStream::connect(self.address).await?;
+        stream.set_nodelay(true)?;
+
+        let stream = TlsConnector::from(Arc::new(client_config))
+            .connect(server_name, stream)
+            .await?;
+
+        let (sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .map_err(|error| http_failure(error.to_string()))?;
+
+        tokio::spawn(connection);
+        Ok(sender)
+    }
+}
+
+#[async_trait]
+impl DnsTransport for HttpsClient {
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        HttpsClient::exchange(self, request).await
+    }
+}
+
+/// The address to reach the endpoint at `url` on: the host when it is an IP
+/// address, or else the `addr=` given in the fragment.
+pub(crate) fn endpoint_address(url: &Url) -> Result<SocketAddr, NauticDnsError> {
+    let invalid = || NauticDnsError::InvalidTarget(url.to_string());
+
+    let host = url.host_str().ok_or_else(invalid)?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let bootstrap = form_urlencoded::parse(url.fragment().unwrap_or_default().as_bytes())
+        .find(|(key, _)| key == "addr")
+        .map(|(_, address)| address.into_owned());
+
+    let ip = match bootstrap {
+        Some(address) => address.parse::<IpAddr>(),
+        None => host.parse::<IpAddr>(),
+    }
+    .map_err(|_| invalid())?;
+
+    Ok(SocketAddr::new(
+        ip,
+        url.port_or_known_default().unwrap_or(443),
+    ))
+}
+
+fn http_failure(reason: String) -> NauticDnsError {
+    NauticDnsError::ConnectionFailure(io::Error::new(io::ErrorKind::Other, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        convert::Infallible,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use http_body_util::Full;
+    use hyper::{
+        body::Incoming, server::conn::http2 as server_http2, service::service_fn, Method, Response,
+    };
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::{
+        protocol::{
+            Class, FlagsBuilder, HeaderBuilder, LabelSequence, MessageBuilder, MessageType, Query,
+            RecordType,
+        },
+        transport::tls::test_server_config,
+    };
+
+    struct TestServer {
+        address: SocketAddr,
+        certificate: Vec<u8>,
+        connections: Arc<AtomicUsize>,
+        methods: Arc<std::sync::Mutex<Vec<Method>>>,
+    }
+
+    async fn answer(
+        request: hyper::Request<Incoming>,
+        methods: Arc<std::sync::Mutex<Vec<Method>>>,
+    ) -> Result<Response<Full<Bytes>>, Infallible> {
+        methods.lock().unwrap().push(request.method().clone());
+
+        let wire = match *request.method() {
+            Method::GET => {
+                let url = Url::parse(&format!("https://localhost{}", request.uri())).unwrap();
+                let parameters = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+                Bytes::from(URL_SAFE_NO_PAD.decode(&parameters["dns"]).unwrap())
+            }
+            _ => request.into_body().collect().await.unwrap().to_bytes(),
+        };
+
+        let query = Message::try_scan(&wire, 0).unwrap().value().clone();
+        assert_eq!(query.header().id(), 0);
+
+        let flags = FlagsBuilder::default()
+            .message_type(MessageType::Response)
+            .build()
+            .unwrap();
+
+        let response = MessageBuilder::default()
+            .header(HeaderBuilder::default().id(0).flags(flags).build().unwrap())
+            .questions(query.questions().to_vec())
+            .build()
+            .unwrap();
+
+        Ok(Response::builder()
+            .header(header::CONTENT_TYPE, DNS_MESSAGE)
+            .body(Full::new(Bytes::from(response)))
+            .unwrap())
+    }
+
+    /// Runs a DNS over HTTPS server over HTTP/2 for `dns.example`.
+    async fn spawn_https_server() -> TestServer {
+        let (mut config, certificate, _) = test_server_config("dns.example");
+        config.alpn_protocols = vec![b"h2".to_vec()];
+
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let methods = Arc::new(std::sync::Mutex::new(vec![]));
+
+        tokio::spawn({
+            let connections = connections.clone();
+            let methods = methods.clone();
+
+            async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    connections.fetch_add(1, Ordering::SeqCst);
+
+                    let acceptor = acceptor.clone();
+                    let methods = methods.clone();
+                    tokio::spawn(async move {
+                        let stream = acceptor.accept(stream).await.unwrap();
+                        let service = service_fn(move |request| answer(request, methods.clone()));
+
+                        let _ = server_http2::Builder::new(TokioExecutor::new())
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await;
+                    });
+                }
+            }
+        });
+
+        TestServer {
+            address,
+            certificate,
+            connections,
+            methods,
+        }
+    }
+
+    fn client(server: &TestServer, method: HttpMethod) -> HttpsClient {
+        let tls = TlsConfigBuilder::default()
+            .server_name("dns.example")
+            .root_certificates(vec![server.certificate.clone()])
+            .build()
+            .unwrap();
+
+        let url = Url::parse(&format!("https://{}/dns-query", server.address)).unwrap();
+        HttpsClient::new(url, method, Some(tls)).unwrap()
+    }
+
+    fn request(name: &str) -> Message {
+        let query = Query::new(LabelSequence::new(name), RecordType::A, Class::IN);
+        Message::query(query, true)
+    }
+
+    #[tokio::test]
+    async fn post_query_restores_id() {
+        let server = spawn_https_server().await;
+        let client = client(&server, HttpMethod::Post);
+
+        let request = request("github.com");
+        let response = client.exchange(&request).await.unwrap();
+
+        assert_eq!(response.header().id(), request.header().id());
+        assert_eq!(response.questions(), request.questions());
+        assert_eq!(*server.methods.lock().unwrap(), vec![Method::POST]);
+    }
+
+    #[tokio::test]
+    async fn get_query_encodes_dns_parameter() {
+        let server = spawn_https_server().await;
+        let client = client(&server, HttpMethod::Get);
+
+        let response = client.exchange(&request("github.com")).await.unwrap();
+
+        assert_eq!(response.questions(), request("github.com").questions());
+        assert_eq!(*server.methods.lock().unwrap(), vec![Method::GET]);
+    }
+
+    #[tokio::test]
+    async fn queries_reuse_one_connection() {
+        let server = spawn_https_server().await;
+        let client = Arc::new(client(&server, HttpMethod::Post));
+
+        let lookups = (0..5)
+            .map(|i| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    client
+                        .exchange(&request(&format!("host{i}.github.com")))
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for lookup in lookups {
+            assert!(lookup.await.unwrap().is_ok());
+        }
+
+        assert_eq!(server.connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn named_host_connects_to_address_in_fragment() {
+        let server = spawn_https_server().await;
+        let tls = TlsConfigBuilder::default()
+            .server_name("dns.example")
+            .root_certificates(vec![server.certificate.clone()])
+            .build()
+            .unwrap();
+
+        let url = format!(
+            "https://dns.example:{}/dns-query#addr={}",
+            server.address.port(),
+            server.address.ip()
+        );
+        let client = HttpsClient::new(Url::parse(&url).unwrap(), HttpMethod::Post, Some(tls));
+        let client = client.unwrap();
+
+        assert_eq!(client.url().fragment(), None);
+        assert!(client.exchange(&request("github.com")).await.is_ok());
+    }
+
+    #[test]
+    fn plain_http_url_is_rejected() {
+        let url = Url::parse("http://dns.example/dns-query").unwrap();
+        assert!(HttpsClient::new(url, HttpMethod::Post, None).is_err());
+    }
+
+    #[test]
+    fn named_host_without_address_is_rejected() {
+        let url = Url::parse("https://dns.example/dns-query").unwrap();
+        assert!(HttpsClient::new(url, HttpMethod::Post, None).is_err());
+    }
+}
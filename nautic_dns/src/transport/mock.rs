@@ -0,0 +1,214 @@
+use std::{collections::VecDeque, io, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use tokio::time;
+
+use super::DnsTransport;
+use crate::{
+    errors::NauticDnsError,
+    protocol::{
+        FlagsBuilder, HeaderBuilder, Message, MessageBuilder, MessageType, Record, ResponseCode,
+    },
+};
+
+/// What a [`MockTransport`] does with one request.
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    /// Answers the request after `latency`.
+    Respond {
+        response_code: ResponseCode,
+        answers: Vec<Record>,
+        authorities: Vec<Record>,
+        latency: Duration,
+    },
+    /// Never answers, like a lost datagram.
+    Drop,
+    /// Fails straight away, like an unreachable server.
+    Fail,
+}
+
+impl MockReply {
+    /// Answers straight away with `answers`.
+    pub fn answer(answers: Vec<Record>) -> Self {
+        Self::Respond {
+            response_code: ResponseCode::NoError,
+            answers,
+            authorities: vec![],
+            latency: Duration::ZERO,
+        }
+    }
+
+    /// Answers straight away with no records and `response_code`.
+    pub fn status(response_code: ResponseCode) -> Self {
+        Self::Respond {
+            response_code,
+            answers: vec![],
+            authorities: vec![],
+            latency: Duration::ZERO,
+        }
+    }
+
+    /// Delays the reply by `latency`, if it is a response.
+    pub fn after(self, delay: Duration) -> Self {
+        match self {
+            Self::Respond {
+                response_code,
+                answers,
+                authorities,
+                ..
+            } => Self::Respond {
+                response_code,
+                answers,
+                authorities,
+                latency: delay,
+            },
+            other => other,
+        }
+    }
+}
+
+/// An in-memory transport that replies from a script, for testing resolver logic
+/// without sockets. Replies are used in order; once the script runs out, every
+/// further request gets the fallback reply.
+pub struct MockTransport {
+    script: Mutex<VecDeque<MockReply>>,
+    fallback: MockReply,
+    requests: Mutex<Vec<Message>>,
+}
+
+impl MockTransport {
+    pub fn new(script: Vec<MockReply>) -> Self {
+        Self {
+            script: Mutex::new(script.into()),
+            fallback: MockReply::Fail,
+            requests: Mutex::new(vec![]),
+        }
+    }
+
+    /// Replies with `reply` to every request the script does not cover.
+    pub fn with_fallback(self, reply: MockReply) -> Self {
+        Self {
+            fallback: reply,
+            ..self
+        }
+    }
+
+    /// Appends `reply` to the script.
+    pub fn push(&self, reply: MockReply) {
+        self.script.lock().unwrap().push_back(reply);
+    }
+
+    /// Every request received so far, in order.
+    pub fn requests(&self) -> Vec<Message> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl DnsTransport for MockTransport {
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        self.requests.lock().unwrap().push(request.clone());
+        let reply = self
+            .script
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| self.fallback.clone());
+
+        match reply {
+            MockReply::Respond {
+                response_code,
+                answers,
+                authorities,
+                latency,
+            } => {
+                time::sleep(latency).await;
+                Ok(response(request, response_code, answers, authorities))
+            }
+            MockReply::Drop => std::future::pending().await,
+            MockReply::Fail => Err(NauticDnsError::ConnectionFailure(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "Scripted failure",
+            ))),
+        }
+    }
+}
+
+fn response(
+    request: &Message,
+    response_code: ResponseCode,
+    answers: Vec<Record>,
+    authorities: Vec<Record>,
+) -> Message {
+    let flags = FlagsBuilder::default()
+        .message_type(MessageType::Response)
+        .recursion_desired(request.header().flags().recursion_desired())
+        .recursion_available(true)
+        .response(response_code)
+        .build()
+        .expect("Flags have defaults for every field");
+
+    let header = HeaderBuilder::default()
+        .id(request.header().id())
+        .flags(flags)
+        .questions_size(request.questions().len() as u16)
+        .answers_size(answers.len() as u16)
+        .name_servers_size(authorities.len() as u16)
+        .build()
+        .expect("Header has defaults for every field");
+
+    MessageBuilder::default()
+        .header(header)
+        .questions(request.questions().to_vec())
+        .answers(answers)
+        .authorities(authorities)
+        .build()
+        .expect("Message has every field set")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::protocol::{Class, LabelSequence, Query, RecordData, RecordType};
+
+    fn request() -> Message {
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+        Message::query(query, true)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn scripted_replies_are_used_in_order() {
+        let record = Record::new(
+            LabelSequence::new("github.com"),
+            Class::IN,
+            60,
+            RecordData::A(Ipv4Addr::new(140, 82, 121, 4)),
+        );
+        let transport = MockTransport::new(vec![
+            MockReply::answer(vec![record]).after(Duration::from_millis(50)),
+            MockReply::status(ResponseCode::ServerFailure),
+        ]);
+
+        let request = request();
+        let first = transport.exchange(&request).await.unwrap();
+        let second = transport.exchange(&request).await.unwrap();
+        let third = transport.exchange(&request).await;
+
+        assert_eq!(first.header().id(), request.header().id());
+        assert_eq!(first.answers().len(), 1);
+        assert_eq!(second.response_code(), &ResponseCode::ServerFailure);
+        assert!(third.is_err());
+        assert_eq!(transport.requests().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropped_request_is_never_answered() {
+        let transport = MockTransport::new(vec![MockReply::Drop]);
+        let request = request();
+        let exchange = time::timeout(Duration::from_secs(5), transport.exchange(&request));
+
+        assert!(exchange.await.is_err());
+    }
+}
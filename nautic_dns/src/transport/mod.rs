@@ -0,0 +1,201 @@
+mod bootstrap;
+mod https;
+mod mock;
+mod spec;
+mod stream;
+mod tls;
+mod udp;
+
+pub use bootstrap::*;
+pub use https::*;
+pub use mock::*;
+pub use spec::*;
+pub use stream::*;
+pub use tls::*;
+pub use udp::*;
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use tokio::sync::oneshot;
+
+use crate::{
+    errors::NauticDnsError,
+    protocol::{Message, MessageType, Query},
+};
+
+/// Largest UDP payload we are willing to receive from an upstream.
+pub(crate) const MAX_UDP_PAYLOAD: usize = 4096;
+
+/// How queries are carried to an upstream name server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Protocol {
+    #[default]
+    Udp,
+    Tcp,
+    /// DNS over TLS (RFC 7858), which never falls back to plaintext.
+    Tls,
+    /// DNS over HTTPS (RFC 8484), sent to the configured endpoint URLs.
+    Https,
+}
+
+/// Carries DNS messages to one upstream and brings back its responses. Every way of
+/// reaching a name server implements it, so the resolver can use any of them, or a
+/// [`MockTransport`] in tests.
+#[async_trait]
+pub trait DnsTransport: Send + Sync {
+    /// Sends `request` and waits for the response that matches it. Callers apply
+    /// their own timeout.
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError>;
+}
+
+type PendingKey = (u16, Vec<Query>);
+
+/// Queries waiting for their response on a shared socket or connection. Responses
+/// are matched to their query by both id and question, so a response to one query
+/// can never complete another.
+#[derive(Default)]
+pub(crate) struct PendingQueries {
+    waiters: Mutex<HashMap<PendingKey, oneshot::Sender<Message>>>,
+}
+
+impl PendingQueries {
+    /// Registers `request` under an id no other pending query is using. The request
+    /// must be sent as returned, and stays registered until the guard is dropped.
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        request: &Message,
+    ) -> (Message, PendingGuard, oneshot::Receiver<Message>) {
+        let (sender, receiver) = oneshot::channel();
+        let mut waiters = self.waiters.lock().unwrap();
+
+        let mut request = request.clone();
+        while waiters.contains_key(&(request.header().id(), request.questions().to_vec())) {
+            request = request.with_id(rand::random());
+        }
+
+        let key = (request.header().id(), request.questions().to_vec());
+        waiters.insert(key.clone(), sender);
+
+        let guard = PendingGuard {
+            pending: self.clone(),
+            key,
+        };
+
+        (request, guard, receiver)
+    }
+
+    /// Hands `response` to the query waiting for it. Responses nobody is waiting for
+    /// are dropped.
+    pub(crate) fn dispatch(&self, response: Message) {
+        if response.header().flags().message_type() != &MessageType::Response {
+            return;
+        }
+
+        let key = (response.header().id(), response.questions().to_vec());
+        if let Some(waiter) = self.waiters.lock().unwrap().remove(&key) {
+            let _ = waiter.send(response);
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.waiters.lock().unwrap().is_empty()
+    }
+
+    /// Fails every pending query, e.g. once their connection is gone.
+    pub(crate) fn clear(&self) {
+        self.waiters.lock().unwrap().clear();
+    }
+}
+
+/// Removes a pending query once its caller stops waiting, e.g. after a timeout.
+pub(crate) struct PendingGuard {
+    pending: Arc<PendingQueries>,
+    key: PendingKey,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pending.waiters.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Waits for the response to a registered query.
+pub(crate) async fn wait_for(
+    receiver: oneshot::Receiver<Message>,
+) -> Result<Message, NauticDnsError> {
+    receiver.await.map_err(|_| {
+        NauticDnsError::ConnectionFailure(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "Connection closed before the response arrived",
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{Class, FlagsBuilder, HeaderBuilder, LabelSequence, RecordType};
+
+    fn response_to(request: &Message) -> Message {
+        let flags = FlagsBuilder::default()
+            .message_type(MessageType::Response)
+            .build()
+            .unwrap();
+
+        crate::protocol::MessageBuilder::default()
+            .header(
+                HeaderBuilder::default()
+                    .id(request.header().id())
+                    .flags(flags)
+                    .build()
+                    .unwrap(),
+            )
+            .questions(request.questions().to_vec())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn register_same_request_twice_uses_new_id() {
+        let pending = Arc::new(PendingQueries::default());
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+        let request = Message::query(query, true);
+
+        let (first, _first_guard, _) = pending.register(&request);
+        let (second, _second_guard, _) = pending.register(&request);
+
+        assert_ne!(first.header().id(), second.header().id());
+    }
+
+    #[test]
+    fn dispatch_matches_id_and_question() {
+        let pending = Arc::new(PendingQueries::default());
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+        let other = Query::new(LabelSequence::new("gitlab.com"), RecordType::A, Class::IN);
+
+        let (request, _guard, mut receiver) = pending.register(&Message::query(query, true));
+        let forged = Message::query(other, true).with_id(request.header().id());
+
+        pending.dispatch(response_to(&forged));
+        assert!(receiver.try_recv().is_err());
+
+        pending.dispatch(response_to(&request));
+        assert!(receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn dropped_guard_unregisters_query() {
+        let pending = Arc::new(PendingQueries::default());
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+
+        let (_, guard, _) = pending.register(&Message::query(query, true));
+        drop(guard);
+
+        assert!(pending.waiters.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use url::{form_urlencoded, Url};
+
+use super::{
+    endpoint_address, BootstrapTransport, DnsTransport, HttpMethod, HttpsClient, StreamTransport,
+    TlsConfig, TlsConfigBuilder, UdpTransport, DOT_PORT,
+};
+use crate::errors::NauticDnsError;
+
+/// Port plain DNS servers listen on.
+pub const DNS_PORT: u16 = 53;
+
+/// An upstream name server and the protocol to reach it with, parsed from a URL:
+///
+/// - `udp://9.9.9.9:53` and `tcp://[2001:db8::1]` for plain DNS, on port 53 unless
+///   given
+/// - `tls://dns.example:853` for DNS over TLS, where the port may also be written
+///   as `tls://dns.example#853`. The query takes `pin=` with a base64 SHA-256 SPKI
+///   digest, any number of times, and `name=` to verify the certificate against
+///   another name than the host
+/// - `https://dns.example/dns-query` for DNS over HTTPS
+///
+/// Host names are never looked up through the system resolver, as that would send
+/// plaintext DNS off the host. The transports enforce this themselves: they only
+/// connect to addresses, see [`StreamTransport`] and [`HttpsClient::new`]. The address to connect to can be given with `addr=`,
+/// e.g. `tls://dns.example?addr=192.0.2.53`, or in the fragment for HTTPS, e.g.
+/// `https://dns.example/dns-query#addr=192.0.2.53`. Without one, TLS and HTTPS
+/// hosts are looked up through the encrypted bootstrap upstreams passed to
+/// [`UpstreamSpec::transport`]. Plain DNS upstreams always need an address.
+#[derive(Debug, Clone)]
+pub enum UpstreamSpec {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+    Tls(UpstreamAddress, TlsConfig),
+    Https(Url),
+}
+
+/// Where an encrypted upstream is reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpstreamAddress {
+    Ip(SocketAddr),
+    /// A host name and port, looked up through the bootstrap upstreams.
+    Name(String, u16),
+}
+
+impl UpstreamSpec {
+    pub fn parse(spec: &str) -> Result<Self, NauticDnsError> {
+        let url = Url::parse(spec)?;
+        let invalid = |reason: &str| NauticDnsError::InvalidUpstream(format!("{spec}: {reason}"));
+
+        if url.scheme() == "https" {
+            if url.host_str().filter(|host| !host.is_empty()).is_none() {
+                return Err(invalid("missing host"));
+            }
+
+            // Named hosts without `#addr=` are looked up through the bootstrap upstreams
+            let addr = form_urlencoded::parse(url.fragment().unwrap_or_default().as_bytes())
+                .any(|(key, _)| key == "addr");
+            if addr && endpoint_address(&url).is_err() {
+                return Err(invalid("addr must be an IP address"));
+            }
+
+            return Ok(UpstreamSpec::Https(url));
+        }
+
+        // `tls://host#853?pin=...` carries the port and parameters in the fragment
+        let (port, query) = match url.fragment() {
+            Some(fragment) => {
+                let (port, query) = fragment.split_once('?').unwrap_or((fragment, ""));
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| invalid("the fragment must be a port"))?;
+                (Some(port), query.to_owned())
+            }
+            None => (url.port(), url.query().unwrap_or_default().to_owned()),
+        };
+
+        let host = url
+            .host_str()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| invalid("missing host"))?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+
+        let parameters = form_urlencoded::parse(query.as_bytes()).collect::<Vec<_>>();
+
+        let ip = match parameters.iter().find(|(key, _)| key == "addr") {
+            Some((_, ip)) => Some(
+                ip.parse::<IpAddr>()
+                    .map_err(|_| invalid("addr must be an IP address"))?,
+            ),
+            None => host.parse::<IpAddr>().ok(),
+        };
+        let address = |default_port: u16| {
+            ip.map(|ip| SocketAddr::new(ip, port.unwrap_or(default_port)))
+                .ok_or_else(|| invalid("plain DNS upstreams need an IP address or addr="))
+        };
+
+        match url.scheme() {
+            "udp" => Ok(UpstreamSpec::Udp(address(DNS_PORT)?)),
+            "tcp" => Ok(UpstreamSpec::Tcp(address(DNS_PORT)?)),
+            "tls" => {
+                let mut server_name = host.to_ascii_lowercase();
+                let mut spki_pins = vec![];
+
+                for (key, value) in parameters {
+                    match key.as_ref() {
+                        "addr" => {}
+                        "name" => server_name = value.into_owned(),
+                        // Form decoding turns the `+` of base64 into spaces
+                        "pin" => spki_pins.push(
+                            decode_pin(&value.replace(' ', "+"))
+                                .ok_or_else(|| invalid("pins must be base64 SHA-256 digests"))?,
+                        ),
+                        _ => return Err(invalid(&format!("unknown parameter {key}"))),
+                    }
+                }
+
+                let tls = TlsConfigBuilder::default()
+                    .server_name(server_name)
+                    .spki_pins(spki_pins)
+                    .build()
+                    .expect("TLS config has defaults for every other field");
+
+                let address = match ip {
+                    Some(ip) => UpstreamAddress::Ip(SocketAddr::new(ip, port.unwrap_or(DOT_PORT))),
+                    None => {
+                        UpstreamAddress::Name(host.to_ascii_lowercase(), port.unwrap_or(DOT_PORT))
+                    }
+                };
+
+                Ok(UpstreamSpec::Tls(address, tls))
+            }
+            scheme => Err(invalid(&format!("unsupported scheme {scheme}"))),
+        }
+    }
+
+    /// Whether queries to this upstream are encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, UpstreamSpec::Tls(..) | UpstreamSpec::Https(_))
+    }
+
+    /// The host name to look up before this upstream can be reached, or `None` when
+    /// its address is known.
+    pub fn host_name(&self) -> Option<&str> {
+        match self {
+            UpstreamSpec::Tls(UpstreamAddress::Name(host, _), _) => Some(host),
+            UpstreamSpec::Https(url) if endpoint_address(url).is_err() => url.host_str(),
+            _ => None,
+        }
+    }
+
+    /// Builds the transport to this upstream. Named hosts are looked up through
+    /// `bootstrap` when first queried.
+    pub fn transport(
+        &self,
+        multiplex: bool,
+        idle_timeout: Option<Duration>,
+        https_method: HttpMethod,
+        bootstrap: &[Arc<dyn DnsTransport>],
+    ) -> Arc<dyn DnsTransport> {
+        let https = move |url: &Url, server: SocketAddr| -> Arc<dyn DnsTransport> {
+            Arc::new(
+                HttpsClient::with_address(url.clone(), server, https_method, None)
+                    .expect("URLs are checked when the spec is parsed"),
+            )
+        };
+
+        match self {
+            UpstreamSpec::Udp(server) => {
+                Arc::new(UdpTransport::new(*server, multiplex, idle_timeout))
+            }
+            UpstreamSpec::Tcp(server) => Arc::new(StreamTransport::tcp(*server, idle_timeout)),
+            UpstreamSpec::Tls(UpstreamAddress::Ip(server), tls) => {
+                Arc::new(StreamTransport::tls(*server, tls.clone(), idle_timeout))
+            }
+            UpstreamSpec::Tls(UpstreamAddress::Name(host, port), tls) => {
+                let tls = tls.clone();
+                Arc::new(BootstrapTransport::new(
+                    host.clone(),
+                    *port,
+                    bootstrap.to_vec(),
+                    move |server| Arc::new(StreamTransport::tls(server, tls.clone(), idle_timeout)),
+                ))
+            }
+            UpstreamSpec::Https(url) => match endpoint_address(url) {
+                Ok(server) => https(url, server),
+                Err(_) => {
+                    let host = url.host_str().unwrap_or_default().to_owned();
+                    let port = url.port_or_known_default().unwrap_or(443);
+                    let url = url.clone();
+
+                    Arc::new(BootstrapTransport::new(
+                        host,
+                        port,
+                        bootstrap.to_vec(),
+                        move |server| https(&url, server),
+                    ))
+                }
+            },
+        }
+    }
+}
+
+/// Checks that the named hosts among `upstreams` can be looked up through
+/// `bootstrap`, which must be encrypted and given by IP address itself.
+pub(crate) fn check_bootstrap<'a>(
+    upstreams: impl IntoIterator<Item = &'a UpstreamSpec>,
+    bootstrap: &[UpstreamSpec],
+) -> Result<(), String> {
+    if let Some(spec) = bootstrap
+        .iter()
+        .find(|spec| !spec.is_encrypted() || spec.host_name().is_some())
+    {
+        return Err(format!(
+            "Bootstrap upstream {spec:?} must be encrypted and given by IP address"
+        ));
+    }
+
+    let named = upstreams.into_iter().find_map(UpstreamSpec::host_name);
+    match named {
+        Some(host) if bootstrap.is_empty() => Err(format!(
+            "Upstream host {host} needs bootstrap upstreams to be looked up through"
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl FromStr for UpstreamSpec {
+    type Err = NauticDnsError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        Self::parse(spec)
+    }
+}
+
+fn decode_pin(pin: &str) -> Option<[u8; 32]> {
+    let digest = STANDARD_NO_PAD.decode(pin.trim_end_matches('=')).ok()?;
+    digest.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn plain_specs_default_to_port_53() {
+        let udp = UpstreamSpec::parse("udp://9.9.9.9:5353").unwrap();
+        let tcp = UpstreamSpec::parse("tcp://[2001:db8::1]").unwrap();
+
+        let UpstreamSpec::Udp(udp) = udp else {
+            panic!("expected a UDP address, got {udp:?}");
+        };
+        let UpstreamSpec::Tcp(tcp) = tcp else {
+            panic!("expected a TCP address, got {tcp:?}");
+        };
+
+        assert_eq!(udp, SocketAddr::from((Ipv4Addr::new(9, 9, 9, 9), 5353)));
+        assert_eq!(
+            tcp,
+            SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 53))
+        );
+    }
+
+    #[test]
+    fn tls_spec_takes_port_and_pins_from_fragment() {
+        let pin = [7u8; 32];
+        let spec = format!(
+            "tls://DNS.example#853?addr=192.0.2.53&pin={}",
+            STANDARD_NO_PAD.encode(pin)
+        );
+
+        let UpstreamSpec::Tls(address, tls) = UpstreamSpec::parse(&spec).unwrap() else {
+            panic!("expected a TLS upstream");
+        };
+
+        assert_eq!(
+            address,
+            UpstreamAddress::Ip(SocketAddr::from((Ipv4Addr::new(192, 0, 2, 53), 853)))
+        );
+        assert_eq!(tls.server_name(), "dns.example");
+        assert_eq!(tls.spki_pins(), &[pin]);
+    }
+
+    #[test]
+    fn tls_spec_with_ip_takes_server_name_from_query() {
+        let spec = UpstreamSpec::parse("tls://1.1.1.1?name=one.one.one.one").unwrap();
+
+        let UpstreamSpec::Tls(address, tls) = spec else {
+            panic!("expected a TLS upstream");
+        };
+
+        assert_eq!(
+            address,
+            UpstreamAddress::Ip(SocketAddr::from((Ipv4Addr::new(1, 1, 1, 1), DOT_PORT)))
+        );
+        assert_eq!(tls.server_name(), "one.one.one.one");
+        assert!(spec_is_encrypted("tls://1.1.1.1"));
+    }
+
+    #[test]
+    fn named_plain_spec_takes_address_from_query() {
+        let spec = UpstreamSpec::parse("tcp://dns.example:5353?addr=2001:db8::53").unwrap();
+
+        let UpstreamSpec::Tcp(address) = spec else {
+            panic!("expected a TCP upstream");
+        };
+
+        assert_eq!(
+            address,
+            SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53), 5353))
+        );
+    }
+
+    #[test]
+    fn https_spec_keeps_url() {
+        let spec = UpstreamSpec::parse("https://dns.example/dns-query#addr=192.0.2.53").unwrap();
+
+        assert!(matches!(spec, UpstreamSpec::Https(url) if url.path() == "/dns-query"));
+        assert!(!spec_is_encrypted("udp://9.9.9.9"));
+    }
+
+    #[test]
+    fn named_encrypted_specs_are_looked_up_through_bootstrap() {
+        let pin = STANDARD_NO_PAD.encode([7u8; 32]);
+        let tls = UpstreamSpec::parse(&format!("tls://dns.example#853?pin={pin}")).unwrap();
+        let https = UpstreamSpec::parse("https://dns.example/dns-query").unwrap();
+
+        let UpstreamSpec::Tls(address, _) = &tls else {
+            panic!("expected a TLS upstream");
+        };
+        assert_eq!(address, &UpstreamAddress::Name("dns.example".into(), 853));
+        assert_eq!(tls.host_name(), Some("dns.example"));
+        assert_eq!(https.host_name(), Some("dns.example"));
+
+        let bootstrap = [UpstreamSpec::parse("tls://9.9.9.9").unwrap()];
+        assert!(check_bootstrap([&tls, &https], &bootstrap).is_ok());
+        assert!(check_bootstrap([&tls], &[]).is_err());
+        assert!(check_bootstrap([&tls], std::slice::from_ref(&https)).is_err());
+        assert!(check_bootstrap([&tls], &[UpstreamSpec::parse("udp://9.9.9.9").unwrap()]).is_err());
+    }
+
+    #[test]
+    fn invalid_specs_are_rejected() {
+        for spec in [
+            "quic://192.0.2.53",
+            "tls://dns.example?addr=dns.example",
+            "https://dns.example/dns-query#addr=dns.example",
+            "tls://192.0.2.53#port",
+            "tls://192.0.2.53?pin=short",
+            "tls://192.0.2.53?unknown=1",
+            "udp://dns.example",
+            "udp://",
+            "not a url",
+        ] {
+            assert!(UpstreamSpec::parse(spec).is_err(), "{spec} was accepted");
+        }
+    }
+
+    fn spec_is_encrypted(spec: &str) -> bool {
+        UpstreamSpec::parse(spec).unwrap().is_encrypted()
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::Mutex,
+    task::JoinHandle,
+    time,
+};
+
+use super::{wait_for, DnsTransport, PendingQueries, TlsConfig};
+use crate::{
+    errors::NauticDnsError,
+    protocol::{ByteScanner, Message},
+};
+
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A single TCP (or TLS) connection to one upstream, with queries pipelined over it
+/// and responses matched back to their query whatever order they arrive in.
+pub struct MultiplexedStream {
+    writer: Arc<Mutex<Writer>>,
+    pending: Arc<PendingQueries>,
+    closed: Arc<AtomicBool>,
+    reader: JoinHandle<()>,
+}
+
+impl MultiplexedStream {
+    pub async fn connect_tcp(
+        server: SocketAddr,
+        idle_timeout: Option<Duration>,
+    ) -> Result<Self, NauticDnsError> {
+        let stream = TcpStream::connect(server).await?;
+        stream.set_nodelay(true)?;
+
+        Ok(Self::new(stream, idle_timeout))
+    }
+
+    /// Multiplexes queries over an already established stream, which is closed once
+    /// it has been idle for `idle_timeout` with no queries pending.
+    pub fn new<S>(stream: S, idle_timeout: Option<Duration>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut read_half, write_half) = tokio::io::split(stream);
+        let writer: Arc<Mutex<Writer>> = Arc::new(Mutex::new(Box::new(write_half)));
+        let pending = Arc::new(PendingQueries::default());
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let reader = tokio::spawn({
+            let writer = writer.clone();
+            let pending = pending.clone();
+            let closed = closed.clone();
+
+            async move {
+                loop {
+                    let next = read_frame(&mut read_half);
+                    tokio::pin!(next);
+
+                    // The read in progress is kept across idle checks, since
+                    // dropping it could lose part of a message
+                    let read = loop {
+                        let Some(idle_timeout) = idle_timeout else {
+                            break next.as_mut().await;
+                        };
+
+                        match time::timeout(idle_timeout, next.as_mut()).await {
+                            Ok(read) => break read,
+                            Err(_) if pending.is_empty() => break Ok(None),
+                            Err(_) => continue,
+                        }
+                    };
+
+                    let Ok(Some(frame)) = read else {
+                        break;
+                    };
+
+                    // A response that fails to parse only concerns its own query,
+                    // which is left to time out
+                    match Message::try_scan(&frame, 0) {
+                        Ok(response) => pending.dispatch(response.value().clone()),
+                        Err(error) => log::debug!("Dropping malformed response: {error}"),
+                    }
+                }
+
+                closed.store(true, Ordering::SeqCst);
+                pending.clear();
+                let _ = writer.lock().await.shutdown().await;
+            }
+        });
+
+        Self {
+            writer,
+            pending,
+            closed,
+            reader,
+        }
+    }
+
+    /// Whether the connection is gone and a new one has to be opened.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Sends `request` and waits for its response. The request may go out under a
+    /// different id when its own is already in use by another pending query.
+    pub async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        let (request, _guard, receiver) = self.pending.register(request);
+
+        let written = write_message(&mut *self.writer.lock().await, &request).await;
+        if written.is_err() {
+            self.closed.store(true, Ordering::SeqCst);
+        }
+
+        written?;
+        wait_for(receiver).await
+    }
+}
+
+impl Drop for MultiplexedStream {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[async_trait]
+impl DnsTransport for MultiplexedStream {
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        MultiplexedStream::exchange(self, request).await
+    }
+}
+
+/// DNS over TCP or TLS to one server. All queries share one connection, which is
+/// opened on first use and reopened whenever it has been closed.
+///
+/// Servers are given by address only, so building a transport never looks a name
+/// up through the system resolver. Servers known by name go through a
+/// [`BootstrapTransport`](super::BootstrapTransport).
+pub struct StreamTransport {
+    server: SocketAddr,
+    tls: Option<TlsConfig>,
+    idle_timeout: Option<Duration>,
+    connection: Mutex<Option<Arc<MultiplexedStream>>>,
+}
+
+impl StreamTransport {
+    pub fn tcp(server: SocketAddr, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            server,
+            tls: None,
+            idle_timeout,
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// DNS over TLS (RFC 7858) to the address `server`, verified as `tls`
+    /// describes. The server name in `tls` is only checked against the certificate
+    /// and sent as SNI, never looked up.
+    pub fn tls(server: SocketAddr, tls: TlsConfig, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            server,
+            tls: Some(tls),
+            idle_timeout,
+            connection: Mutex::new(None),
+        }
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    async fn connection(&self) -> Result<Arc<MultiplexedStream>, NauticDnsError> {
+        let mut connection = self.connection.lock().await;
+        if let Some(stream) = connection.as_ref() {
+            if !stream.is_closed() {
+                return Ok(stream.clone());
+            }
+        }
+
+        let stream = match &self.tls {
+            Some(tls) => {
+                MultiplexedStream::connect_tls(self.server, tls, self.idle_timeout).await?
+            }
+            None => MultiplexedStream::connect_tcp(self.server, self.idle_timeout).await?,
+        };
+
+        let stream = Arc::new(stream);
+        *connection = Some(stream.clone());
+
+        Ok(stream)
+    }
+}
+
+#[async_trait]
+impl DnsTransport for StreamTransport {
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        self.connection().await?.exchange(request).await
+    }
+}
+
+/// Reads one message with its two byte length prefix (RFC 1035 section 4.2.2).
+/// Returns `None` when the peer closed the stream between messages.
+pub async fn read_message<R>(reader: &mut R) -> Result<Option<Message>, NauticDnsError>
+where
+    R: AsyncRead + Unpin,
+{
+    let Some(frame) = read_frame(reader).await? else {
+        return Ok(None);
+    };
+
+    let message = Message::try_scan(&frame, 0)?;
+    Ok(Some(message.value().clone()))
+}
+
+/// Reads one length-prefixed message without parsing it, returning `Ok(None)` when
+/// the stream ends cleanly between messages.
+pub(crate) async fn read_frame<R>(reader: &mut R) -> Result<Option<Vec<u8>>, NauticDnsError>
+where
+    R: AsyncRead + Unpin,
+{
+    let length = match reader.read_u16().await {
+        Ok(length) => length,
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+
+    let mut buffer = vec![0; length as usize];
+    reader.read_exact(&mut buffer).await?;
+
+    Ok(Some(buffer))
+}
+
+/// Writes `message` with its two byte length prefix (RFC 1035 section 4.2.2).
+pub async fn write_message<W>(writer: &mut W, message: &Message) -> Result<(), NauticDnsError>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let bytes = Bytes::from(message);
+    let length = u16::try_from(bytes.len()).map_err(|_| {
+        NauticDnsError::ConnectionFailure(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Message is too long for a stream",
+        ))
+    })?;
+
+    let mut framed = Vec::with_capacity(bytes.len() + 2);
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(&bytes);
+
+    writer.write_all(&framed).await?;
+    writer.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::protocol::{
+        Class, FlagsBuilder, HeaderBuilder, LabelSequence, MessageBuilder, MessageType, Query,
+        RecordType,
+    };
+
+    fn response_to(request: &Message) -> Message {
+        let flags = FlagsBuilder::default()
+            .message_type(MessageType::Response)
+            .build()
+            .unwrap();
+
+        MessageBuilder::default()
+            .header(
+                HeaderBuilder::default()
+                    .id(request.header().id())
+                    .flags(flags)
+                    .build()
+                    .unwrap(),
+            )
+            .questions(request.questions().to_vec())
+            .build()
+            .unwrap()
+    }
+
+    /// Accepts one connection, collects `count` requests and answers them in
+    /// reverse order.
+    async fn spawn_reordering_upstream(count: usize) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut requests = vec![];
+            while requests.len() < count {
+                requests.push(read_message(&mut stream).await.unwrap().unwrap());
+            }
+
+            for request in requests.iter().rev() {
+                write_message(&mut stream, &response_to(request))
+                    .await
+                    .unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn framed_message_round_trip_success() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+        let request = Message::query(query, true);
+
+        write_message(&mut client, &request).await.unwrap();
+        drop(client);
+
+        assert_eq!(read_message(&mut server).await.unwrap(), Some(request));
+        assert_eq!(read_message(&mut server).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn concurrent_queries_share_one_connection() {
+        let server = spawn_reordering_upstream(10).await;
+        let transport = Arc::new(MultiplexedStream::connect_tcp(server, None).await.unwrap());
+
+        let lookups = (0..10)
+            .map(|i| {
+                let transport = transport.clone();
+                tokio::spawn(async move {
+                    let name = LabelSequence::new(&format!("host{i}.github.com"));
+                    let query = Query::new(name, RecordType::A, Class::IN);
+                    let response = transport
+                        .exchange(&Message::query(query.clone(), true))
+                        .await;
+
+                    (query, response.unwrap())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for lookup in lookups {
+            let (query, response) = lookup.await.unwrap();
+            assert_eq!(response.question(), Some(&query));
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_response_keeps_connection_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let request = read_message(&mut stream).await.unwrap().unwrap();
+
+            stream
+                .write_all(&[0x00, 0x03, 0xde, 0xad, 0x00])
+                .await
+                .unwrap();
+            write_message(&mut stream, &response_to(&request))
+                .await
+                .unwrap();
+            read_message(&mut stream).await.unwrap();
+        });
+
+        let transport = MultiplexedStream::connect_tcp(address, None).await.unwrap();
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+        let response = transport
+            .exchange(&Message::query(query.clone(), true))
+            .await
+            .unwrap();
+
+        assert_eq!(response.question(), Some(&query));
+        assert!(!transport.is_closed());
+    }
+
+    #[tokio::test]
+    async fn closed_connection_fails_pending_queries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_message(&mut stream).await.unwrap();
+        });
+
+        let transport = MultiplexedStream::connect_tcp(address, None).await.unwrap();
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+        let response = transport.exchange(&Message::query(query, true)).await;
+
+        assert!(response.is_err());
+        assert!(transport.is_closed());
+    }
+}
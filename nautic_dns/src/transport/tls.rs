@@ -0,0 +1,403 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use derive_builder::Builder;
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, CryptoProvider},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    },
+    TlsConnector,
+};
+
+use super::MultiplexedStream;
+use crate::errors::NauticDnsError;
+
+/// Port DNS over TLS servers listen on (RFC 7858 section 3.1).
+pub const DOT_PORT: u16 = 853;
+
+#[derive(Debug, Clone, Builder)]
+pub struct TlsConfig {
+    /// Name the server certificate is verified against, also sent as SNI.
+    #[builder(setter(into))]
+    server_name: String,
+
+    /// SHA-256 digests of the SubjectPublicKeyInfo the server has to present
+    /// (RFC 7858 section 4.2). When set, they replace hostname verification.
+    #[builder(default)]
+    spki_pins: Vec<[u8; 32]>,
+
+    /// DER encoded trust anchors to use instead of the bundled Mozilla roots.
+    #[builder(default)]
+    root_certificates: Vec<Vec<u8>>,
+}
+
+impl TlsConfig {
+    pub fn server_name(&self) -> &str {
+        &self.server_name
+    }
+    pub fn spki_pins(&self) -> &[[u8; 32]] {
+        &self.spki_pins
+    }
+    pub fn root_certificates(&self) -> &[Vec<u8>] {
+        &self.root_certificates
+    }
+
+    pub(crate) fn client_config(&self) -> Result<ClientConfig, NauticDnsError> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(tls_failure)?;
+
+        if !self.spki_pins.is_empty() {
+            let verifier = SpkiPinVerifier {
+                pins: self.spki_pins.clone(),
+                provider,
+            };
+
+            return Ok(builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(verifier))
+                .with_no_client_auth());
+        }
+
+        let mut roots = RootCertStore::empty();
+        if self.root_certificates.is_empty() {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+
+        for certificate in &self.root_certificates {
+            roots
+                .add(CertificateDer::from(certificate.clone()))
+                .map_err(tls_failure)?;
+        }
+
+        Ok(builder.with_root_certificates(roots).with_no_client_auth())
+    }
+}
+
+impl MultiplexedStream {
+    /// Opens a DNS over TLS connection to `server` (RFC 7858). The server name in
+    /// `tls` is only used to verify the certificate, never looked up.
+    pub async fn connect_tls(
+        server: SocketAddr,
+        tls: &TlsConfig,
+        idle_timeout: Option<Duration>,
+    ) -> Result<Self, NauticDnsError> {
+        let server_name = ServerName::try_from(tls.server_name().to_owned())
+            .map_err(|_| NauticDnsError::InvalidTarget(tls.server_name().to_owned()))?;
+
+        let connector = TlsConnector::from(Arc::new(tls.client_config()?));
+        let stream = TcpStream::connect(server).await?;
+        stream.set_nodelay(true)?;
+
+        let stream = connector.connect(server_name, stream).await?;
+        Ok(Self::new(stream, idle_timeout))
+    }
+}
+
+fn tls_failure(error: rustls::Error) -> NauticDnsError {
+    NauticDnsError::ConnectionFailure(io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Accepts exactly the certificates whose public key matches one of the pins, while
+/// still checking that the server holds the matching private key.
+#[derive(Debug)]
+struct SpkiPinVerifier {
+    pins: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for SpkiPinVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let spki = subject_public_key_info(end_entity).ok_or(rustls::Error::InvalidCertificate(
+            rustls::CertificateError::BadEncoding,
+        ))?;
+
+        let digest: [u8; 32] = Sha256::digest(spki).into();
+        match self.pins.contains(&digest) {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Finds the DER encoded SubjectPublicKeyInfo inside an X.509 certificate, which is
+/// the seventh field of the TBSCertificate when the explicit version is present.
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (tbs, _) = der_element(der_content(certificate)?)?;
+    let mut fields = der_content(tbs)?;
+
+    // Skip the optional [0] version, then serial, signature, issuer, validity and subject
+    if fields.first() == Some(&0xa0) {
+        fields = der_element(fields)?.1;
+    }
+
+    for _ in 0..5 {
+        fields = der_element(fields)?.1;
+    }
+
+    der_element(fields).map(|(spki, _)| spki)
+}
+
+/// Splits the first DER element, header included, from the bytes that follow it.
+fn der_element(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (header_length, content_length) = der_header(bytes)?;
+    let end = header_length.checked_add(content_length)?;
+
+    (end <= bytes.len()).then(|| bytes.split_at(end))
+}
+
+/// Returns the content of the first DER element, without its header.
+fn der_content(bytes: &[u8]) -> Option<&[u8]> {
+    let (header_length, content_length) = der_header(bytes)?;
+    bytes.get(header_length..header_length.checked_add(content_length)?)
+}
+
+/// Decodes the tag and length octets of a DER element into the header length and
+/// the content length.
+fn der_header(bytes: &[u8]) -> Option<(usize, usize)> {
+    let first = *bytes.get(1)?;
+    if first & 0x80 == 0 {
+        return Some((2, first as usize));
+    }
+
+    let count = (first & 0x7f) as usize;
+    if count == 0 || count > 4 {
+        return None;
+    }
+
+    let length = bytes
+        .get(2..2 + count)?
+        .iter()
+        .fold(0usize, |length, byte| (length << 8) | *byte as usize);
+
+    Some((2 + count, length))
+}
+
+/// Builds a server config with a fresh self-signed certificate for `name`, returning
+/// it along with the DER certificate and the SPKI pin of its key.
+#[cfg(test)]
+pub(crate) fn test_server_config(name: &str) -> (rustls::ServerConfig, Vec<u8>, [u8; 32]) {
+    use rustls::pki_types::PrivateKeyDer;
+
+    let key = rcgen::KeyPair::generate().unwrap();
+    let params = rcgen::CertificateParams::new(vec![name.to_owned()]).unwrap();
+    let certificate = params.self_signed(&key).unwrap();
+    let spki_pin: [u8; 32] = Sha256::digest(key.public_key_der()).into();
+
+    let config =
+        rustls::ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![certificate.der().clone()],
+                PrivateKeyDer::try_from(key.serialize_der()).unwrap(),
+            )
+            .unwrap();
+
+    (config, certificate.der().to_vec(), spki_pin)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::{
+        protocol::{
+            Class, FlagsBuilder, HeaderBuilder, LabelSequence, Message, MessageBuilder,
+            MessageType, Query, RecordType,
+        },
+        transport::{read_message, write_message},
+    };
+
+    struct TestServer {
+        address: SocketAddr,
+        certificate: Vec<u8>,
+        spki_pin: [u8; 32],
+    }
+
+    /// Runs a DNS over TLS server for `dns.example` that answers every query with an
+    /// empty response.
+    async fn spawn_tls_server() -> TestServer {
+        let (config, certificate, spki_pin) = test_server_config("dns.example");
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+
+                    while let Ok(Some(request)) = read_message(&mut stream).await {
+                        let flags = FlagsBuilder::default()
+                            .message_type(MessageType::Response)
+                            .build()
+                            .unwrap();
+
+                        let response = MessageBuilder::default()
+                            .header(
+                                HeaderBuilder::default()
+                                    .id(request.header().id())
+                                    .flags(flags)
+                                    .build()
+                                    .unwrap(),
+                            )
+                            .questions(request.questions().to_vec())
+                            .build()
+                            .unwrap();
+
+                        write_message(&mut stream, &response).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        TestServer {
+            address,
+            certificate,
+            spki_pin,
+        }
+    }
+
+    fn request() -> Message {
+        let query = Query::new(LabelSequence::new("github.com"), RecordType::A, Class::IN);
+        Message::query(query, true)
+    }
+
+    #[tokio::test]
+    async fn verified_hostname_connects() {
+        let server = spawn_tls_server().await;
+        let tls = TlsConfigBuilder::default()
+            .server_name("dns.example")
+            .root_certificates(vec![server.certificate])
+            .build()
+            .unwrap();
+
+        let stream = MultiplexedStream::connect_tls(server.address, &tls, None)
+            .await
+            .unwrap();
+        let response = stream.exchange(&request()).await.unwrap();
+
+        assert_eq!(response.questions(), request().questions());
+    }
+
+    #[tokio::test]
+    async fn mismatched_hostname_fails() {
+        let server = spawn_tls_server().await;
+        let tls = TlsConfigBuilder::default()
+            .server_name("other.example")
+            .root_certificates(vec![server.certificate])
+            .build()
+            .unwrap();
+
+        let stream = MultiplexedStream::connect_tls(server.address, &tls, None).await;
+
+        assert!(stream.is_err());
+    }
+
+    #[tokio::test]
+    async fn pinned_public_key_connects() {
+        let server = spawn_tls_server().await;
+        let tls = TlsConfigBuilder::default()
+            .server_name("dns.example")
+            .spki_pins(vec![server.spki_pin])
+            .build()
+            .unwrap();
+
+        let stream = MultiplexedStream::connect_tls(server.address, &tls, None)
+            .await
+            .unwrap();
+
+        assert!(stream.exchange(&request()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn wrong_pin_fails() {
+        let server = spawn_tls_server().await;
+        let tls = TlsConfigBuilder::default()
+            .server_name("dns.example")
+            .spki_pins(vec![[0; 32]])
+            .build()
+            .unwrap();
+
+        let stream = MultiplexedStream::connect_tls(server.address, &tls, None).await;
+
+        assert!(stream.is_err());
+    }
+
+    #[tokio::test]
+    async fn idle_connection_is_closed() {
+        let server = spawn_tls_server().await;
+        let tls = TlsConfigBuilder::default()
+            .server_name("dns.example")
+            .spki_pins(vec![server.spki_pin])
+            .build()
+            .unwrap();
+
+        let stream =
+            MultiplexedStream::connect_tls(server.address, &tls, Some(Duration::from_millis(100)))
+                .await
+                .unwrap();
+        stream.exchange(&request()).await.unwrap();
+        assert!(!stream.is_closed());
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(stream.is_closed());
+    }
+}
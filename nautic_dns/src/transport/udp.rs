@@ -0,0 +1,270 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use rand::Rng;
+use tokio::{net::UdpSocket, sync::OnceCell, task::JoinHandle};
+
+use super::{wait_for, DnsTransport, PendingQueries, StreamTransport, MAX_UDP_PAYLOAD};
+use crate::{
+    errors::NauticDnsError,
+    protocol::{ByteScanner, Message, MessageType},
+};
+
+/// Random source ports tried before giving up on binding a query socket.
+const SOURCE_PORT_ATTEMPTS: usize = 16;
+
+/// A single UDP socket to one upstream, shared by any number of concurrent queries.
+/// A background task reads every response and wakes the query it belongs to.
+pub struct MultiplexedUdp {
+    server: SocketAddr,
+    socket: Arc<UdpSocket>,
+    pending: Arc<PendingQueries>,
+    reader: JoinHandle<()>,
+}
+
+impl MultiplexedUdp {
+    pub async fn connect(server: SocketAddr) -> Result<Self, NauticDnsError> {
+        let socket = bind_random_port(server).await?;
+        socket.connect(server).await?;
+
+        let socket = Arc::new(socket);
+        let pending = Arc::new(PendingQueries::default());
+
+        let reader = tokio::spawn({
+            let socket = socket.clone();
+            let pending = pending.clone();
+
+            async move {
+                let mut buffer = vec![0; MAX_UDP_PAYLOAD];
+                loop {
+                    // Errors such as ICMP port unreachable only concern one datagram
+                    let Ok((length, peer)) = socket.recv_from(&mut buffer).await else {
+                        continue;
+                    };
+
+                    if peer != server {
+                        continue;
+                    }
+
+                    if let Ok(response) = Message::try_scan(&buffer[..length], 0) {
+                        pending.dispatch(response.value().clone());
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            server,
+            socket,
+            pending,
+            reader,
+        })
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    /// Sends `request` and waits for its response. The request may go out under a
+    /// different id when its own is already in use by another pending query.
+    pub async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        let (request, _guard, receiver) = self.pending.register(request);
+        self.socket.send(&Bytes::from(&request)).await?;
+
+        wait_for(receiver).await
+    }
+}
+
+impl Drop for MultiplexedUdp {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+#[async_trait]
+impl DnsTransport for MultiplexedUdp {
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        MultiplexedUdp::exchange(self, request).await
+    }
+}
+
+/// Plain DNS over UDP to one server, retrying over TCP whenever a response comes
+/// back truncated.
+pub struct UdpTransport {
+    server: SocketAddr,
+    multiplex: bool,
+    socket: OnceCell<MultiplexedUdp>,
+    tcp: StreamTransport,
+}
+
+impl UdpTransport {
+    /// Sends each query from a fresh random source port, or over one shared socket
+    /// when `multiplex` is set. The TCP fallback connection is closed after
+    /// `idle_timeout` without queries.
+    pub fn new(server: SocketAddr, multiplex: bool, idle_timeout: Option<Duration>) -> Self {
+        Self {
+            server,
+            multiplex,
+            socket: OnceCell::new(),
+            tcp: StreamTransport::tcp(server, idle_timeout),
+        }
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+}
+
+#[async_trait]
+impl DnsTransport for UdpTransport {
+    async fn exchange(&self, request: &Message) -> Result<Message, NauticDnsError> {
+        let response = match self.multiplex {
+            true => {
+                self.socket
+                    .get_or_try_init(|| MultiplexedUdp::connect(self.server))
+                    .await?
+                    .exchange(request)
+                    .await?
+            }
+            false => exchange(self.server, request).await?,
+        };
+
+        if response.header().flags().truncation() {
+            return self.tcp.exchange(request).await;
+        }
+
+        Ok(response)
+    }
+}
+
+/// Sends `request` to `server` from a fresh random source port and waits for its
+/// response. Anything that does not come from `server`, carry the request id and
+/// echo the question exactly is discarded as a possible spoofing attempt.
+pub(crate) async fn exchange(
+    server: SocketAddr,
+    request: &Message,
+) -> Result<Message, NauticDnsError> {
+    let socket = bind_random_port(server).await?;
+    socket.connect(server).await?;
+    socket.send(&Bytes::from(request)).await?;
+
+    let mut buffer = vec![0; MAX_UDP_PAYLOAD];
+    loop {
+        let (length, peer) = socket.recv_from(&mut buffer).await?;
+        if peer != server {
+            continue;
+        }
+
+        let Ok(response) = Message::try_scan(&buffer[..length], 0) else {
+            continue;
+        };
+
+        let response = response.value();
+        if response.header().id() == request.header().id()
+            && response.header().flags().message_type() == &MessageType::Response
+            && response.questions() == request.questions()
+        {
+            return Ok(response.clone());
+        }
+    }
+}
+
+/// Binds a UDP socket to a random unprivileged port, so that an off-path attacker
+/// has to guess the port as well as the id.
+async fn bind_random_port(server: SocketAddr) -> Result<UdpSocket, NauticDnsError> {
+    let address: IpAddr = match server {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+
+    let mut last_error = None;
+    for _ in 0..SOURCE_PORT_ATTEMPTS {
+        let port = rand::thread_rng().gen_range(1024..=u16::MAX);
+        match UdpSocket::bind((address, port)).await {
+            Ok(socket) => return Ok(socket),
+            Err(error) => last_error = Some(error),
+        }
+    }
+
+    Err(last_error
+        .map(NauticDnsError::ConnectionFailure)
+        .unwrap_or(NauticDnsError::ServerBindingFailure))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        Class, FlagsBuilder, HeaderBuilder, LabelSequence, MessageBuilder, Query, RecordType,
+    };
+
+    /// Collects `count` requests before answering them in reverse order.
+    async fn spawn_reordering_upstream(count: usize) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_UDP_PAYLOAD];
+            let mut requests = vec![];
+            while requests.len() < count {
+                let (length, peer) = socket.recv_from(&mut buffer).await.unwrap();
+                let request = Message::try_scan(&buffer[..length], 0).unwrap();
+                requests.push((request.value().clone(), peer));
+            }
+
+            for (request, peer) in requests.into_iter().rev() {
+                let flags = FlagsBuilder::default()
+                    .message_type(MessageType::Response)
+                    .build()
+                    .unwrap();
+
+                let response = MessageBuilder::default()
+                    .header(
+                        HeaderBuilder::default()
+                            .id(request.header().id())
+                            .flags(flags)
+                            .build()
+                            .unwrap(),
+                    )
+                    .questions(request.questions().to_vec())
+                    .build()
+                    .unwrap();
+
+                socket.send_to(&Bytes::from(response), peer).await.unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn concurrent_queries_share_one_socket() {
+        let server = spawn_reordering_upstream(20).await;
+        let transport = Arc::new(MultiplexedUdp::connect(server).await.unwrap());
+
+        let lookups = (0..20)
+            .map(|i| {
+                let transport = transport.clone();
+                tokio::spawn(async move {
+                    let name = LabelSequence::new(&format!("host{i}.github.com"));
+                    let query = Query::new(name, RecordType::A, Class::IN);
+                    let response = transport
+                        .exchange(&Message::query(query.clone(), true))
+                        .await;
+
+                    (query, response.unwrap())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for lookup in lookups {
+            let (query, response) = lookup.await.unwrap();
+            assert_eq!(response.question(), Some(&query));
+        }
+    }
+}
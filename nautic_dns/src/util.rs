@@ -1,6 +1,6 @@
 use url::{Host, Url};
 
-use crate::errors::NauticDnsError;
+use crate::{errors::NauticDnsError, protocol::LabelSequence};
 
 pub fn parse_domain(target: &Url) -> Result<String, NauticDnsError> {
     let target_host = target
@@ -16,3 +16,96 @@ pub fn parse_domain(target: &Url) -> Result<String, NauticDnsError> {
 
     Ok(target_host)
 }
+
+/// Expands `name` into the fully qualified names to query, in order, following
+/// glibc's `ndots` rules. A trailing dot marks the name as absolute, in which case
+/// the search list is never applied.
+pub fn search_candidates(name: &str, search: &[String], ndots: u8) -> Vec<LabelSequence> {
+    let name = name.trim();
+    if name.ends_with('.') {
+        return vec![LabelSequence::new(name)];
+    }
+
+    let relative = LabelSequence::new(name);
+    let mut candidates = search
+        .iter()
+        .map(|suffix| relative.join(&LabelSequence::new(suffix)))
+        .collect::<Vec<_>>();
+
+    let dots = name.matches('.').count();
+    if dots >= ndots as usize {
+        candidates.insert(0, relative);
+    } else {
+        candidates.push(relative);
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(candidates: Vec<LabelSequence>) -> Vec<String> {
+        candidates
+            .iter()
+            .map(|name| name.label().to_owned())
+            .collect()
+    }
+
+    fn search() -> Vec<String> {
+        vec!["svc.cluster.local".into(), "cluster.local".into()]
+    }
+
+    #[test]
+    fn search_candidates_short_name_tries_search_list_first() {
+        let candidates = search_candidates("redis", &search(), 1);
+
+        assert_eq!(
+            labels(candidates),
+            vec!["redis.svc.cluster.local", "redis.cluster.local", "redis"]
+        );
+    }
+
+    #[test]
+    fn search_candidates_enough_dots_tries_name_first() {
+        let candidates = search_candidates("www.github.com", &search(), 1);
+
+        assert_eq!(
+            labels(candidates),
+            vec![
+                "www.github.com",
+                "www.github.com.svc.cluster.local",
+                "www.github.com.cluster.local"
+            ]
+        );
+    }
+
+    #[test]
+    fn search_candidates_high_ndots_tries_search_list_first() {
+        let candidates = search_candidates("redis.cache", &search(), 5);
+
+        assert_eq!(
+            labels(candidates),
+            vec![
+                "redis.cache.svc.cluster.local",
+                "redis.cache.cluster.local",
+                "redis.cache"
+            ]
+        );
+    }
+
+    #[test]
+    fn search_candidates_absolute_name_skips_search_list() {
+        let candidates = search_candidates("redis.", &search(), 1);
+
+        assert_eq!(labels(candidates), vec!["redis"]);
+    }
+
+    #[test]
+    fn parse_domain_keeps_trailing_dot() {
+        let target = Url::parse("http://redis./").unwrap();
+
+        assert_eq!(parse_domain(&target).unwrap(), "redis.");
+    }
+}
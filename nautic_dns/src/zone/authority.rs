@@ -0,0 +1,551 @@
+use std::{borrow::Cow, collections::HashMap, path::Path};
+
+use super::parse_zone_file;
+use crate::{
+    errors::NauticDnsError,
+    protocol::{Class, LabelSequence, Record, RecordData, RecordType, ResponseCode},
+};
+
+/// How many CNAME and DNAME hops within the zone an answer follows.
+const MAX_ALIAS_DEPTH: usize = 8;
+
+/// A zone this server is authoritative for, answering from its records alone.
+#[derive(Debug, Clone)]
+pub struct Zone {
+    origin: LabelSequence,
+    class: Class,
+    soa: Record,
+    /// Records by lowercased owner. Empty non-terminals have an empty entry so
+    /// they answer NODATA rather than NXDOMAIN (RFC 8020).
+    nodes: HashMap<LabelSequence, Vec<Record>>,
+}
+
+/// The sections of an authoritative response, as found by [`Zone::lookup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZoneAnswer {
+    response_code: ResponseCode,
+    authoritative: bool,
+    answers: Vec<Record>,
+    authorities: Vec<Record>,
+    additionals: Vec<Record>,
+}
+
+impl ZoneAnswer {
+    pub fn response_code(&self) -> &ResponseCode {
+        &self.response_code
+    }
+    /// False only for referrals, which point elsewhere instead of answering.
+    pub fn authoritative(&self) -> bool {
+        self.authoritative
+    }
+    pub fn answers(&self) -> &[Record] {
+        &self.answers
+    }
+    pub fn authorities(&self) -> &[Record] {
+        &self.authorities
+    }
+    pub fn additionals(&self) -> &[Record] {
+        &self.additionals
+    }
+}
+
+/// Where the search for a name ended up within the zone.
+enum Found<'a> {
+    /// The name is at or below a zone cut with these NS records.
+    Delegation(Vec<Record>),
+    /// The name is below a DNAME.
+    Redirection(&'a Record),
+    /// The name exists, possibly without any records of its own, or a wildcard
+    /// stands in for it with these records.
+    Node(Cow<'a, [Record]>),
+    Missing,
+}
+
+impl Zone {
+    /// Checks `records` make up a zone for `origin`: all of them in the zone and of
+    /// one class, exactly one SOA, which is at the apex, and no CNAME sharing its
+    /// name with other data (RFC 2181 section 10.1).
+    pub fn new(origin: LabelSequence, records: Vec<Record>) -> Result<Self, NauticDnsError> {
+        let invalid = |reason: String| NauticDnsError::InvalidZone(format!("{origin}: {reason}"));
+
+        let mut soas = records
+            .iter()
+            .filter(|record| record.r#type() == &RecordType::SOA);
+        let soa = match (soas.next(), soas.next()) {
+            (Some(soa), None) if soa.name.eq_ignore_case(&origin) => soa.clone(),
+            (Some(soa), None) => {
+                return Err(invalid(format!(
+                    "the SOA is at {} instead of the apex",
+                    soa.name
+                )))
+            }
+            (None, _) => return Err(invalid("there is no SOA record".into())),
+            (Some(_), Some(_)) => return Err(invalid("there is more than one SOA record".into())),
+        };
+
+        let class = *soa.class();
+        let mut nodes: HashMap<LabelSequence, Vec<Record>> = HashMap::new();
+        for record in records {
+            if !record.name.is_subdomain_of(&origin) {
+                return Err(invalid(format!("{} is outside the zone", record.name)));
+            }
+
+            if record.class() != &class {
+                return Err(invalid(format!(
+                    "{} is not in class {}",
+                    record.name,
+                    class.mnemonic()
+                )));
+            }
+
+            let owner = record.name.to_lowercase();
+            let node = nodes.entry(owner.clone()).or_default();
+            if !node.contains(&record) {
+                node.push(record);
+            }
+
+            let mut ancestor = owner.parent();
+            while let Some(name) = ancestor.filter(|name| name.is_subdomain_of(&origin)) {
+                ancestor = name.parent();
+                nodes.entry(name).or_default();
+            }
+        }
+
+        for (name, records) in &nodes {
+            let aliases = records
+                .iter()
+                .filter(|record| record.r#type() == &RecordType::CNAME)
+                .count();
+
+            if aliases > 0 && records.len() > 1 {
+                return Err(invalid(format!(
+                    "the CNAME at {name} has other data next to it"
+                )));
+            }
+        }
+
+        Ok(Self {
+            origin,
+            class,
+            soa,
+            nodes,
+        })
+    }
+
+    /// Reads the zone for `origin` from a master file, see [`parse_zone_file`].
+    pub fn from_file(
+        path: impl AsRef<Path>,
+        origin: LabelSequence,
+    ) -> Result<Self, NauticDnsError> {
+        let records = parse_zone_file(path, &origin)?;
+
+        Self::new(origin, records)
+    }
+
+    pub fn origin(&self) -> &LabelSequence {
+        &self.origin
+    }
+    pub fn class(&self) -> &Class {
+        &self.class
+    }
+    pub fn soa(&self) -> &Record {
+        &self.soa
+    }
+
+    /// Every record in the zone, in no particular order.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.nodes.values().flatten()
+    }
+
+    /// Answers a query for `name` and `type` following RFC 1034 section 4.3.2, as
+    /// clarified for wildcards by RFC 4592.
+    /// Aliases are followed for as long as they stay within the zone. Names outside
+    /// the zone get an empty, non-authoritative answer.
+    pub fn lookup(&self, name: &LabelSequence, r#type: RecordType) -> ZoneAnswer {
+        let mut answer = ZoneAnswer {
+            response_code: ResponseCode::NoError,
+            authoritative: name.is_subdomain_of(&self.origin),
+            answers: vec![],
+            authorities: vec![],
+            additionals: vec![],
+        };
+
+        let mut name = name.clone();
+        for _ in 0..MAX_ALIAS_DEPTH {
+            // An alias leading out of the zone is answered as far as it goes
+            if !name.is_subdomain_of(&self.origin) {
+                return answer;
+            }
+
+            match self.find(&name) {
+                Found::Delegation(servers) => {
+                    answer.authoritative &= !answer.answers.is_empty();
+                    answer.additionals = self.glue(&servers);
+                    answer.authorities.extend(servers);
+                    return answer;
+                }
+                Found::Redirection(dname) => {
+                    let RecordData::DNAME(target) = dname.data() else {
+                        unreachable!("Redirections are DNAME records");
+                    };
+
+                    answer.answers.push(dname.clone());
+
+                    // Names too long once substituted are left at the DNAME alone
+                    let Some(substituted) = name.substitute(&dname.name, target) else {
+                        return answer;
+                    };
+
+                    answer.answers.push(Record::new(
+                        name,
+                        self.class,
+                        dname.ttl(),
+                        RecordData::CNAME(substituted.clone()),
+                    ));
+                    name = substituted;
+                }
+                Found::Node(records) => {
+                    let matching = records
+                        .iter()
+                        .filter(|record| record.r#type() == &r#type)
+                        .cloned()
+                        .collect::<Vec<_>>();
+
+                    if !matching.is_empty() {
+                        answer.answers.extend(matching);
+                        return answer;
+                    }
+
+                    let alias = records
+                        .iter()
+                        .find(|record| record.r#type() == &RecordType::CNAME);
+
+                    match alias.map(|record| (record, record.data())) {
+                        Some((alias, RecordData::CNAME(target))) => {
+                            answer.answers.push(alias.clone());
+                            name = target.clone();
+                        }
+                        _ => {
+                            answer.authorities.push(self.negative_soa());
+                            return answer;
+                        }
+                    }
+                }
+                Found::Missing => {
+                    answer.response_code = ResponseCode::NoDomain;
+                    answer.authorities.push(self.negative_soa());
+                    return answer;
+                }
+            }
+        }
+
+        answer
+    }
+
+    /// Walks down from the apex to `name`, stopping at the first zone cut or DNAME
+    /// on the way. Names that do not exist fall back to the wildcard below their
+    /// closest encloser.
+    fn find(&self, name: &LabelSequence) -> Found<'_> {
+        let query_name = name;
+        let name = name.to_lowercase();
+
+        let mut path = vec![];
+        let mut current = Some(name.clone());
+        while let Some(ancestor) = current.filter(|ancestor| ancestor.is_subdomain_of(&self.origin))
+        {
+            current = ancestor.parent();
+            path.push(ancestor);
+        }
+
+        let mut encloser = None;
+        for ancestor in path.iter().rev() {
+            let Some(records) = self.nodes.get(ancestor) else {
+                return match encloser {
+                    Some(encloser) => self.wildcard(encloser, query_name),
+                    None => Found::Missing,
+                };
+            };
+            encloser = Some(ancestor);
+
+            let servers = records
+                .iter()
+                .filter(|record| record.r#type() == &RecordType::NS)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !servers.is_empty() && !ancestor.eq_ignore_case(&self.origin) {
+                return Found::Delegation(servers);
+            }
+
+            if ancestor != &name {
+                if let Some(dname) = records
+                    .iter()
+                    .find(|record| record.r#type() == &RecordType::DNAME)
+                {
+                    return Found::Redirection(dname);
+                }
+            }
+        }
+
+        Found::Node(Cow::Borrowed(&self.nodes[&name]))
+    }
+
+    /// Synthesises the records for `name` from the wildcard directly below its
+    /// closest encloser (RFC 4592 section 3.3.1). Wildcards further up never apply,
+    /// so an empty non-terminal in between blocks them.
+    fn wildcard(&self, encloser: &LabelSequence, name: &LabelSequence) -> Found<'_> {
+        let source = LabelSequence::new("*").join(encloser);
+        let Some(records) = self.nodes.get(&source) else {
+            return Found::Missing;
+        };
+
+        let synthesised = records
+            .iter()
+            .map(|record| {
+                Record::new(
+                    name.clone(),
+                    self.class,
+                    record.ttl(),
+                    record.data().clone(),
+                )
+            })
+            .collect();
+
+        Found::Node(Cow::Owned(synthesised))
+    }
+
+    /// Addresses of the `servers` that are within this zone, which the resolver
+    /// could not look up otherwise (RFC 1034 section 4.2.1).
+    fn glue(&self, servers: &[Record]) -> Vec<Record> {
+        servers
+            .iter()
+            .filter_map(|server| match server.data() {
+                RecordData::NS(host) if host.is_subdomain_of(&self.origin) => {
+                    self.nodes.get(&host.to_lowercase())
+                }
+                _ => None,
+            })
+            .flatten()
+            .filter(|record| matches!(record.r#type(), RecordType::A | RecordType::AAAA))
+            .cloned()
+            .collect()
+    }
+
+    /// The SOA for negative answers, whose TTL caps how long they are cached
+    /// (RFC 2308 section 3).
+    fn negative_soa(&self) -> Record {
+        let ttl = match self.soa.data() {
+            RecordData::SOA(soa) => self.soa.ttl().min(soa.minimum),
+            _ => self.soa.ttl(),
+        };
+
+        Record::new(
+            self.soa.name.clone(),
+            self.class,
+            ttl,
+            self.soa.data().clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::zone::parse_zone;
+
+    const ZONE: &str = "
+$TTL 3600
+@       SOA     ns1 hostmaster 1 7200 1800 1209600 300
+        NS      ns1
+ns1     A       192.0.2.53
+www     A       192.0.2.80
+        A       192.0.2.81
+alias   CNAME   www
+outside CNAME   www.example.net.
+a.b.c   TXT     \"deep\"
+lab     NS      ns.lab
+        NS      ns.example.net.
+ns.lab  A       192.0.2.54
+old     DNAME   new.example.com.
+x.new   A       192.0.2.90
+";
+
+    fn origin() -> LabelSequence {
+        LabelSequence::new("example.com")
+    }
+
+    fn zone() -> Zone {
+        Zone::new(origin(), parse_zone(ZONE, &origin()).unwrap()).unwrap()
+    }
+
+    fn lookup(name: &str, r#type: RecordType) -> ZoneAnswer {
+        zone().lookup(&LabelSequence::new(name), r#type)
+    }
+
+    fn types(records: &[Record]) -> Vec<RecordType> {
+        records.iter().map(|record| *record.r#type()).collect()
+    }
+
+    #[test]
+    fn exact_match_is_answered() {
+        let answer = lookup("WWW.example.com", RecordType::A);
+
+        assert_eq!(answer.response_code(), &ResponseCode::NoError);
+        assert!(answer.authoritative());
+        assert_eq!(
+            answer.answers()[1].data(),
+            &RecordData::A(Ipv4Addr::new(192, 0, 2, 81))
+        );
+        assert!(answer.authorities().is_empty());
+    }
+
+    #[test]
+    fn alias_is_followed_within_zone() {
+        let answer = lookup("alias.example.com", RecordType::A);
+        assert_eq!(
+            types(answer.answers()),
+            vec![RecordType::CNAME, RecordType::A, RecordType::A]
+        );
+
+        let answer = lookup("alias.example.com", RecordType::CNAME);
+        assert_eq!(types(answer.answers()), vec![RecordType::CNAME]);
+
+        let answer = lookup("outside.example.com", RecordType::A);
+        assert_eq!(types(answer.answers()), vec![RecordType::CNAME]);
+        assert_eq!(answer.response_code(), &ResponseCode::NoError);
+    }
+
+    #[test]
+    fn missing_type_is_nodata_and_missing_name_is_nxdomain() {
+        for name in ["www.example.com", "b.c.example.com"] {
+            let answer = lookup(name, RecordType::MX);
+
+            assert_eq!(answer.response_code(), &ResponseCode::NoError, "{name}");
+            assert!(answer.answers().is_empty());
+            assert_eq!(types(answer.authorities()), vec![RecordType::SOA]);
+        }
+
+        let answer = lookup("nope.example.com", RecordType::A);
+        assert_eq!(answer.response_code(), &ResponseCode::NoDomain);
+        assert!(answer.authoritative());
+        assert_eq!(answer.authorities()[0].ttl(), 300);
+    }
+
+    #[test]
+    fn names_below_cut_are_referred_with_glue() {
+        for name in ["lab.example.com", "host.deep.lab.example.com"] {
+            let answer = lookup(name, RecordType::A);
+
+            assert!(!answer.authoritative());
+            assert!(answer.answers().is_empty());
+            assert_eq!(types(answer.authorities()), vec![RecordType::NS; 2]);
+            assert_eq!(types(answer.additionals()), vec![RecordType::A]);
+        }
+    }
+
+    #[test]
+    fn dname_synthesises_alias() {
+        let answer = lookup("x.old.example.com", RecordType::A);
+
+        assert_eq!(
+            types(answer.answers()),
+            vec![RecordType::DNAME, RecordType::CNAME, RecordType::A]
+        );
+        assert_eq!(
+            answer.answers()[1].data(),
+            &RecordData::CNAME(LabelSequence::new("x.new.example.com"))
+        );
+    }
+
+    #[test]
+    fn invalid_zones_are_rejected() {
+        let cases = [
+            "www A 192.0.2.1",
+            "@ SOA ns host 1 2 3 4 5\nwww.example.org. A 192.0.2.1",
+            "@ SOA ns host 1 2 3 4 5\nwww CNAME @\nwww A 192.0.2.1",
+            "@ SOA ns host 1 2 3 4 5\n@ SOA ns host 2 2 3 4 5",
+        ];
+
+        for case in cases {
+            let records = parse_zone(&format!("$TTL 60\n{case}\n"), &origin()).unwrap();
+            let result = Zone::new(origin(), records);
+
+            assert!(
+                matches!(result, Err(NauticDnsError::InvalidZone(_))),
+                "{case}"
+            );
+        }
+    }
+
+    /// The example zone of RFC 4592 section 2.2.1, with TXT in place of SRV.
+    const WILDCARDS: &str = "
+$TTL 3600
+@                       SOA     ns host 1 7200 1800 1209600 300
+                        NS      ns.example.com.
+*                       TXT     \"this is a wildcard\"
+                        MX      10 host1
+sub.*                   TXT     \"this is not a wildcard\"
+host1                   A       192.0.2.1
+_ssh._tcp.host1         TXT     \"ssh\"
+_ssh._tcp.host2         TXT     \"ssh\"
+subdel                  NS      ns.example.com.
+*.preview               CNAME   host1
+";
+
+    fn wildcard_lookup(name: &str, r#type: RecordType) -> ZoneAnswer {
+        let origin = LabelSequence::new("example");
+        let zone = Zone::new(origin.clone(), parse_zone(WILDCARDS, &origin).unwrap()).unwrap();
+
+        zone.lookup(&LabelSequence::new(name), r#type)
+    }
+
+    #[test]
+    fn wildcard_answers_with_query_name_as_owner() {
+        for name in ["host3.example", "foo.bar.example"] {
+            let answer = wildcard_lookup(name, RecordType::MX);
+
+            assert_eq!(answer.response_code(), &ResponseCode::NoError);
+            assert!(answer.authoritative());
+            assert_eq!(answer.answers().len(), 1, "{name}");
+            assert_eq!(answer.answers()[0].name(), name);
+        }
+
+        let answer = wildcard_lookup("pr-42.preview.example", RecordType::A);
+        assert_eq!(
+            types(answer.answers()),
+            vec![RecordType::CNAME, RecordType::A]
+        );
+        assert_eq!(answer.answers()[0].name(), "pr-42.preview.example");
+    }
+
+    #[test]
+    fn wildcard_without_type_is_nodata() {
+        let answer = wildcard_lookup("host3.example", RecordType::A);
+
+        assert_eq!(answer.response_code(), &ResponseCode::NoError);
+        assert!(answer.answers().is_empty());
+        assert_eq!(types(answer.authorities()), vec![RecordType::SOA]);
+    }
+
+    #[test]
+    fn existing_names_and_empty_non_terminals_block_wildcard() {
+        for name in ["host1.example", "sub.*.example", "_tcp.host1.example"] {
+            let answer = wildcard_lookup(name, RecordType::MX);
+
+            assert_eq!(answer.response_code(), &ResponseCode::NoError, "{name}");
+            assert!(answer.answers().is_empty(), "{name}");
+        }
+
+        for name in ["_telnet._tcp.host1.example", "ghost.*.example"] {
+            let answer = wildcard_lookup(name, RecordType::TXT);
+
+            assert_eq!(answer.response_code(), &ResponseCode::NoDomain, "{name}");
+        }
+
+        let answer = wildcard_lookup("host.subdel.example", RecordType::MX);
+        assert!(!answer.authoritative());
+        assert_eq!(types(answer.authorities()), vec![RecordType::NS]);
+    }
+}
@@ -0,0 +1,169 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+
+use super::Zone;
+use crate::{
+    protocol::{LabelSequence, Message, ResponseCode},
+    server::{Request, RequestHandler},
+};
+
+/// The zones a server is authoritative for, answering each query from the zone
+/// closest to the queried name.
+#[derive(Debug, Clone, Default)]
+pub struct ZoneCatalog {
+    /// Zones by lowercased origin.
+    zones: HashMap<LabelSequence, Arc<Zone>>,
+}
+
+impl ZoneCatalog {
+    pub fn new(zones: Vec<Zone>) -> Self {
+        let mut catalog = Self::default();
+        for zone in zones {
+            catalog.insert(zone);
+        }
+
+        catalog
+    }
+
+    /// Adds `zone`, returning the zone it replaces for the same origin.
+    pub fn insert(&mut self, zone: Zone) -> Option<Arc<Zone>> {
+        self.zones
+            .insert(zone.origin().to_lowercase(), Arc::new(zone))
+    }
+
+    pub fn remove(&mut self, origin: &LabelSequence) -> Option<Arc<Zone>> {
+        self.zones.remove(&origin.to_lowercase())
+    }
+
+    pub fn zones(&self) -> impl Iterator<Item = &Arc<Zone>> {
+        self.zones.values()
+    }
+
+    /// The zone with the longest origin that `name` is in, so a child zone served
+    /// here wins over the delegation in its parent.
+    pub fn find(&self, name: &LabelSequence) -> Option<&Arc<Zone>> {
+        let mut current = Some(name.to_lowercase());
+        while let Some(name) = current {
+            if let Some(zone) = self.zones.get(&name) {
+                return Some(zone);
+            }
+
+            current = name.parent();
+        }
+
+        None
+    }
+}
+
+#[async_trait]
+impl RequestHandler for ZoneCatalog {
+    /// Queries for names outside every zone, or in another class, are refused as
+    /// this server only answers authoritatively.
+    async fn handle(&self, request: &Request) -> Option<Message> {
+        let question = request.message().question()?;
+        let zone = self
+            .find(question.name())
+            .filter(|zone| zone.class() == question.class());
+
+        let Some(zone) = zone else {
+            return request.reply(ResponseCode::Refused).build().ok();
+        };
+
+        let answer = zone.lookup(question.name(), *question.r#type());
+        let mut reply = match answer.authoritative() {
+            true => request.authoritative_reply(answer.response_code().clone()),
+            false => request.reply(answer.response_code().clone()),
+        };
+
+        reply
+            .answers(answer.answers().to_vec())
+            .authorities(answer.authorities().to_vec())
+            .additionals(answer.additionals().to_vec())
+            .build()
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::{
+        protocol::{Class, Query, RecordType},
+        transport::Protocol,
+        zone::parse_zone,
+    };
+
+    fn zone(origin: &str, text: &str) -> Zone {
+        let origin = LabelSequence::new(origin);
+        let records = parse_zone(text, &origin).unwrap();
+
+        Zone::new(origin, records).unwrap()
+    }
+
+    fn catalog() -> ZoneCatalog {
+        ZoneCatalog::new(vec![
+            zone(
+                "example.com",
+                "$TTL 300\n@ SOA ns1 host 1 2 3 4 60\n@ NS ns1\nns1 A 192.0.2.53\n\
+                 lab NS ns.lab\nns.lab A 192.0.2.54\n",
+            ),
+            zone(
+                "lab.example.com",
+                "$TTL 300\n@ SOA ns host 1 2 3 4 60\n@ NS ns\nns A 192.0.2.54\nhost A 192.0.2.80\n",
+            ),
+        ])
+    }
+
+    fn request(name: &str, class: Class) -> Request {
+        let query = Query::new(LabelSequence::new(name), RecordType::A, class);
+        let source = SocketAddr::from(([192, 0, 2, 1], 5353));
+
+        Request::new(Message::query(query, false), source, Protocol::Udp)
+    }
+
+    #[test]
+    fn longest_origin_is_chosen() {
+        let catalog = catalog();
+
+        let zone = catalog.find(&LabelSequence::new("host.LAB.example.com"));
+        assert_eq!(
+            zone.unwrap().origin(),
+            &LabelSequence::new("lab.example.com")
+        );
+
+        let zone = catalog.find(&LabelSequence::new("www.example.com"));
+        assert_eq!(zone.unwrap().origin(), &LabelSequence::new("example.com"));
+
+        assert!(catalog.find(&LabelSequence::new("example.org")).is_none());
+    }
+
+    #[tokio::test]
+    async fn child_zone_answers_authoritatively() {
+        let response = catalog()
+            .handle(&request("host.lab.example.com", Class::IN))
+            .await
+            .unwrap();
+
+        assert!(response.header().flags().authoritative_answer());
+        assert!(!response.header().flags().recursion_available());
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn names_outside_zones_are_refused() {
+        let catalog = catalog();
+
+        for request in [
+            request("www.example.org", Class::IN),
+            request("www.example.com", Class::CH),
+        ] {
+            let response = catalog.handle(&request).await.unwrap();
+
+            assert_eq!(response.response_code(), &ResponseCode::Refused);
+            assert!(!response.header().flags().authoritative_answer());
+        }
+    }
+}
@@ -0,0 +1,9 @@
+mod authority;
+mod catalog;
+mod parser;
+mod writer;
+
+pub use authority::*;
+pub use catalog::*;
+pub use parser::*;
+pub use writer::*;
@@ -0,0 +1,876 @@
+use std::{
+    fs,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::protocol::{
+    split_labels, unescape, Class, LabelSequence, Record, RecordData, RecordType, StartOfAuthority,
+};
+
+/// How deeply `$INCLUDE` directives may nest, which also stops include loops.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// A zone file that could not be parsed, pointing at the offending token.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("{source_name}:{line}:{column}: {reason}")]
+pub struct ZoneParseError {
+    source_name: String,
+    line: usize,
+    column: usize,
+    reason: String,
+}
+
+impl ZoneParseError {
+    fn new(source_name: &str, line: usize, column: usize, reason: impl Into<String>) -> Self {
+        Self {
+            source_name: source_name.to_owned(),
+            line,
+            column,
+            reason: reason.into(),
+        }
+    }
+
+    /// The file the error is in, or `<zone>` for zones parsed from a string.
+    pub fn source_name(&self) -> &str {
+        &self.source_name
+    }
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn column(&self) -> usize {
+        self.column
+    }
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+/// Parses a zone in RFC 1035 master file format (section 5), with relative names
+/// completed by `origin`. `$INCLUDE` paths are relative to the working directory.
+pub fn parse_zone(text: &str, origin: &LabelSequence) -> Result<Vec<Record>, ZoneParseError> {
+    let mut parser = ZoneParser::default();
+    parser.parse(text, "<zone>", None, State::new(origin.clone()))?;
+
+    Ok(parser.records)
+}
+
+/// Parses the zone file at `path` like [`parse_zone`]. `$INCLUDE` paths are
+/// relative to the directory of the including file.
+pub fn parse_zone_file(
+    path: impl AsRef<Path>,
+    origin: &LabelSequence,
+) -> Result<Vec<Record>, ZoneParseError> {
+    let path = path.as_ref();
+    let source_name = path.display().to_string();
+    let text = fs::read_to_string(path)
+        .map_err(|error| ZoneParseError::new(&source_name, 0, 0, error.to_string()))?;
+
+    let mut parser = ZoneParser::default();
+    parser.parse(
+        &text,
+        &source_name,
+        path.parent(),
+        State::new(origin.clone()),
+    )?;
+
+    Ok(parser.records)
+}
+
+/// One field of an entry. Escapes are kept as written, so names can tell an
+/// escaped dot from a label separator, and are decoded by the field parsers.
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    quoted: bool,
+    line: usize,
+    column: usize,
+}
+
+/// One record or directive, which parentheses may spread over several lines.
+#[derive(Debug)]
+struct Entry {
+    tokens: Vec<Token>,
+    /// The entry started with whitespace, so it has no owner of its own.
+    inherits_owner: bool,
+}
+
+/// What later entries inherit from earlier ones.
+#[derive(Debug, Clone)]
+struct State {
+    origin: LabelSequence,
+    default_ttl: Option<u32>,
+    last_ttl: Option<u32>,
+    last_owner: Option<LabelSequence>,
+    last_class: Class,
+}
+
+impl State {
+    fn new(origin: LabelSequence) -> Self {
+        Self {
+            origin,
+            default_ttl: None,
+            last_ttl: None,
+            last_owner: None,
+            last_class: Class::IN,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ZoneParser {
+    records: Vec<Record>,
+    depth: usize,
+}
+
+impl ZoneParser {
+    fn parse(
+        &mut self,
+        text: &str,
+        source_name: &str,
+        directory: Option<&Path>,
+        mut state: State,
+    ) -> Result<(), ZoneParseError> {
+        let error = |token: &Token, reason: String| {
+            ZoneParseError::new(source_name, token.line, token.column, reason)
+        };
+
+        for entry in tokenize(text).map_err(|(line, column, reason)| {
+            ZoneParseError::new(source_name, line, column, reason)
+        })? {
+            let mut tokens = entry.tokens.iter().peekable();
+            let first = &entry.tokens[0];
+
+            if !entry.inherits_owner && first.text.starts_with('$') && !first.quoted {
+                tokens.next();
+                let mut argument = |name: &str| {
+                    tokens
+                        .next()
+                        .ok_or_else(|| error(first, format!("{} needs a {name}", first.text)))
+                };
+
+                match first.text.to_ascii_uppercase().as_str() {
+                    "$ORIGIN" => {
+                        let name = argument("name")?;
+                        state.origin = parse_name(name, &state.origin)
+                            .map_err(|reason| error(name, reason))?;
+                    }
+                    "$TTL" => {
+                        let ttl = argument("TTL")?;
+                        state.default_ttl = Some(
+                            parse_ttl(&ttl.text)
+                                .ok_or_else(|| error(ttl, format!("invalid TTL {}", ttl.text)))?,
+                        );
+                    }
+                    "$INCLUDE" => {
+                        let file = argument("file name")?.clone();
+                        let origin = match tokens.next() {
+                            Some(name) => parse_name(name, &state.origin)
+                                .map_err(|reason| error(name, reason))?,
+                            None => state.origin.clone(),
+                        };
+
+                        self.include(&file, directory, &state, origin)
+                            .map_err(|reason| match reason {
+                                Include::Nested(nested) => nested,
+                                Include::Failed(reason) => error(&file, reason),
+                            })?;
+                    }
+                    directive => {
+                        return Err(error(first, format!("unknown directive {directive}")))
+                    }
+                }
+
+                if let Some(extra) = tokens.next() {
+                    return Err(error(extra, format!("unexpected {}", extra.text)));
+                }
+
+                continue;
+            }
+
+            let owner = match entry.inherits_owner {
+                true => state
+                    .last_owner
+                    .clone()
+                    .ok_or_else(|| error(first, "the first record needs an owner".into()))?,
+                false => {
+                    let owner = tokens.next().expect("Entries have at least one token");
+                    parse_name(owner, &state.origin).map_err(|reason| error(owner, reason))?
+                }
+            };
+
+            // TTL and class may come in either order, and either may be left out
+            let mut ttl = None;
+            let mut class = None;
+            while let Some(token) = tokens.peek() {
+                if class.is_none() && !token.quoted {
+                    if let Some(parsed) = Class::from_mnemonic(&token.text) {
+                        class = Some(parsed);
+                        tokens.next();
+                        continue;
+                    }
+                }
+
+                if ttl.is_none() && !token.quoted {
+                    if let Some(parsed) = parse_ttl(&token.text) {
+                        ttl = Some(parsed);
+                        tokens.next();
+                        continue;
+                    }
+                }
+
+                break;
+            }
+
+            let type_token = tokens
+                .next()
+                .ok_or_else(|| error(entry.tokens.last().unwrap(), "missing record type".into()))?;
+            let r#type = RecordType::from_mnemonic(&type_token.text)
+                .filter(|_| !type_token.quoted)
+                .ok_or_else(|| {
+                    error(
+                        type_token,
+                        format!("unknown record type {}", type_token.text),
+                    )
+                })?;
+
+            let rdata = tokens.cloned().collect::<Vec<_>>();
+            let data = parse_rdata(r#type, type_token, &rdata, &state.origin)
+                .map_err(|(token, reason)| error(&token, reason))?;
+
+            let ttl = ttl
+                .or(state.default_ttl)
+                .or(state.last_ttl)
+                .or(match &data {
+                    // Without any TTL in sight, BIND falls back to the SOA minimum
+                    RecordData::SOA(soa) => Some(soa.minimum),
+                    _ => None,
+                })
+                .ok_or_else(|| error(type_token, "no TTL given and no $TTL set".into()))?;
+
+            let class = class.unwrap_or(state.last_class);
+
+            state.last_owner = Some(owner.clone());
+            state.last_ttl = Some(ttl);
+            state.last_class = class;
+
+            self.records.push(Record::new(owner, class, ttl, data));
+        }
+
+        Ok(())
+    }
+
+    /// Parses the file named by `file` into the records, under `origin`. The
+    /// including file's origin and owner are left as they were (RFC 1035 section
+    /// 5.1).
+    fn include(
+        &mut self,
+        file: &Token,
+        directory: Option<&Path>,
+        state: &State,
+        origin: LabelSequence,
+    ) -> Result<(), Include> {
+        if self.depth >= MAX_INCLUDE_DEPTH {
+            return Err(Include::Failed("$INCLUDE is nested too deeply".into()));
+        }
+
+        let path = match directory {
+            Some(directory) => directory.join(&file.text),
+            None => PathBuf::from(&file.text),
+        };
+
+        let text = fs::read_to_string(&path).map_err(|reason| {
+            Include::Failed(format!("cannot read {}: {reason}", path.display()))
+        })?;
+
+        let mut state = state.clone();
+        state.origin = origin;
+        state.last_owner = None;
+
+        self.depth += 1;
+        let result = self.parse(&text, &path.display().to_string(), path.parent(), state);
+        self.depth -= 1;
+
+        result.map_err(Include::Nested)
+    }
+}
+
+enum Include {
+    Nested(ZoneParseError),
+    Failed(String),
+}
+
+type TokenError = (Token, String);
+
+fn parse_rdata(
+    r#type: RecordType,
+    type_token: &Token,
+    tokens: &[Token],
+    origin: &LabelSequence,
+) -> Result<RecordData, TokenError> {
+    let mut fields = tokens.iter();
+    let mut next = |field: &str| {
+        fields.next().ok_or_else(|| {
+            (
+                type_token.clone(),
+                format!("{} record is missing its {field}", r#type.mnemonic()),
+            )
+        })
+    };
+
+    let name = |token: &Token| parse_name(token, origin).map_err(|reason| (token.clone(), reason));
+    let number = |token: &Token| parse_number::<u16>(token);
+
+    let data = match r#type {
+        RecordType::A => RecordData::A(parse_number::<Ipv4Addr>(next("address")?)?),
+        RecordType::AAAA => RecordData::AAAA(parse_number::<Ipv6Addr>(next("address")?)?),
+        RecordType::CNAME => RecordData::CNAME(name(next("target")?)?),
+        RecordType::DNAME => RecordData::DNAME(name(next("target")?)?),
+        RecordType::NS => RecordData::NS(name(next("name server")?)?),
+        RecordType::PTR => RecordData::PTR(name(next("target")?)?),
+        RecordType::MX => RecordData::MX {
+            preference: number(next("preference")?)?,
+            exchange: name(next("exchange")?)?,
+        },
+        RecordType::SOA => {
+            let mname = name(next("primary name server")?)?;
+            let rname = name(next("mailbox")?)?;
+            let mut timer = |field: &str| {
+                let token = next(field)?;
+                parse_ttl(&token.text)
+                    .ok_or_else(|| (token.clone(), format!("invalid SOA {field} {}", token.text)))
+            };
+
+            RecordData::SOA(StartOfAuthority {
+                mname,
+                rname,
+                serial: timer("serial")?,
+                refresh: timer("refresh")?,
+                retry: timer("retry")?,
+                expire: timer("expire")?,
+                minimum: timer("minimum")?,
+            })
+        }
+        RecordType::TXT => {
+            let strings = tokens
+                .iter()
+                .map(|token| unescape(&token.text))
+                .collect::<Vec<_>>();
+            if strings.is_empty() {
+                return Err((
+                    type_token.clone(),
+                    "TXT record needs at least one string".into(),
+                ));
+            }
+
+            if let Some((long, _)) = tokens
+                .iter()
+                .zip(&strings)
+                .find(|(_, string)| string.len() > u8::MAX as usize)
+            {
+                return Err((long.clone(), "TXT strings are at most 255 bytes".into()));
+            }
+
+            return Ok(RecordData::TXT(strings));
+        }
+        RecordType::OPT | RecordType::TSIG => {
+            return Err((
+                type_token.clone(),
+                format!("{} records cannot appear in zone files", r#type.mnemonic()),
+            ))
+        }
+        RecordType::Unknown(r#type) => {
+            let marker = next("\\# marker")?;
+            if marker.text != "\\#" || marker.quoted {
+                return Err((
+                    marker.clone(),
+                    format!("TYPE{} records need the \\# syntax", r#type),
+                ));
+            }
+
+            let length = parse_number::<usize>(next("length")?)?;
+            let hex = fields.map(|token| token.text.as_str()).collect::<String>();
+            let data = decode_hex(&hex)
+                .filter(|data| data.len() == length)
+                .ok_or_else(|| {
+                    (
+                        marker.clone(),
+                        format!("RDATA does not match its length {length}"),
+                    )
+                })?;
+
+            return Ok(RecordData::Unknown { r#type, data });
+        }
+    };
+
+    match fields.next() {
+        Some(extra) => Err((extra.clone(), format!("unexpected {}", extra.text))),
+        None => Ok(data),
+    }
+}
+
+/// Decodes hexadecimal digits into bytes, as in the RDATA of the generic syntax
+/// (RFC 3597 section 5).
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 || !text.is_ascii() {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(&text[start..start + 2], 16).ok())
+        .collect()
+}
+
+fn parse_number<T: FromStr>(token: &Token) -> Result<T, TokenError> {
+    token
+        .text
+        .parse::<T>()
+        .map_err(|_| (token.clone(), format!("invalid value {}", token.text)))
+}
+
+/// Completes `token` into an absolute name: `@` is the origin, names ending in a
+/// dot are already absolute and anything else is relative to the origin.
+fn parse_name(token: &Token, origin: &LabelSequence) -> Result<LabelSequence, String> {
+    let text = token.text.as_str();
+    if text == "@" {
+        return Ok(origin.clone());
+    }
+
+    if text == "." {
+        return Ok(LabelSequence::root());
+    }
+
+    // Escaped dots belong to their label rather than separating labels
+    let mut labels = split_labels(text).collect::<Vec<_>>();
+    let absolute = labels.len() > 1 && labels.last() == Some(&"");
+    if absolute {
+        labels.pop();
+    }
+
+    if labels.iter().any(|label| label.is_empty()) {
+        return Err(format!("invalid name {text}"));
+    }
+
+    let name = match absolute {
+        true => LabelSequence::new(text),
+        false => LabelSequence::new(text).join(origin),
+    };
+
+    if name.label_bytes().any(|label| label.len() > 63) {
+        return Err(format!("label of {text} is longer than 63 bytes"));
+    }
+
+    if name.total_bits() > 255 * 8 {
+        return Err(format!("name {text} is longer than 255 bytes"));
+    }
+
+    Ok(name)
+}
+
+/// Parses a TTL in seconds, or in BIND's unit notation such as `1h30m` or `2W`.
+fn parse_ttl(text: &str) -> Option<u32> {
+    if !text.starts_with(|character: char| character.is_ascii_digit()) {
+        return None;
+    }
+
+    if let Ok(seconds) = text.parse::<u32>() {
+        return Some(seconds);
+    }
+
+    let mut total = 0u32;
+    let mut value = String::new();
+    for character in text.chars() {
+        if character.is_ascii_digit() {
+            value.push(character);
+            continue;
+        }
+
+        let unit = match character.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+
+        let amount = value.parse::<u32>().ok()?;
+        total = total.checked_add(amount.checked_mul(unit)?)?;
+        value.clear();
+    }
+
+    value.is_empty().then_some(total)
+}
+
+type LexError = (usize, usize, String);
+
+/// Splits `text` into entries of tokens, dropping comments and joining the lines
+/// inside parentheses.
+fn tokenize(text: &str) -> Result<Vec<Entry>, LexError> {
+    let mut entries = vec![];
+    let mut tokens: Vec<Token> = vec![];
+    let mut inherits_owner = false;
+    let mut open_parens: Vec<(usize, usize)> = vec![];
+    let mut line_start = true;
+
+    let mut characters = text.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    while let Some(character) = characters.next() {
+        let (token_line, token_column) = (line, column);
+        column += 1;
+
+        match character {
+            '\n' => {
+                if open_parens.is_empty() {
+                    if !tokens.is_empty() {
+                        entries.push(Entry {
+                            tokens: std::mem::take(&mut tokens),
+                            inherits_owner,
+                        });
+                    }
+                    inherits_owner = false;
+                }
+
+                line += 1;
+                column = 1;
+                line_start = true;
+                continue;
+            }
+            ' ' | '\t' | '\r' => {
+                if line_start && open_parens.is_empty() && tokens.is_empty() {
+                    inherits_owner = true;
+                }
+            }
+            ';' => while characters.next_if(|next| *next != '\n').is_some() {},
+            '(' => open_parens.push((token_line, token_column)),
+            ')' => {
+                if open_parens.pop().is_none() {
+                    return Err((token_line, token_column, "unbalanced )".into()));
+                }
+            }
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match characters.next() {
+                        Some('"') => break,
+                        Some('\\') => {
+                            let (escape, width) = escape_sequence(&mut characters).ok_or((
+                                line,
+                                column,
+                                "invalid escape".to_owned(),
+                            ))?;
+                            value.push_str(&escape);
+                            column += width + 1;
+                            continue;
+                        }
+                        Some('\n') | None => {
+                            return Err((token_line, token_column, "unterminated string".into()))
+                        }
+                        Some(character) => value.push(character),
+                    }
+                    column += 1;
+                }
+                column += 1;
+
+                tokens.push(Token {
+                    text: value,
+                    quoted: true,
+                    line: token_line,
+                    column: token_column,
+                });
+            }
+            character => {
+                let mut value = String::new();
+                let mut next = Some(character);
+                while let Some(character) = next {
+                    if character == '\\' {
+                        let (escape, width) = escape_sequence(&mut characters).ok_or((
+                            line,
+                            column,
+                            "invalid escape".to_owned(),
+                        ))?;
+                        value.push_str(&escape);
+                        column += width;
+                    } else {
+                        value.push(character);
+                    }
+
+                    next = characters.next_if(|next| !" \t\r\n;()\"".contains(*next));
+                    if next.is_some() {
+                        column += 1;
+                    }
+                }
+
+                tokens.push(Token {
+                    text: value,
+                    quoted: false,
+                    line: token_line,
+                    column: token_column,
+                });
+            }
+        }
+
+        line_start = false;
+    }
+
+    if let Some((line, column)) = open_parens.pop() {
+        return Err((line, column, "unbalanced (".into()));
+    }
+
+    if !tokens.is_empty() {
+        entries.push(Entry {
+            tokens,
+            inherits_owner,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Reads the escape after a backslash, either `\DDD` with a decimal byte value or
+/// `\X` for a literal X. Returns the escape as written, backslash included, and
+/// how many characters followed the backslash.
+fn escape_sequence(
+    characters: &mut std::iter::Peekable<std::str::Chars>,
+) -> Option<(String, usize)> {
+    let first = characters.next()?;
+    if !first.is_ascii_digit() {
+        return Some((format!("\\{first}"), 1));
+    }
+
+    let mut digits = String::from(first);
+    for _ in 0..2 {
+        digits.push(characters.next_if(char::is_ascii_digit)?);
+    }
+
+    digits.parse::<u8>().ok()?;
+    Some((format!("\\{digits}"), 3))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn origin() -> LabelSequence {
+        LabelSequence::new("example.com")
+    }
+
+    const ZONE: &str = r#"
+$ORIGIN example.com.
+$TTL 1h
+@   IN  SOA ns1 hostmaster (
+            2024010101 ; serial
+            2h         ; refresh
+            30m        ; retry
+            2w         ; expire
+            300 )      ; minimum
+        NS  ns1
+        NS  ns2.example.net.
+        MX  10 mail
+ns1 300 A   192.0.2.53
+    IN 600 AAAA 2001:db8::53
+www     CNAME   @
+txt     TXT "v=spf1 -all" "second \"quoted\" string" unquoted
+$ORIGIN sub.example.com.
+host    A   192.0.2.80
+alias   DNAME   other.example.
+"#;
+
+    #[test]
+    fn zone_with_every_feature_parses() {
+        let records = parse_zone(ZONE, &origin()).unwrap();
+        let summary = records
+            .iter()
+            .map(|record| (record.name().to_owned(), *record.r#type(), record.ttl()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            summary,
+            vec![
+                ("example.com".into(), RecordType::SOA, 3600),
+                ("example.com".into(), RecordType::NS, 3600),
+                ("example.com".into(), RecordType::NS, 3600),
+                ("example.com".into(), RecordType::MX, 3600),
+                ("ns1.example.com".into(), RecordType::A, 300),
+                ("ns1.example.com".into(), RecordType::AAAA, 600),
+                ("www.example.com".into(), RecordType::CNAME, 3600),
+                ("txt.example.com".into(), RecordType::TXT, 3600),
+                ("host.sub.example.com".into(), RecordType::A, 3600),
+                ("alias.sub.example.com".into(), RecordType::DNAME, 3600),
+            ]
+        );
+
+        let RecordData::SOA(soa) = records[0].data() else {
+            panic!("expected the SOA first");
+        };
+        assert_eq!(soa.mname, LabelSequence::new("ns1.example.com"));
+        assert_eq!(
+            (soa.serial, soa.refresh, soa.retry, soa.expire, soa.minimum),
+            (2024010101, 7200, 1800, 1209600, 300)
+        );
+
+        assert_eq!(
+            records[2].data(),
+            &RecordData::NS(LabelSequence::new("ns2.example.net"))
+        );
+        assert_eq!(
+            records[3].data(),
+            &RecordData::MX {
+                preference: 10,
+                exchange: LabelSequence::new("mail.example.com")
+            }
+        );
+        assert_eq!(
+            records[6].data(),
+            &RecordData::CNAME(LabelSequence::new("example.com"))
+        );
+        assert_eq!(
+            records[7].data(),
+            &RecordData::TXT(vec![
+                "v=spf1 -all".into(),
+                "second \"quoted\" string".into(),
+                "unquoted".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn ttl_is_inherited_without_default() {
+        let records = parse_zone("a 120 A 192.0.2.1\nb A 192.0.2.2\n", &origin()).unwrap();
+
+        assert_eq!(records[1].ttl(), 120);
+    }
+
+    #[test]
+    fn errors_report_line_and_column() {
+        let cases = [
+            ("$TTL 60\nwww A 192.0.2.256\n", 2, 7),
+            ("$TTL 60\nwww   BOGUS x\n", 2, 7),
+            ("$TTL 60\n  A 192.0.2.1\n", 2, 3),
+            ("$TTL 60\n@ SOA ns1 host ( 1 2 3 4\n", 2, 16),
+            ("www A 192.0.2.1\n", 1, 5),
+            ("$TTL 60\nwww MX 10\n", 2, 5),
+            ("$TTL 60\nwww A 192.0.2.1 extra\n", 2, 17),
+            ("$TTL 60\nx TYPE65 \\# 2 00\n", 2, 10),
+        ];
+
+        for (zone, line, column) in cases {
+            let error = parse_zone(zone, &origin()).unwrap_err();
+            assert_eq!(
+                (error.line(), error.column()),
+                (line, column),
+                "{zone:?} failed with {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn include_uses_its_own_origin() {
+        let directory = env::temp_dir().join(format!("nautic-zone-{}", rand::random::<u64>()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("hosts.zone"), "host A 192.0.2.10\n").unwrap();
+        fs::write(
+            directory.join("main.zone"),
+            "$TTL 60\n$INCLUDE hosts.zone lab.example.com.\nafter A 192.0.2.11\n",
+        )
+        .unwrap();
+
+        let records = parse_zone_file(directory.join("main.zone"), &origin());
+        fs::remove_dir_all(&directory).unwrap();
+
+        let names = records
+            .unwrap()
+            .iter()
+            .map(|record| record.name().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["host.lab.example.com", "after.example.com"]);
+    }
+
+    #[test]
+    fn include_errors_name_the_included_file() {
+        let directory = env::temp_dir().join(format!("nautic-zone-{}", rand::random::<u64>()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("broken.zone"), "\nhost A nope\n").unwrap();
+        fs::write(
+            directory.join("main.zone"),
+            "$TTL 60\n$INCLUDE broken.zone\n",
+        )
+        .unwrap();
+
+        let error = parse_zone_file(directory.join("main.zone"), &origin()).unwrap_err();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert!(error.source_name().ends_with("broken.zone"));
+        assert_eq!((error.line(), error.column()), (2, 8));
+    }
+
+    #[test]
+    fn escapes_keep_exact_bytes() {
+        let zone = "$TTL 60\ncaf\\233 A 192.0.2.1\na\\.b TXT \"caf\\233\" \\\"x\\\"\n";
+        let records = parse_zone(zone, &origin()).unwrap();
+        let names = records
+            .iter()
+            .map(|record| LabelSequence::new(record.name()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names[0].label_bytes().collect::<Vec<_>>(),
+            vec![b"caf\xe9".to_vec(), b"example".to_vec(), b"com".to_vec()]
+        );
+        let bytes: Bytes = names[0].clone().into();
+        assert_eq!(&bytes[..5], b"\x04caf\xe9");
+
+        assert_eq!(names[1].label_count(), 3);
+        assert_eq!(names[1].labels().next(), Some("a\\.b"));
+        assert_eq!(
+            records[1].data(),
+            &RecordData::TXT(vec![b"caf\xe9".to_vec(), b"\"x\"".to_vec()])
+        );
+    }
+
+    #[test]
+    fn txt_length_counts_bytes() {
+        let fits = format!("$TTL 60\nt TXT \"{}\"\n", "\\233".repeat(255));
+        let RecordData::TXT(strings) = parse_zone(&fits, &origin()).unwrap()[0].data().clone()
+        else {
+            panic!("expected a TXT record");
+        };
+        assert_eq!(strings, vec![vec![0xe9; 255]]);
+
+        let long = format!("$TTL 60\nt TXT \"{}\"\n", "\\233".repeat(256));
+        let error = parse_zone(&long, &origin()).unwrap_err();
+        assert_eq!(error.reason(), "TXT strings are at most 255 bytes");
+    }
+
+    #[test]
+    fn invalid_escapes_and_labels_are_rejected() {
+        for zone in [
+            "$TTL 60\nx\\256 A 192.0.2.1\n",
+            "$TTL 60\nx\\25 A 192.0.2.1\n",
+            "$TTL 60\na..b A 192.0.2.1\n",
+            "$TTL 60\n.a A 192.0.2.1\n",
+        ] {
+            assert!(parse_zone(zone, &origin()).is_err(), "{zone:?} parsed");
+        }
+
+        let long = format!("$TTL 60\n{} A 192.0.2.1\n", "\\255".repeat(64));
+        assert!(parse_zone(&long, &origin()).is_err());
+    }
+
+    #[test]
+    fn ttl_units_are_summed() {
+        assert_eq!(parse_ttl("1h30m"), Some(5400));
+        assert_eq!(parse_ttl("1W"), Some(604800));
+        assert_eq!(parse_ttl("h"), None);
+        assert_eq!(parse_ttl("10x"), None);
+    }
+}
@@ -0,0 +1,207 @@
+use std::{cmp::Ordering, fmt::Write};
+
+use bytes::Bytes;
+
+use super::Zone;
+use crate::protocol::{LabelSequence, Record, RecordData, RecordType};
+
+/// Serialises `zone` as a master file that [`parse_zone`](super::parse_zone) reads
+/// back into the same records. The output only depends on the records themselves:
+/// the SOA comes first and the rest follow in canonical order (RFC 4034 section
+/// 6.3), one per line with an explicit TTL and names relative to the origin, so
+/// the file diffs cleanly between versions.
+pub fn write_zone(zone: &Zone) -> String {
+    let origin = zone.origin();
+    let mut records = zone.records().collect::<Vec<_>>();
+    records.sort_by(|left, right| canonical_cmp(left, right));
+
+    let mut output = format!("$ORIGIN {origin}\n");
+    for record in records {
+        writeln!(
+            output,
+            "{}\t{}\t{}\t{}\t{}",
+            relative_name(&record.name, origin),
+            record.ttl(),
+            record.class().mnemonic(),
+            record.r#type().mnemonic(),
+            presentation(record.data(), origin)
+        )
+        .expect("Writing to a string never fails");
+    }
+
+    output
+}
+
+/// Orders records SOA first, then by owner, type and RDATA.
+fn canonical_cmp(left: &Record, right: &Record) -> Ordering {
+    let is_soa = |record: &Record| record.r#type() == &RecordType::SOA;
+
+    is_soa(right)
+        .cmp(&is_soa(left))
+        .then_with(|| left.name.canonical_cmp(&right.name))
+        .then_with(|| u16::from(*left.r#type()).cmp(&u16::from(*right.r#type())))
+        .then_with(|| Bytes::from(left.data()).cmp(&Bytes::from(right.data())))
+}
+
+fn presentation(data: &RecordData, origin: &LabelSequence) -> String {
+    match data {
+        RecordData::A(address) => address.to_string(),
+        RecordData::AAAA(address) => address.to_string(),
+        RecordData::CNAME(name)
+        | RecordData::DNAME(name)
+        | RecordData::NS(name)
+        | RecordData::PTR(name) => relative_name(name, origin),
+        RecordData::MX {
+            preference,
+            exchange,
+        } => format!("{preference} {}", relative_name(exchange, origin)),
+        RecordData::SOA(soa) => format!(
+            "{} {} {} {} {} {} {}",
+            relative_name(&soa.mname, origin),
+            relative_name(&soa.rname, origin),
+            soa.serial,
+            soa.refresh,
+            soa.retry,
+            soa.expire,
+            soa.minimum
+        ),
+        RecordData::TXT(strings) => strings
+            .iter()
+            .map(|string| format!("\"{}\"", escape(string, b"\"\\")))
+            .collect::<Vec<_>>()
+            .join(" "),
+        RecordData::OPT(_) | RecordData::TSIG(_) | RecordData::Unknown { .. } => {
+            let data = Bytes::from(data);
+            let hex = data
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>();
+            format!("\\# {} {hex}", data.len()).trim_end().to_owned()
+        }
+    }
+}
+
+/// `@` for the origin, the labels in front of the origin for names within it and
+/// the absolute name otherwise.
+fn relative_name(name: &LabelSequence, origin: &LabelSequence) -> String {
+    if name.eq_ignore_case(origin) {
+        return "@".into();
+    }
+
+    let labels = name.label_bytes().map(|label| escape(&label, b".\"\\;() "));
+    match name.is_subdomain_of(origin) {
+        true => labels
+            .take(name.label_count() - origin.label_count())
+            .collect::<Vec<_>>()
+            .join("."),
+        false => labels.map(|label| label + ".").collect(),
+    }
+}
+
+/// Escapes the `special` bytes with a backslash and every byte outside printable
+/// ASCII as `\DDD` (RFC 1035 section 5.1).
+fn escape(bytes: &[u8], special: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for &byte in bytes {
+        match byte {
+            byte if special.contains(&byte) => {
+                escaped.push('\\');
+                escaped.push(char::from(byte));
+            }
+            b' '..=b'~' => escaped.push(char::from(byte)),
+            byte => write!(escaped, "\\{byte:03}").expect("Writing to a string never fails"),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zone::parse_zone;
+
+    const ZONE: &str = "
+$TTL 3600
+www     300 A   192.0.2.81
+        300 A   192.0.2.80
+Mail        MX  20 mx.example.net.
+mail        MX  10 @
+ns1         A   192.0.2.53
+@           NS  ns1
+@       SOA ns1 hostmaster 2024010101 7200 1800 1209600 300
+txt         TXT \"a \\\"quoted\\\" string;\" plain
+*.preview   CNAME www
+svc         TYPE65 \\# 3 00 0100
+";
+
+    fn zone(text: &str) -> Zone {
+        let origin = LabelSequence::new("example.com");
+        Zone::new(origin.clone(), parse_zone(text, &origin).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn zone_is_written_in_canonical_order() {
+        let expected = "\
+$ORIGIN example.com.
+@\t3600\tIN\tSOA\tns1 hostmaster 2024010101 7200 1800 1209600 300
+@\t3600\tIN\tNS\tns1
+mail\t3600\tIN\tMX\t10 @
+Mail\t3600\tIN\tMX\t20 mx.example.net.
+ns1\t3600\tIN\tA\t192.0.2.53
+*.preview\t3600\tIN\tCNAME\twww
+svc\t3600\tIN\tTYPE65\t\\# 3 000100
+txt\t3600\tIN\tTXT\t\"a \\\"quoted\\\" string;\" \"plain\"
+www\t300\tIN\tA\t192.0.2.80
+www\t300\tIN\tA\t192.0.2.81
+";
+
+        assert_eq!(write_zone(&zone(ZONE)), expected);
+    }
+
+    #[test]
+    fn names_and_strings_outside_printable_ascii_round_trip() {
+        let text = "$TTL 60\n@ SOA ns1 hostmaster 1 2 3 4 5\ncaf\\233.a\\.b\\032c TXT \"caf\\233\\009\" \"\\\\\"\n";
+        let expected = "\
+$ORIGIN example.com.
+@\t60\tIN\tSOA\tns1 hostmaster 1 2 3 4 5
+caf\\233.a\\.b\\ c\t60\tIN\tTXT\t\"caf\\233\\009\" \"\\\\\"
+";
+
+        let original = zone(text);
+        let written = write_zone(&original);
+        assert_eq!(written, expected);
+
+        let parsed = zone(&written);
+        let mut records = parsed.records().cloned().collect::<Vec<_>>();
+        let mut expected = original.records().cloned().collect::<Vec<_>>();
+        records.sort_by(canonical_cmp);
+        expected.sort_by(canonical_cmp);
+        assert_eq!(records, expected);
+
+        let txt = parsed
+            .records()
+            .find(|record| record.r#type() == &RecordType::TXT)
+            .unwrap();
+        let name = LabelSequence::new(txt.name());
+        assert_eq!(
+            name.label_bytes().take(2).collect::<Vec<_>>(),
+            vec![b"caf\xe9".to_vec(), b"a.b c".to_vec()]
+        );
+    }
+
+    #[test]
+    fn written_zone_parses_back_to_same_records() {
+        let original = zone(ZONE);
+        let written = write_zone(&original);
+        let parsed = zone(&written);
+
+        assert_eq!(write_zone(&parsed), written);
+
+        let mut records = parsed.records().cloned().collect::<Vec<_>>();
+        let mut expected = original.records().cloned().collect::<Vec<_>>();
+        records.sort_by(canonical_cmp);
+        expected.sort_by(canonical_cmp);
+        assert_eq!(records, expected);
+    }
+}